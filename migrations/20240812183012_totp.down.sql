@@ -0,0 +1,3 @@
+-- Drop TOTP tables
+DROP TABLE TOTP_RECOVERY_CODE;
+DROP TABLE USER_TOTP;
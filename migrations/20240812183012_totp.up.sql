@@ -0,0 +1,13 @@
+-- Create tables for TOTP secrets and recovery codes
+CREATE TABLE USER_TOTP (
+    username TEXT PRIMARY KEY NOT NULL,
+    secret TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    CONSTRAINT USER_TOTP_USER_FK FOREIGN KEY (username) REFERENCES USER(username)
+);
+
+CREATE TABLE TOTP_RECOVERY_CODE (
+    username TEXT NOT NULL,
+    code TEXT NOT NULL,
+    CONSTRAINT TOTP_RECOVERY_CODE_USER_FK FOREIGN KEY (username) REFERENCES USER(username)
+);
@@ -0,0 +1,2 @@
+-- Drop API key table
+DROP TABLE API_KEY;
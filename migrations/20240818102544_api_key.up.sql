@@ -0,0 +1,10 @@
+-- Create table for device API keys
+CREATE TABLE API_KEY (
+    id TEXT PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    CONSTRAINT API_KEY_USER_FK FOREIGN KEY (username) REFERENCES USER(username)
+);
@@ -0,0 +1,3 @@
+-- Drop role and disabled flag from user table
+ALTER TABLE USER DROP COLUMN disabled;
+ALTER TABLE USER DROP COLUMN role;
@@ -0,0 +1,3 @@
+-- Add role and disabled flag to user table
+ALTER TABLE USER ADD role TEXT NOT NULL DEFAULT 'athlete';
+ALTER TABLE USER ADD disabled BOOLEAN NOT NULL DEFAULT FALSE;
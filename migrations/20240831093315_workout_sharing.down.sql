@@ -0,0 +1,3 @@
+-- Drop sharing table and workout owner
+DROP TABLE WORKOUT_SHARE;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN username;
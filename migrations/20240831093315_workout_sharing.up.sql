@@ -0,0 +1,14 @@
+-- Add workout owner and sharing table
+
+-- Workouts saved before this migration have no owner
+ALTER TABLE WORKOUT_SUMMARY ADD username TEXT REFERENCES USER(username);
+
+CREATE TABLE WORKOUT_SHARE (
+    athlete TEXT NOT NULL,
+    coach TEXT NOT NULL,
+    permission TEXT NOT NULL,
+    accepted BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (athlete, coach),
+    CONSTRAINT WORKOUT_SHARE_ATHLETE_FK FOREIGN KEY (athlete) REFERENCES USER(username),
+    CONSTRAINT WORKOUT_SHARE_COACH_FK FOREIGN KEY (coach) REFERENCES USER(username)
+);
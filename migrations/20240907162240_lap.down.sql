@@ -0,0 +1,2 @@
+-- Drop lap table
+DROP TABLE LAP;
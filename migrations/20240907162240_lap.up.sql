@@ -0,0 +1,8 @@
+-- Add lap boundaries of workouts
+
+CREATE TABLE LAP (
+    workout_id INTEGER NOT NULL,
+    start_index INTEGER NOT NULL,
+    PRIMARY KEY (workout_id, start_index),
+    CONSTRAINT LAP_WORKOUT_SUMMARY_FK FOREIGN KEY (workout_id) REFERENCES WORKOUT_SUMMARY(id)
+);
@@ -0,0 +1,7 @@
+-- Drop moving time and moving averages from workout_summary table
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN elapsed_time;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN moving_time;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN moving_avg_speed;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN moving_avg_watts;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN moving_avg_rpm;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN moving_avg_heartrate;
@@ -0,0 +1,22 @@
+-- Add moving time and moving averages to workout_summary table. Existing
+-- workouts were recorded without auto-pause, so their measurements, one per
+-- second, all count as moving.
+
+ALTER TABLE WORKOUT_SUMMARY ADD elapsed_time INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD moving_time INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD moving_avg_speed NUMERIC NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD moving_avg_watts NUMERIC NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD moving_avg_rpm NUMERIC NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD moving_avg_heartrate NUMERIC NOT NULL DEFAULT 0;
+
+UPDATE WORKOUT_SUMMARY SET
+    elapsed_time = (
+        SELECT COUNT(*) FROM MEASUREMENTS WHERE workout_id = WORKOUT_SUMMARY.id
+    ),
+    moving_time = (
+        SELECT COUNT(*) FROM MEASUREMENTS WHERE workout_id = WORKOUT_SUMMARY.id
+    ),
+    moving_avg_speed = avg_speed,
+    moving_avg_watts = avg_watts,
+    moving_avg_rpm = avg_rpm,
+    moving_avg_heartrate = avg_heartrate;
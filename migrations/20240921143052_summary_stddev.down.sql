@@ -0,0 +1,5 @@
+-- Drop standard deviations from workout_summary table
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN stddev_speed;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN stddev_watts;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN stddev_rpm;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN stddev_heartrate;
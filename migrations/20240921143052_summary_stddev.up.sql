@@ -0,0 +1,6 @@
+-- Add standard deviations to workout_summary table
+
+ALTER TABLE WORKOUT_SUMMARY ADD stddev_speed NUMERIC NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD stddev_watts NUMERIC NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD stddev_rpm NUMERIC NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD stddev_heartrate NUMERIC NOT NULL DEFAULT 0;
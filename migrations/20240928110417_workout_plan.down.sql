@@ -0,0 +1,3 @@
+-- Drop workout plan tables
+DROP TABLE PLAN_STEP;
+DROP TABLE WORKOUT_PLAN;
@@ -0,0 +1,23 @@
+-- Add structured workout plans and their steps
+
+CREATE TABLE WORKOUT_PLAN (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    name TEXT NOT NULL,
+    CONSTRAINT WORKOUT_PLAN_USER_FK FOREIGN KEY (username) REFERENCES USER(username)
+);
+
+-- Steps target either absolute power or a share of FTP, or neither
+CREATE TABLE PLAN_STEP (
+    plan_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    target_watts INTEGER,
+    target_ftp_share FLOAT,
+    min_heartrate INTEGER,
+    max_heartrate INTEGER,
+    min_rpm INTEGER,
+    max_rpm INTEGER,
+    PRIMARY KEY (plan_id, position),
+    CONSTRAINT PLAN_STEP_WORKOUT_PLAN_FK FOREIGN KEY (plan_id) REFERENCES WORKOUT_PLAN(id)
+);
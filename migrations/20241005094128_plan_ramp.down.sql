@@ -0,0 +1,3 @@
+-- Remove the end power of ramp steps
+ALTER TABLE PLAN_STEP DROP COLUMN target_end_ftp_share;
+ALTER TABLE PLAN_STEP DROP COLUMN target_end_watts;
@@ -0,0 +1,4 @@
+-- Add the end power of ramp steps, in the same unit as the target power
+
+ALTER TABLE PLAN_STEP ADD target_end_watts INTEGER;
+ALTER TABLE PLAN_STEP ADD target_end_ftp_share FLOAT;
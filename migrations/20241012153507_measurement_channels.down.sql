@@ -0,0 +1,16 @@
+-- Remove optional power meter channels
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN avg_right_watts;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN avg_left_watts;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN avg_right_pedal_smoothness;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN avg_left_pedal_smoothness;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN avg_right_torque_effectiveness;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN avg_left_torque_effectiveness;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN avg_left_right_balance;
+
+ALTER TABLE MEASUREMENTS DROP COLUMN right_watts;
+ALTER TABLE MEASUREMENTS DROP COLUMN left_watts;
+ALTER TABLE MEASUREMENTS DROP COLUMN right_pedal_smoothness;
+ALTER TABLE MEASUREMENTS DROP COLUMN left_pedal_smoothness;
+ALTER TABLE MEASUREMENTS DROP COLUMN right_torque_effectiveness;
+ALTER TABLE MEASUREMENTS DROP COLUMN left_torque_effectiveness;
+ALTER TABLE MEASUREMENTS DROP COLUMN left_right_balance;
@@ -0,0 +1,18 @@
+-- Add optional power meter channels to measurements, and their averages to
+-- workout_summary table
+
+ALTER TABLE MEASUREMENTS ADD left_right_balance FLOAT;
+ALTER TABLE MEASUREMENTS ADD left_torque_effectiveness FLOAT;
+ALTER TABLE MEASUREMENTS ADD right_torque_effectiveness FLOAT;
+ALTER TABLE MEASUREMENTS ADD left_pedal_smoothness FLOAT;
+ALTER TABLE MEASUREMENTS ADD right_pedal_smoothness FLOAT;
+ALTER TABLE MEASUREMENTS ADD left_watts INTEGER;
+ALTER TABLE MEASUREMENTS ADD right_watts INTEGER;
+
+ALTER TABLE WORKOUT_SUMMARY ADD avg_left_right_balance NUMERIC;
+ALTER TABLE WORKOUT_SUMMARY ADD avg_left_torque_effectiveness NUMERIC;
+ALTER TABLE WORKOUT_SUMMARY ADD avg_right_torque_effectiveness NUMERIC;
+ALTER TABLE WORKOUT_SUMMARY ADD avg_left_pedal_smoothness NUMERIC;
+ALTER TABLE WORKOUT_SUMMARY ADD avg_right_pedal_smoothness NUMERIC;
+ALTER TABLE WORKOUT_SUMMARY ADD avg_left_watts NUMERIC;
+ALTER TABLE WORKOUT_SUMMARY ADD avg_right_watts NUMERIC;
@@ -0,0 +1,3 @@
+-- Drop custom channel tables
+DROP TABLE CUSTOM_CHANNEL_VALUE;
+DROP TABLE CUSTOM_CHANNEL;
@@ -0,0 +1,21 @@
+-- Add custom sensor channels of workouts, and their values by sample index
+
+CREATE TABLE CUSTOM_CHANNEL (
+    workout_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    unit TEXT NOT NULL,
+    min_value NUMERIC NOT NULL,
+    avg_value NUMERIC NOT NULL,
+    max_value NUMERIC NOT NULL,
+    PRIMARY KEY (workout_id, name),
+    CONSTRAINT CUSTOM_CHANNEL_WORKOUT_SUMMARY_FK FOREIGN KEY (workout_id) REFERENCES WORKOUT_SUMMARY(id)
+);
+
+CREATE TABLE CUSTOM_CHANNEL_VALUE (
+    workout_id INTEGER NOT NULL,
+    channel TEXT NOT NULL,
+    sample_index INTEGER NOT NULL,
+    value FLOAT NOT NULL,
+    PRIMARY KEY (workout_id, channel, sample_index),
+    CONSTRAINT CUSTOM_CHANNEL_VALUE_CUSTOM_CHANNEL_FK FOREIGN KEY (workout_id, channel) REFERENCES CUSTOM_CHANNEL(workout_id, name)
+);
@@ -0,0 +1,12 @@
+-- Remove positions, altitude and elevation
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN max_longitude;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN max_latitude;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN min_longitude;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN min_latitude;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN max_grade;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN total_descent;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN total_ascent;
+
+ALTER TABLE MEASUREMENTS DROP COLUMN altitude;
+ALTER TABLE MEASUREMENTS DROP COLUMN longitude;
+ALTER TABLE MEASUREMENTS DROP COLUMN latitude;
@@ -0,0 +1,14 @@
+-- Add positions and altitude to measurements, and elevation, grade and bounding
+-- box to workout_summary table
+
+ALTER TABLE MEASUREMENTS ADD latitude FLOAT;
+ALTER TABLE MEASUREMENTS ADD longitude FLOAT;
+ALTER TABLE MEASUREMENTS ADD altitude FLOAT;
+
+ALTER TABLE WORKOUT_SUMMARY ADD total_ascent NUMERIC;
+ALTER TABLE WORKOUT_SUMMARY ADD total_descent NUMERIC;
+ALTER TABLE WORKOUT_SUMMARY ADD max_grade NUMERIC;
+ALTER TABLE WORKOUT_SUMMARY ADD min_latitude FLOAT;
+ALTER TABLE WORKOUT_SUMMARY ADD min_longitude FLOAT;
+ALTER TABLE WORKOUT_SUMMARY ADD max_latitude FLOAT;
+ALTER TABLE WORKOUT_SUMMARY ADD max_longitude FLOAT;
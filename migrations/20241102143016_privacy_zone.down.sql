@@ -0,0 +1,2 @@
+-- Drop privacy zone table
+DROP TABLE PRIVACY_ZONE;
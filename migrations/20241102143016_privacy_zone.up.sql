@@ -0,0 +1,10 @@
+-- Add privacy zones, whose positions are only shown to the user
+
+CREATE TABLE PRIVACY_ZONE (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    latitude FLOAT NOT NULL,
+    longitude FLOAT NOT NULL,
+    radius FLOAT NOT NULL,
+    CONSTRAINT PRIVACY_ZONE_USER_FK FOREIGN KEY (username) REFERENCES USER(username)
+);
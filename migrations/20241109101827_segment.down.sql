@@ -0,0 +1,4 @@
+-- Drop segment tables
+DROP TABLE SEGMENT_EFFORT;
+DROP TABLE SEGMENT_POINT;
+DROP TABLE SEGMENT;
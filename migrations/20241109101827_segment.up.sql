@@ -0,0 +1,35 @@
+-- Add segments, their positions and the efforts of workouts matching them
+
+CREATE TABLE SEGMENT (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    name TEXT NOT NULL,
+    distance FLOAT NOT NULL,
+    polyline TEXT NOT NULL,
+    min_latitude FLOAT NOT NULL,
+    min_longitude FLOAT NOT NULL,
+    max_latitude FLOAT NOT NULL,
+    max_longitude FLOAT NOT NULL,
+    CONSTRAINT SEGMENT_USER_FK FOREIGN KEY (username) REFERENCES USER(username)
+);
+
+CREATE TABLE SEGMENT_POINT (
+    segment_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    latitude FLOAT NOT NULL,
+    longitude FLOAT NOT NULL,
+    PRIMARY KEY (segment_id, position),
+    CONSTRAINT SEGMENT_POINT_SEGMENT_FK FOREIGN KEY (segment_id) REFERENCES SEGMENT(id)
+);
+
+CREATE TABLE SEGMENT_EFFORT (
+    segment_id INTEGER NOT NULL,
+    workout_id INTEGER NOT NULL,
+    start_index INTEGER NOT NULL,
+    username TEXT NOT NULL,
+    elapsed_time INTEGER NOT NULL,
+    avg_watts INTEGER NOT NULL,
+    PRIMARY KEY (segment_id, workout_id, start_index),
+    CONSTRAINT SEGMENT_EFFORT_SEGMENT_FK FOREIGN KEY (segment_id) REFERENCES SEGMENT(id),
+    CONSTRAINT SEGMENT_EFFORT_WORKOUT_SUMMARY_FK FOREIGN KEY (workout_id) REFERENCES WORKOUT_SUMMARY(id)
+);
@@ -0,0 +1,6 @@
+-- Remove energy and start time of workouts, and the profile
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN start_time;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN calories;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN work;
+
+DROP TABLE USER_PROFILE;
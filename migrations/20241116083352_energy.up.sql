@@ -0,0 +1,15 @@
+-- Add the profile used to estimate calories, and work, calories and start time
+-- to workout_summary table. Workouts saved before have no start time.
+
+CREATE TABLE USER_PROFILE (
+    username TEXT PRIMARY KEY NOT NULL,
+    age INTEGER,
+    weight FLOAT,
+    sex TEXT,
+    gross_efficiency FLOAT,
+    CONSTRAINT USER_PROFILE_USER_FK FOREIGN KEY (username) REFERENCES USER(username)
+);
+
+ALTER TABLE WORKOUT_SUMMARY ADD work FLOAT NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD calories FLOAT;
+ALTER TABLE WORKOUT_SUMMARY ADD start_time INTEGER;
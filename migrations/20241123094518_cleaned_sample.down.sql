@@ -0,0 +1,2 @@
+-- Remove the cleaned measurement values
+DROP TABLE CLEANED_SAMPLE;
@@ -0,0 +1,12 @@
+-- Add the measurement values changed by cleaning workouts before saving them
+
+CREATE TABLE CLEANED_SAMPLE (
+    workout_id INTEGER NOT NULL,
+    sample_index INTEGER NOT NULL,
+    field TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    original_value FLOAT NOT NULL,
+    value FLOAT NOT NULL,
+    PRIMARY KEY (workout_id, sample_index, field),
+    CONSTRAINT CLEANED_SAMPLE_WORKOUT_SUMMARY_FK FOREIGN KEY (workout_id) REFERENCES WORKOUT_SUMMARY(id)
+);
@@ -0,0 +1,7 @@
+-- Remove the stored aerobic metrics and mean-max power of workouts
+DROP TABLE MEAN_MAX_POWER;
+
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN analysed;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN heartrate_drift;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN decoupling;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN efficiency_factor;
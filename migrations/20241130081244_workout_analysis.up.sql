@@ -0,0 +1,16 @@
+-- Store the aerobic metrics and mean-max power of workouts, so trends don't have
+-- to read all their measurements. Workouts saved before are analysed the first
+-- time their user asks for a trend.
+
+ALTER TABLE WORKOUT_SUMMARY ADD efficiency_factor FLOAT;
+ALTER TABLE WORKOUT_SUMMARY ADD decoupling FLOAT;
+ALTER TABLE WORKOUT_SUMMARY ADD heartrate_drift FLOAT;
+ALTER TABLE WORKOUT_SUMMARY ADD analysed BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE MEAN_MAX_POWER (
+    workout_id INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    watts FLOAT NOT NULL,
+    PRIMARY KEY (workout_id, duration),
+    CONSTRAINT MEAN_MAX_POWER_WORKOUT_SUMMARY_FK FOREIGN KEY (workout_id) REFERENCES WORKOUT_SUMMARY(id)
+);
@@ -0,0 +1,2 @@
+-- Remove the time step of the last accepted TOTP code
+ALTER TABLE USER_TOTP DROP COLUMN last_step;
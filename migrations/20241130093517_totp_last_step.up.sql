@@ -0,0 +1,2 @@
+-- Add the time step of the last accepted TOTP code, so codes can't be replayed
+ALTER TABLE USER_TOTP ADD last_step INTEGER;
@@ -0,0 +1,4 @@
+-- Remove averages of power, cadence and heart rate leaving out zeros
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN nonzero_avg_heartrate;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN nonzero_avg_rpm;
+ALTER TABLE WORKOUT_SUMMARY DROP COLUMN nonzero_avg_watts;
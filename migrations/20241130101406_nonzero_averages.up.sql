@@ -0,0 +1,20 @@
+-- Add averages of power, cadence and heart rate leaving out zeros to
+-- workout_summary table, computing them from the stored measurements of
+-- existing workouts
+ALTER TABLE WORKOUT_SUMMARY ADD nonzero_avg_watts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD nonzero_avg_rpm INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE WORKOUT_SUMMARY ADD nonzero_avg_heartrate INTEGER NOT NULL DEFAULT 0;
+
+UPDATE WORKOUT_SUMMARY SET
+    nonzero_avg_watts = COALESCE((
+        SELECT CAST(AVG(watts) AS INTEGER) FROM MEASUREMENTS
+        WHERE workout_id = WORKOUT_SUMMARY.id AND watts != 0
+    ), 0),
+    nonzero_avg_rpm = COALESCE((
+        SELECT CAST(AVG(rpm) AS INTEGER) FROM MEASUREMENTS
+        WHERE workout_id = WORKOUT_SUMMARY.id AND rpm != 0
+    ), 0),
+    nonzero_avg_heartrate = COALESCE((
+        SELECT CAST(AVG(heartrate) AS INTEGER) FROM MEASUREMENTS
+        WHERE workout_id = WORKOUT_SUMMARY.id AND heartrate != 0
+    ), 0);
@@ -1,3 +1,6 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::Result;
 use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
 use thiserror::Error;
@@ -6,16 +9,22 @@ use tonic_reflection::server::Builder as ReflectionServerBuilder;
 
 use crate::cycling_tracker;
 use crate::grpc::{
-    auth::SessionAuthService, cycling_tracker::CyclingTrackerService,
-    BuildError as GRPCBuildError, Builder as GRPCBuilder, GRPC,
+    admin::AdminService, auth::SessionAuthService,
+    cycling_tracker::CyclingTrackerService, plan::PlanService, profile::ProfileService,
+    segment::SegmentService, sharing::SharingService, BuildError as GRPCBuildError,
+    Builder as GRPCBuilder, GRPC,
 };
 use crate::handler::{
-    RedisHandler, SQLiteHandler, SessionHandler, UserHandler, WorkoutHandler,
+    recording::RECORDING_TIMEOUT, ApiKeyHandler, AutoPause, DataCleaning, LiveHandler,
+    LogNotifier, Notifier, PlanHandler, ProfileHandler, RecordingHandler, RedisHandler,
+    SQLiteHandler, SegmentHandler, SessionHandler, SharingHandler, TotpHandler,
+    UserHandler, WorkoutHandler,
 };
 use crate::FILE_DESCRIPTOR_SET;
 
 pub struct App {
     grpc: GRPC,
+    recording_handler: RecordingHandler,
 }
 
 impl App {
@@ -24,19 +33,19 @@ impl App {
     }
 
     pub async fn run(mut self) -> Result<()> {
-        tokio::select! {
-            e = self.grpc.run() => {
-                e
-            }
-        }
+        let finalizer = tokio::spawn(self.recording_handler.run_finalizer());
+        let result = self.grpc.run().await;
+        finalizer.abort();
+
+        result
     }
 
     pub async fn run_tcp(mut self, tcp_listener: TcpListenerStream) -> Result<()> {
-        tokio::select! {
-            e = self.grpc.run_tcp(tcp_listener) => {
-                e
-            }
-        }
+        let finalizer = tokio::spawn(self.recording_handler.run_finalizer());
+        let result = self.grpc.run_tcp(tcp_listener).await;
+        finalizer.abort();
+
+        result
     }
 }
 
@@ -44,6 +53,11 @@ pub struct Builder {
     grpc: Option<GRPC>,
     db: Option<SqlitePool>,
     redis: Option<redis::Client>,
+    notifier: Arc<dyn Notifier>,
+    recording_timeout: Duration,
+    recording_handler: Option<RecordingHandler>,
+    auto_pause: AutoPause,
+    data_cleaning: DataCleaning,
 }
 
 impl Builder {
@@ -52,6 +66,11 @@ impl Builder {
             grpc: None,
             db: None,
             redis: None,
+            notifier: Arc::new(LogNotifier),
+            recording_timeout: RECORDING_TIMEOUT,
+            recording_handler: None,
+            auto_pause: AutoPause::default(),
+            data_cleaning: DataCleaning::default(),
         }
     }
 
@@ -98,6 +117,29 @@ impl Builder {
         self
     }
 
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Set when riders are considered stopped, leaving stops out of moving time.
+    pub fn with_auto_pause(mut self, auto_pause: AutoPause) -> Self {
+        self.auto_pause = auto_pause;
+        self
+    }
+
+    /// Set how measurements are cleaned before they're saved.
+    pub fn with_data_cleaning(mut self, data_cleaning: DataCleaning) -> Self {
+        self.data_cleaning = data_cleaning;
+        self
+    }
+
+    /// Set after how long without measurements a recording is saved as is.
+    pub fn with_recording_timeout(mut self, timeout: Duration) -> Self {
+        self.recording_timeout = timeout;
+        self
+    }
+
     pub async fn setup_grpc(
         mut self,
         host_url: &str,
@@ -109,20 +151,70 @@ impl Builder {
         };
 
         self.redis.as_ref().ok_or(BuildError::RedisNotSet)?;
-        let redis_handler = RedisHandler {
-            client: self.redis.clone().unwrap(),
+        let redis_handler = RedisHandler::new(self.redis.clone().unwrap());
+
+        let api_key_handler = ApiKeyHandler {
+            sqlite_handler: sqlite_handler.clone(),
+        };
+        let segment_handler = SegmentHandler {
+            sqlite_handler: sqlite_handler.clone(),
+        };
+        let workout_handler = WorkoutHandler {
+            sqlite_handler: sqlite_handler.clone(),
+            segment_handler: segment_handler.clone(),
+            auto_pause: self.auto_pause,
+            data_cleaning: self.data_cleaning,
+        };
+        let live_handler = LiveHandler::new(redis_handler.clone());
+        let recording_handler = RecordingHandler {
+            redis_handler: redis_handler.clone(),
+            workout_handler: workout_handler.clone(),
+            timeout: self.recording_timeout,
+        };
+        let session_handler = SessionHandler {
+            redis_handler,
+            api_key_handler: api_key_handler.clone(),
+        };
+
+        let sharing_handler = SharingHandler {
+            sqlite_handler: sqlite_handler.clone(),
+        };
+        let plan_handler = PlanHandler {
+            sqlite_handler: sqlite_handler.clone(),
         };
 
         let cts =
             cycling_tracker::CyclingTrackerServer::new(CyclingTrackerService::new(
-                WorkoutHandler {
-                    sqlite_handler: sqlite_handler.clone(),
-                },
-                SessionHandler {
-                    redis_handler: redis_handler.clone(),
-                },
+                workout_handler,
+                session_handler.clone(),
+                sharing_handler.clone(),
+                live_handler,
+                recording_handler.clone(),
+                plan_handler.clone(),
             ));
 
+        let sharing = cycling_tracker::SharingServer::new(SharingService::new(
+            sharing_handler,
+            session_handler.clone(),
+        ));
+
+        let plans = cycling_tracker::WorkoutPlansServer::new(PlanService::new(
+            plan_handler,
+            session_handler.clone(),
+        ));
+
+        let profile = cycling_tracker::ProfileServer::new(ProfileService::new(
+            ProfileHandler {
+                sqlite_handler: sqlite_handler.clone(),
+            },
+            session_handler.clone(),
+        ));
+
+        let segments = cycling_tracker::SegmentsServer::new(SegmentService::new(
+            segment_handler,
+            session_handler.clone(),
+        ));
+
         let refl = ReflectionServerBuilder::configure()
             .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
             .build()
@@ -134,24 +226,47 @@ impl Builder {
             grpc_builder = grpc_builder.with_tls()?;
         }
 
+        let admin = cycling_tracker::AdminServer::new(AdminService::new(
+            UserHandler {
+                sqlite_handler: sqlite_handler.clone(),
+            },
+            session_handler.clone(),
+            sqlite_handler.clone(),
+        ));
+
         let auth = cycling_tracker::SessionAuthServer::new(SessionAuthService::new(
-            UserHandler { sqlite_handler },
-            SessionHandler { redis_handler },
+            UserHandler {
+                sqlite_handler: sqlite_handler.clone(),
+            },
+            session_handler,
+            TotpHandler { sqlite_handler },
+            api_key_handler,
+            self.notifier.clone(),
         ));
         let grpc = grpc_builder
             .add_auth_service(auth)
+            .add_admin_service(admin)
+            .add_sharing_service(sharing)
+            .add_plan_service(plans)
+            .add_profile_service(profile)
+            .add_segment_service(segments)
             .add_reflection_service(refl)
             .add_ct_service(cts)
             .build()?;
 
         self.grpc = Some(grpc);
+        self.recording_handler = Some(recording_handler);
         Ok(self)
     }
 
     pub fn build(self) -> Result<App, BuildError> {
         let grpc = self.grpc.ok_or(BuildError::GRPCNotSet)?;
+        let recording_handler = self.recording_handler.ok_or(BuildError::GRPCNotSet)?;
 
-        Ok(App { grpc })
+        Ok(App {
+            grpc,
+            recording_handler,
+        })
     }
 }
 
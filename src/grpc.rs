@@ -7,13 +7,26 @@ use tonic::transport::{server::Router, Identity, Server, ServerTlsConfig};
 use tonic_reflection::server::{ServerReflection, ServerReflectionServer};
 use tracing::{info, instrument};
 
-use crate::cycling_tracker::{CyclingTrackerServer, SessionAuthServer};
+use crate::cycling_tracker::{
+    AdminServer, CyclingTrackerServer, ProfileServer, SegmentsServer,
+    SessionAuthServer, SharingServer, WorkoutPlansServer,
+};
 
+pub mod admin;
 pub mod auth;
 pub mod cycling_tracker;
+pub mod plan;
+pub mod profile;
+pub mod segment;
+pub mod sharing;
 
+use admin::AdminService;
 use auth::SessionAuthService;
 use cycling_tracker::CyclingTrackerService;
+use plan::PlanService;
+use profile::ProfileService;
+use segment::SegmentService;
+use sharing::SharingService;
 
 #[derive(Debug)]
 pub struct GRPC {
@@ -122,6 +135,58 @@ impl Builder {
         self
     }
 
+    pub fn add_admin_service(mut self, service: AdminServer<AdminService>) -> Self {
+        match self.router {
+            Some(r) => self.router = Some(r.add_service(service)),
+            None => self.router = Some(self.server.add_service(service)),
+        }
+        self
+    }
+
+    pub fn add_sharing_service(
+        mut self,
+        service: SharingServer<SharingService>,
+    ) -> Self {
+        match self.router {
+            Some(r) => self.router = Some(r.add_service(service)),
+            None => self.router = Some(self.server.add_service(service)),
+        }
+        self
+    }
+
+    pub fn add_plan_service(
+        mut self,
+        service: WorkoutPlansServer<PlanService>,
+    ) -> Self {
+        match self.router {
+            Some(r) => self.router = Some(r.add_service(service)),
+            None => self.router = Some(self.server.add_service(service)),
+        }
+        self
+    }
+
+    pub fn add_profile_service(
+        mut self,
+        service: ProfileServer<ProfileService>,
+    ) -> Self {
+        match self.router {
+            Some(r) => self.router = Some(r.add_service(service)),
+            None => self.router = Some(self.server.add_service(service)),
+        }
+        self
+    }
+
+    pub fn add_segment_service(
+        mut self,
+        service: SegmentsServer<SegmentService>,
+    ) -> Self {
+        match self.router {
+            Some(r) => self.router = Some(r.add_service(service)),
+            None => self.router = Some(self.server.add_service(service)),
+        }
+        self
+    }
+
     pub fn add_reflection_service(
         mut self,
         service: ServerReflectionServer<impl ServerReflection>,
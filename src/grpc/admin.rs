@@ -0,0 +1,130 @@
+use tonic::{Request, Response, Status};
+
+use crate::cycling_tracker::{
+    admin_server::Admin, AdminResult, ForceLogoutRequest, ListUsersRequest, Role,
+    ServerStats, ServerStatsRequest, UserDisabledRequest, UserList, UserRoleRequest,
+};
+use crate::handler::{SQLiteHandler, Scope, SessionHandler, UserHandler};
+
+type GRPCResult<T> = Result<Response<T>, Status>;
+
+pub struct AdminService {
+    user_handler: UserHandler,
+    session_handler: SessionHandler,
+    sqlite_handler: SQLiteHandler,
+}
+
+impl AdminService {
+    pub fn new(
+        user_handler: UserHandler,
+        session_handler: SessionHandler,
+        sqlite_handler: SQLiteHandler,
+    ) -> Self {
+        Self {
+            user_handler,
+            session_handler,
+            sqlite_handler,
+        }
+    }
+
+    /// Return the authenticated user, if it's an admin.
+    async fn verify_admin<RT>(&self, request: &Request<RT>) -> Result<String, Status> {
+        let username = self
+            .session_handler
+            .verify_session_token(request, Scope::Account)
+            .await?;
+
+        match self.user_handler.get_role(&username).await {
+            Some(Role::Admin) => Ok(username),
+            _ => Err(Status::permission_denied("Admin role required")),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn list_users(
+        &self,
+        request: Request<ListUsersRequest>,
+    ) -> GRPCResult<UserList> {
+        self.verify_admin(&request).await?;
+
+        Ok(Response::new(UserList {
+            users: self.user_handler.list().await,
+        }))
+    }
+
+    async fn set_user_disabled(
+        &self,
+        request: Request<UserDisabledRequest>,
+    ) -> GRPCResult<AdminResult> {
+        let admin = self.verify_admin(&request).await?;
+        let request = request.into_inner();
+
+        if request.disabled && request.username == admin {
+            return Err(Status::invalid_argument("Admins can't disable themselves"));
+        }
+
+        if !self
+            .user_handler
+            .set_disabled(&request.username, request.disabled)
+            .await
+        {
+            return Err(Status::not_found("User not found"));
+        }
+
+        if request.disabled {
+            self.session_handler.end_all(&request.username);
+        }
+
+        println!(
+            "User {:?} set disabled = {:?} for user {:?}",
+            admin, request.disabled, request.username
+        );
+
+        Ok(Response::new(AdminResult { result: true }))
+    }
+
+    async fn set_user_role(
+        &self,
+        request: Request<UserRoleRequest>,
+    ) -> GRPCResult<AdminResult> {
+        let admin = self.verify_admin(&request).await?;
+        let request = request.into_inner();
+
+        let role = Role::try_from(request.role)
+            .map_err(|_| Status::invalid_argument("Unknown role"))?;
+
+        if !self.user_handler.set_role(&request.username, role).await {
+            return Err(Status::not_found("User not found"));
+        }
+
+        println!(
+            "User {:?} set role {:?} for user {:?}",
+            admin, role, request.username
+        );
+
+        Ok(Response::new(AdminResult { result: true }))
+    }
+
+    async fn force_logout(
+        &self,
+        request: Request<ForceLogoutRequest>,
+    ) -> GRPCResult<AdminResult> {
+        self.verify_admin(&request).await?;
+        let username = request.into_inner().username;
+
+        self.session_handler.end_all(&username);
+
+        Ok(Response::new(AdminResult { result: true }))
+    }
+
+    async fn get_server_stats(
+        &self,
+        request: Request<ServerStatsRequest>,
+    ) -> GRPCResult<ServerStats> {
+        self.verify_admin(&request).await?;
+
+        Ok(Response::new(self.sqlite_handler.get_server_stats().await))
+    }
+}
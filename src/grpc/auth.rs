@@ -1,20 +1,40 @@
+use std::sync::Arc;
+
 use tonic::{Request, Response, Status};
 
 use crate::cycling_tracker::{
-    session_auth_server::SessionAuth, Credentials, SessionToken, SignUpResult,
+    session_auth_server::SessionAuth, ApiKeyList, ApiKeyRequest, ApiKeyResult,
+    CreatedApiKey, Credentials, ListApiKeysRequest, PasswordReset,
+    PasswordResetRequest, PasswordResetResult, RevokeApiKeyRequest, SessionToken,
+    SignUpResult, TotpChallengeResponse, TotpCode, TotpEnrollment,
+    TotpEnrollmentRequest, TotpResult,
+};
+use crate::handler::{
+    ApiKeyHandler, Notifier, Scope, SessionHandler, TotpHandler, UserHandler,
 };
-use crate::handler::{SessionHandler, UserHandler};
 
 pub struct SessionAuthService {
     pub user_handler: UserHandler,
     pub session_handler: SessionHandler,
+    pub totp_handler: TotpHandler,
+    pub api_key_handler: ApiKeyHandler,
+    pub notifier: Arc<dyn Notifier>,
 }
 
 impl SessionAuthService {
-    pub fn new(user_handler: UserHandler, session_handler: SessionHandler) -> Self {
+    pub fn new(
+        user_handler: UserHandler,
+        session_handler: SessionHandler,
+        totp_handler: TotpHandler,
+        api_key_handler: ApiKeyHandler,
+        notifier: Arc<dyn Notifier>,
+    ) -> Self {
         Self {
             user_handler,
             session_handler,
+            totp_handler,
+            api_key_handler,
+            notifier,
         }
     }
 }
@@ -42,13 +62,195 @@ impl SessionAuth for SessionAuthService {
         let credentials = request.into_inner();
         println!("Login request from user = {:?}", credentials.username);
 
-        if self.user_handler.login(credentials.clone()).await {
-            let session_token = self.session_handler.start(credentials.username);
+        if !self.user_handler.login(credentials.clone()).await {
+            return Err(Status::unauthenticated("Invalid credentials"));
+        }
+
+        if self.totp_handler.is_enabled(&credentials.username).await {
+            let challenge_token =
+                self.session_handler.start_challenge(&credentials.username);
             return Ok(Response::new(SessionToken {
-                token: session_token,
+                token: challenge_token,
+                second_factor_required: true,
             }));
         }
 
-        Err(Status::unauthenticated("Invalid credentials"))
+        let session_token = self.session_handler.start(credentials.username);
+        Ok(Response::new(SessionToken {
+            token: session_token,
+            second_factor_required: false,
+        }))
+    }
+
+    async fn verify_totp(
+        &self,
+        request: Request<TotpChallengeResponse>,
+    ) -> Result<Response<SessionToken>, Status> {
+        let challenge = request.into_inner();
+
+        let username = self
+            .session_handler
+            .get_challenge(&challenge.challenge_token)
+            .ok_or(Status::unauthenticated("Invalid challenge token"))?;
+
+        if !self.totp_handler.verify(&username, &challenge.code).await {
+            // A challenge only gets one attempt, so codes can't be brute forced
+            self.session_handler
+                .cancel_challenge(&challenge.challenge_token);
+            return Err(Status::unauthenticated("Invalid code"));
+        }
+
+        let session_token = self
+            .session_handler
+            .complete_challenge(&challenge.challenge_token)
+            .ok_or(Status::unauthenticated("Invalid challenge token"))?;
+
+        Ok(Response::new(SessionToken {
+            token: session_token,
+            second_factor_required: false,
+        }))
+    }
+
+    async fn enroll_totp(
+        &self,
+        request: Request<TotpEnrollmentRequest>,
+    ) -> Result<Response<TotpEnrollment>, Status> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+        println!("TOTP enrollment for user = {:?}", username);
+
+        match self.totp_handler.enroll(username).await {
+            Some(enrollment) => Ok(Response::new(enrollment)),
+            None => Err(Status::failed_precondition(
+                "Two-factor authentication is already enabled",
+            )),
+        }
+    }
+
+    async fn confirm_totp(
+        &self,
+        request: Request<TotpCode>,
+    ) -> Result<Response<TotpResult>, Status> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+        let code = request.into_inner().code;
+
+        if self.totp_handler.confirm(&username, &code).await {
+            return Ok(Response::new(TotpResult { result: true }));
+        }
+
+        Err(Status::invalid_argument("Invalid code"))
+    }
+
+    async fn request_password_reset(
+        &self,
+        request: Request<PasswordResetRequest>,
+    ) -> Result<Response<PasswordResetResult>, Status> {
+        let username = request.into_inner().username;
+        println!("Password reset requested for user = {:?}", username);
+
+        // Unknown usernames get the same response, so accounts can't be enumerated
+        if self.user_handler.exists(username.clone()).await {
+            let reset_token = self.session_handler.start_password_reset(&username);
+            self.notifier.send_password_reset(&username, &reset_token);
+        }
+
+        Ok(Response::new(PasswordResetResult { result: true }))
+    }
+
+    async fn confirm_password_reset(
+        &self,
+        request: Request<PasswordReset>,
+    ) -> Result<Response<PasswordResetResult>, Status> {
+        let reset = request.into_inner();
+        println!(
+            "Password reset confirmation for user = {:?}",
+            reset.username
+        );
+
+        if !self
+            .session_handler
+            .consume_password_reset(&reset.username, &reset.reset_token)
+        {
+            return Err(Status::permission_denied("Invalid password reset token"));
+        }
+
+        if self
+            .user_handler
+            .reset_password(reset.username.clone(), &reset.new_password)
+            .await
+        {
+            // Whoever knew the old password may still be logged in
+            self.session_handler.end_all(&reset.username);
+            return Ok(Response::new(PasswordResetResult { result: true }));
+        }
+
+        Err(Status::internal("Failed to reset password"))
+    }
+
+    async fn create_api_key(
+        &self,
+        request: Request<ApiKeyRequest>,
+    ) -> Result<Response<CreatedApiKey>, Status> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+        let api_key_request = request.into_inner();
+
+        let scopes = api_key_request
+            .scopes
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<Vec<Scope>, _>>()
+            .map_err(Status::invalid_argument)?;
+
+        if scopes.is_empty() {
+            return Err(Status::invalid_argument("At least one scope is required"));
+        }
+
+        match self
+            .api_key_handler
+            .create(&username, api_key_request.name, scopes)
+            .await
+        {
+            Some(api_key) => Ok(Response::new(api_key)),
+            None => Err(Status::internal("Failed to create API key")),
+        }
+    }
+
+    async fn list_api_keys(
+        &self,
+        request: Request<ListApiKeysRequest>,
+    ) -> Result<Response<ApiKeyList>, Status> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+
+        Ok(Response::new(ApiKeyList {
+            api_keys: self.api_key_handler.list(&username).await,
+        }))
+    }
+
+    async fn revoke_api_key(
+        &self,
+        request: Request<RevokeApiKeyRequest>,
+    ) -> Result<Response<ApiKeyResult>, Status> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+        let id = request.into_inner().id;
+
+        if self.api_key_handler.revoke(&username, &id).await {
+            return Ok(Response::new(ApiKeyResult { result: true }));
+        }
+
+        Err(Status::not_found("API key not found"))
     }
 }
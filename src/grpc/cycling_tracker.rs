@@ -6,8 +6,26 @@ use tonic::{Request, Response, Status, Streaming};
 use tracing::info;
 
 use crate::cycling_tracker::cycling_tracker_server::CyclingTracker;
-use crate::cycling_tracker::{Measurement, Workout, WorkoutRequest, WorkoutSummary};
-use crate::handler::{SessionHandler, WorkoutHandler};
+use crate::cycling_tracker::{
+    ghost_race_request, live_recording_request, live_recording_response,
+    measurements_request, AerobicTrend, AerobicTrendRequest, CriticalPowerModel,
+    CriticalPowerRequest, DetectIntervalsRequest, DetectedIntervals, GhostAlignment,
+    GhostRaceRequest, GhostUpdate, LiveRecordingRequest, LiveRecordingResponse,
+    LiveWorkoutUpdate, Measurement, MeasurementsRequest, RecordingAck, RecordingStatus,
+    RecordingStatusRequest, Route, SharePermission, WatchLiveWorkoutRequest,
+    WeeklyTotals, WeeklyTotalsRequest, Workout, WorkoutRequest, WorkoutSummary,
+};
+use crate::handler::analysis::{detect_intervals, IntervalOptions};
+use crate::handler::critical_power::WPrimeBalance;
+use crate::handler::ghost::GhostRace;
+use crate::handler::plan::PlanExecution;
+use crate::handler::recording::is_valid_recording_id;
+use crate::handler::resample::downsample;
+use crate::handler::route::route;
+use crate::handler::{
+    LiveHandler, PlanHandler, RecordingHandler, Scope, SessionHandler, SharingHandler,
+    SummaryAccumulator, WorkoutHandler,
+};
 
 type GRPCResult<T> = Result<Response<T>, Status>;
 
@@ -15,18 +33,208 @@ type GRPCResult<T> = Result<Response<T>, Status>;
 pub struct CyclingTrackerService {
     workout_handler: WorkoutHandler,
     session_handler: SessionHandler,
+    sharing_handler: SharingHandler,
+    live_handler: LiveHandler,
+    recording_handler: RecordingHandler,
+    plan_handler: PlanHandler,
 }
 
 impl CyclingTrackerService {
     pub fn new(
         workout_handler: WorkoutHandler,
         session_handler: SessionHandler,
+        sharing_handler: SharingHandler,
+        live_handler: LiveHandler,
+        recording_handler: RecordingHandler,
+        plan_handler: PlanHandler,
     ) -> Self {
         Self {
             workout_handler,
             session_handler,
+            sharing_handler,
+            live_handler,
+            recording_handler,
+            plan_handler,
         }
     }
+
+    /// Start tracking a workout for the user, continuing from the given one.
+    /// Workouts with a recording id are checkpointed and saved when finished,
+    /// others only feed the averages. Summaries only include the measurements
+    /// so far if the history is kept.
+    fn start_workout(
+        &self,
+        username: String,
+        recording_id: Option<String>,
+        workout: Workout,
+        keep_history: bool,
+    ) -> ActiveWorkout {
+        ActiveWorkout {
+            username,
+            recording_id,
+            km_ridden: workout.measurements.iter().map(|m| m.speed).sum(),
+            accumulator: SummaryAccumulator::from_workout(
+                self.workout_handler.auto_pause,
+                &workout,
+            ),
+            history: keep_history.then_some(workout.measurements),
+            paused: false,
+            w_prime_balance: None,
+            live_handler: self.live_handler.clone(),
+            recording_handler: self.recording_handler.clone(),
+        }
+    }
+
+    /// Record measurements of an open recording until the stream ends, then save
+    /// it as a workout.
+    async fn record(
+        &self,
+        mut active_workout: ActiveWorkout,
+        mut stream: Streaming<Measurement>,
+    ) -> GRPCResult<WorkoutSummary> {
+        while let Some(measurement) = stream.next().await {
+            let measurement = measurement?;
+            info!("Measurement = {:?}", &measurement);
+
+            active_workout
+                .add(measurement)
+                .await
+                .ok_or(Status::aborted("Recording was already finalized"))?;
+        }
+        info!("Recording done");
+
+        let summary = active_workout
+            .finish()
+            .await
+            .ok_or(Status::aborted("Recording was already finalized"))?;
+
+        Ok(Response::new(summary))
+    }
+}
+
+/// A workout whose measurements are still coming in.
+struct ActiveWorkout {
+    username: String,
+    recording_id: Option<String>,
+    km_ridden: f32,
+    accumulator: SummaryAccumulator,
+    history: Option<Vec<Measurement>>,
+    paused: bool,
+    w_prime_balance: Option<WPrimeBalance>,
+    live_handler: LiveHandler,
+    recording_handler: RecordingHandler,
+}
+
+impl ActiveWorkout {
+    /// Add a measurement and return the updated averages, or None if the
+    /// recording was already finalized.
+    async fn add(&mut self, measurement: Measurement) -> Option<WorkoutSummary> {
+        if let Some(recording_id) = &self.recording_id {
+            if !self
+                .recording_handler
+                .append(&self.username, recording_id, &measurement)
+                .await
+            {
+                return None;
+            }
+        }
+
+        self.km_ridden += measurement.speed;
+        self.accumulator.add(&measurement);
+        if let Some(w_prime_balance) = &mut self.w_prime_balance {
+            w_prime_balance.add(&measurement);
+        }
+
+        let summary = self.summary();
+        self.publish(LiveWorkoutUpdate {
+            measurement: Some(measurement.clone()),
+            summary: Some(summary.clone()),
+            finished: false,
+        })
+        .await;
+
+        if let Some(history) = &mut self.history {
+            history.push(measurement);
+        }
+
+        Some(self.with_history(summary))
+    }
+
+    /// Start a new lap at the next measurement, returning false if the recording
+    /// was already finalized.
+    async fn start_lap(&mut self) -> bool {
+        let Some(start_index) = self.accumulator.start_lap() else {
+            return true;
+        };
+
+        if let Some(recording_id) = &self.recording_id {
+            if !self
+                .recording_handler
+                .add_lap(&self.username, recording_id, start_index)
+                .await
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Save the recording, if any, and let watchers know the workout is over.
+    /// Returns None if the recording was already finalized.
+    async fn finish(self) -> Option<WorkoutSummary> {
+        self.publish(LiveWorkoutUpdate {
+            finished: true,
+            ..Default::default()
+        })
+        .await;
+
+        match &self.recording_id {
+            Some(recording_id) => {
+                self.recording_handler
+                    .finish(&self.username, recording_id)
+                    .await
+            }
+            None => Some(self.with_history(self.summary())),
+        }
+    }
+
+    fn summary(&self) -> WorkoutSummary {
+        WorkoutSummary {
+            w_prime_balance: self.w_prime_balance.as_ref().map(|w| w.balance()),
+            ..self.accumulator.summary(self.km_ridden)
+        }
+    }
+
+    fn with_history(&self, summary: WorkoutSummary) -> WorkoutSummary {
+        match &self.history {
+            Some(history) => WorkoutSummary {
+                measurements: history.clone(),
+                ..summary
+            },
+            None => summary,
+        }
+    }
+
+    /// Publish an update to anyone watching the athlete.
+    async fn publish(&self, update: LiveWorkoutUpdate) {
+        self.live_handler.publish(&self.username, update).await;
+    }
+}
+
+/// Return the recording id given in the request metadata, if any.
+#[allow(clippy::result_large_err)]
+fn recording_id<RT>(request: &Request<RT>) -> Result<Option<String>, Status> {
+    let Some(recording_id) = request.metadata().get("recording-id") else {
+        return Ok(None);
+    };
+
+    match recording_id.to_str() {
+        Ok(recording_id) if is_valid_recording_id(recording_id) => {
+            Ok(Some(recording_id.to_string()))
+        }
+        _ => Err(Status::invalid_argument("Invalid recording id")),
+    }
 }
 
 #[tonic::async_trait]
@@ -35,11 +243,22 @@ impl CyclingTracker for CyclingTrackerService {
         &self,
         request: Request<Workout>,
     ) -> GRPCResult<WorkoutSummary> {
-        self.session_handler.verify_session_token(&request)?;
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
 
         let workout = request.into_inner();
+        let owner = self
+            .sharing_handler
+            .authorize(username, workout.athlete.clone(), SharePermission::Write)
+            .await?;
 
-        let summary = self.workout_handler.save_workout(&workout).await;
+        let summary = self
+            .workout_handler
+            .save_workout(&workout, &owner)
+            .await
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
         Ok(Response::new(summary))
     }
@@ -48,16 +267,91 @@ impl CyclingTracker for CyclingTrackerService {
 
     async fn get_measurements(
         &self,
-        request: Request<WorkoutRequest>,
+        request: Request<MeasurementsRequest>,
     ) -> GRPCResult<Self::GetMeasurementsStream> {
-        self.session_handler.verify_session_token(&request)?;
+        use measurements_request::Range;
+
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let measurements_request = request.into_inner();
+        let owner = self
+            .sharing_handler
+            .authorize(
+                username.clone(),
+                measurements_request.athlete.clone(),
+                SharePermission::Read,
+            )
+            .await?;
 
-        let workout_id = request.into_inner().id;
-        let measurements: Vec<Measurement> = self
+        let mut measurements: Vec<Measurement> = self
             .workout_handler
-            .get_measurements(workout_id)
+            .get_measurements(measurements_request.id, &owner)
             .await
-            .unwrap();
+            .ok_or(Status::not_found("Workout not found"))?;
+
+        if let Some(mask) = self.sharing_handler.privacy_mask(&username, &owner).await {
+            mask.mask_workout(&mut measurements);
+        }
+
+        // Measurements are one second apart, so times are offsets into them
+        let (start, end) = match &measurements_request.range {
+            None => (0, None),
+            Some(Range::IndexRange(range)) => {
+                (range.start as i64, range.end.map(|end| end as i64))
+            }
+            Some(Range::TimeRange(range)) => {
+                let start_time = self
+                    .workout_handler
+                    .start_time(measurements_request.id)
+                    .await
+                    .ok_or(Status::failed_precondition("Workout has no start time"))?;
+
+                let start = range.start.checked_sub(start_time);
+                let end = range.end.map(|end| end.checked_sub(start_time));
+
+                match (start, end) {
+                    (Some(start), None) => (start, None),
+                    (Some(start), Some(Some(end))) => (start, Some(end)),
+                    _ => {
+                        return Err(Status::invalid_argument(
+                            "Time range out of bounds",
+                        ))
+                    }
+                }
+            }
+        };
+        let count = measurements.len() as i64;
+        let start = start.clamp(0, count) as usize;
+        let end = end.unwrap_or(count).clamp(0, count) as usize;
+        if start > end {
+            return Err(Status::invalid_argument("Range ends before it starts"));
+        }
+
+        if measurements_request.max_points == Some(0) {
+            return Err(Status::invalid_argument("Max points must be positive"));
+        }
+
+        if measurements_request.range.is_some()
+            || measurements_request.max_points.is_some()
+        {
+            let max_points = measurements_request.max_points.unwrap_or(u32::MAX);
+
+            measurements = downsample(
+                &measurements[start..end],
+                max_points as usize,
+                measurements_request.aggregation(),
+                measurements_request.lttb_field(),
+            )
+            .into_iter()
+            .map(|(index, measurement)| Measurement {
+                index: Some((start + index) as u32),
+                ..measurement
+            })
+            .collect();
+        }
 
         let (tx, rx) = channel(32);
         tokio::spawn(async move {
@@ -74,24 +368,67 @@ impl CyclingTracker for CyclingTrackerService {
         &self,
         request: Request<Streaming<Measurement>>,
     ) -> GRPCResult<WorkoutSummary> {
-        self.session_handler.verify_session_token(&request)?;
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
 
-        let mut stream = request.into_inner();
+        // Recordings without an id can't be resumed, but are still checkpointed
+        let recording_id =
+            recording_id(&request)?.unwrap_or_else(|| uuid7::uuid7().to_string());
 
-        let mut workout = Workout {
-            km_ridden: 53.5,
-            ..Default::default()
-        };
+        if !self.recording_handler.start(&username, &recording_id) {
+            return Err(Status::already_exists("Recording already exists"));
+        }
 
-        while let Some(measurement) = stream.next().await {
-            info!("Measurement = {:?}", &measurement);
-            workout.measurements.push(measurement?.clone());
+        let active_workout =
+            self.start_workout(username, Some(recording_id), Workout::default(), false);
+
+        self.record(active_workout, request.into_inner()).await
+    }
+
+    async fn resume_recording(
+        &self,
+        request: Request<Streaming<Measurement>>,
+    ) -> GRPCResult<WorkoutSummary> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
+
+        let recording_id = recording_id(&request)?
+            .ok_or(Status::invalid_argument("Recording id not provided"))?;
+
+        if !self.recording_handler.is_open(&username, &recording_id) {
+            return Err(Status::not_found("Recording not found"));
         }
-        info!("Recording done");
 
-        let summary = self.workout_handler.save_workout(&workout).await;
+        let workout = self.recording_handler.workout(&username, &recording_id);
+        let active_workout =
+            self.start_workout(username, Some(recording_id), workout, false);
 
-        Ok(Response::new(summary))
+        self.record(active_workout, request.into_inner()).await
+    }
+
+    async fn get_recording_status(
+        &self,
+        request: Request<RecordingStatusRequest>,
+    ) -> GRPCResult<RecordingStatus> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
+
+        let recording_id = request.into_inner().recording_id;
+        let acknowledged = self
+            .recording_handler
+            .acknowledged(&username, &recording_id)
+            .ok_or(Status::not_found("Recording not found"))?;
+
+        Ok(Response::new(RecordingStatus {
+            recording_id,
+            acknowledged,
+        }))
     }
 
     type GetCurrentAveragesStream =
@@ -101,36 +438,421 @@ impl CyclingTracker for CyclingTrackerService {
         &self,
         request: Request<Streaming<Measurement>>,
     ) -> GRPCResult<Self::GetCurrentAveragesStream> {
-        self.session_handler.verify_session_token(&request)?;
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
 
-        let mut stream = request.into_inner();
+        let model = self.workout_handler.recent_critical_power(&username).await;
 
-        let mut workout = Workout::default();
-        let workout_handler = self.workout_handler.clone();
+        let keep_history = !request.metadata().contains_key("omit-measurements");
+        let mut stream = request.into_inner();
+        let mut active_workout =
+            self.start_workout(username, None, Workout::default(), keep_history);
+        active_workout.w_prime_balance = model.as_ref().map(WPrimeBalance::new);
 
         let output = async_stream::try_stream! {
             while let Some(measurement) = stream.next().await {
-                workout.measurements.push(measurement.clone()?);
-                workout.km_ridden += measurement?.speed;
-                yield workout_handler.create_summary(&workout);
+                if let Some(summary) = active_workout.add(measurement?).await {
+                    yield summary;
+                }
             }
+            active_workout.finish().await;
         };
 
         Ok(Response::new(
             Box::pin(output) as Self::GetCurrentAveragesStream
         ))
     }
-}
 
-impl std::ops::Add for Measurement {
-    type Output = Self;
+    type LiveRecordingStream = Pin<
+        Box<dyn Stream<Item = Result<LiveRecordingResponse, Status>> + Send + 'static>,
+    >;
 
-    fn add(self, other: Self) -> Self {
-        Self {
-            speed: self.speed + other.speed,
-            watts: self.watts + other.watts,
-            rpm: self.rpm + other.rpm,
-            heartrate: self.heartrate + other.heartrate,
+    async fn live_recording(
+        &self,
+        request: Request<Streaming<LiveRecordingRequest>>,
+    ) -> GRPCResult<Self::LiveRecordingStream> {
+        use live_recording_request::Event;
+        use live_recording_response::Event as ResponseEvent;
+
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
+
+        let recording_id =
+            recording_id(&request)?.unwrap_or_else(|| uuid7::uuid7().to_string());
+
+        if !self.recording_handler.start(&username, &recording_id) {
+            return Err(Status::already_exists("Recording already exists"));
+        }
+
+        let mut stream = request.into_inner();
+        let mut active_workout = self.start_workout(
+            username.clone(),
+            Some(recording_id),
+            Workout::default(),
+            false,
+        );
+        let plan_handler = self.plan_handler.clone();
+
+        let output = async_stream::try_stream! {
+            let mut sequence = 0;
+            let mut plan: Option<PlanExecution> = None;
+
+            while let Some(request) = stream.next().await {
+                let event = request?
+                    .event
+                    .ok_or(Status::invalid_argument("Unknown recording event"))?;
+
+                let finished = matches!(event, Event::Finish(_));
+                let mut compliance = None;
+                let mut target = None;
+                let summary = match event {
+                    Event::Measurement(measurement) if !active_workout.paused => {
+                        // Plans are graded on the same measurements as the averages
+                        if let Some(plan) = &mut plan {
+                            compliance = plan.add(&measurement);
+                            target = compliance.as_ref().and(plan.target());
+                        }
+
+                        Some(active_workout.add(measurement).await.ok_or(
+                            Status::aborted("Recording was already finalized"),
+                        )?)
+                    }
+                    Event::Measurement(_) | Event::Finish(_) => None,
+                    Event::Lap(_) => {
+                        if !active_workout.start_lap().await {
+                            Err(Status::aborted("Recording was already finalized"))?;
+                        }
+                        None
+                    }
+                    Event::Pause(_) => {
+                        active_workout.paused = true;
+                        None
+                    }
+                    Event::Resume(_) => {
+                        active_workout.paused = false;
+                        None
+                    }
+                    Event::StartPlan(start) => {
+                        let stored = plan_handler
+                            .get(start.plan_id, &username)
+                            .await
+                            .ok_or(Status::not_found("Plan not found"))?;
+                        let execution = PlanExecution::new(&stored, start.ftp).ok_or(
+                            Status::invalid_argument("Plan requires a positive FTP"),
+                        )?;
+
+                        target = execution.target();
+                        plan = Some(execution);
+                        None
+                    }
+                };
+
+                yield LiveRecordingResponse {
+                    event: Some(ResponseEvent::Ack(RecordingAck { sequence })),
+                };
+                sequence += 1;
+
+                if let Some(summary) = summary {
+                    yield LiveRecordingResponse {
+                        event: Some(ResponseEvent::Summary(WorkoutSummary {
+                            measurements: vec![],
+                            ..summary
+                        })),
+                    };
+                }
+
+                if let Some(compliance) = compliance {
+                    yield LiveRecordingResponse {
+                        event: Some(ResponseEvent::Compliance(compliance)),
+                    };
+                }
+
+                if let Some(target) = target {
+                    yield LiveRecordingResponse {
+                        event: Some(ResponseEvent::Target(target)),
+                    };
+                }
+
+                if finished {
+                    break;
+                }
+            }
+            info!("Recording done");
+
+            let saved = active_workout
+                .finish()
+                .await
+                .ok_or(Status::aborted("Recording was already finalized"))?;
+
+            yield LiveRecordingResponse {
+                event: Some(ResponseEvent::Saved(saved)),
+            };
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::LiveRecordingStream))
+    }
+
+    async fn get_detected_intervals(
+        &self,
+        request: Request<DetectIntervalsRequest>,
+    ) -> GRPCResult<DetectedIntervals> {
+        // Saving laps changes the workout
+        let (scope, permission) = if request.get_ref().save_as_laps {
+            (Scope::WorkoutsWrite, SharePermission::Write)
+        } else {
+            (Scope::WorkoutsRead, SharePermission::Read)
+        };
+
+        let username = self
+            .session_handler
+            .verify_session_token(&request, scope)
+            .await?;
+
+        let request = request.into_inner();
+        if request.ftp <= 0 {
+            return Err(Status::invalid_argument("FTP must be positive"));
+        }
+
+        let owner = self
+            .sharing_handler
+            .authorize(username, request.athlete.clone(), permission)
+            .await?;
+
+        let measurements = self
+            .workout_handler
+            .get_measurements(request.id, &owner)
+            .await
+            .ok_or(Status::not_found("Workout not found"))?;
+
+        let intervals =
+            detect_intervals(&measurements, &IntervalOptions::from(&request));
+
+        if request.save_as_laps {
+            let laps: Vec<u32> = intervals.iter().map(|i| i.start_index).collect();
+
+            if !self
+                .workout_handler
+                .set_laps(request.id, &owner, &laps)
+                .await
+            {
+                return Err(Status::internal("Failed to save laps"));
+            }
         }
+
+        Ok(Response::new(DetectedIntervals { intervals }))
+    }
+
+    type WatchLiveWorkoutStream =
+        Pin<Box<dyn Stream<Item = Result<LiveWorkoutUpdate, Status>> + Send + 'static>>;
+
+    async fn watch_live_workout(
+        &self,
+        request: Request<WatchLiveWorkoutRequest>,
+    ) -> GRPCResult<Self::WatchLiveWorkoutStream> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let athlete = self
+            .sharing_handler
+            .authorize(
+                username.clone(),
+                request.into_inner().athlete,
+                SharePermission::Read,
+            )
+            .await?;
+
+        let mut updates = self.live_handler.subscribe(&athlete).await;
+
+        if let Some(mut mask) =
+            self.sharing_handler.privacy_mask(&username, &athlete).await
+        {
+            let mut unmasked = updates;
+            updates = Box::pin(async_stream::stream! {
+                while let Some(mut update) = unmasked.next().await {
+                    // The bounding box could give away where the workout started
+                    if let Some(summary) = update.summary.as_mut() {
+                        summary.bounding_box = None;
+                    }
+                    for update in mask.mask_live(update) {
+                        yield update;
+                    }
+                }
+            });
+        }
+
+        Ok(Response::new(
+            Box::pin(updates.map(Ok)) as Self::WatchLiveWorkoutStream
+        ))
+    }
+
+    type GhostRaceStream =
+        Pin<Box<dyn Stream<Item = Result<GhostUpdate, Status>> + Send + 'static>>;
+
+    async fn ghost_race(
+        &self,
+        request: Request<Streaming<GhostRaceRequest>>,
+    ) -> GRPCResult<Self::GhostRaceStream> {
+        use ghost_race_request::Event;
+
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let mut stream = request.into_inner();
+        let workout_handler = self.workout_handler.clone();
+        let sharing_handler = self.sharing_handler.clone();
+
+        let output = async_stream::try_stream! {
+            let mut race: Option<GhostRace> = None;
+
+            while let Some(request) = stream.next().await {
+                let event = request?
+                    .event
+                    .ok_or(Status::invalid_argument("Unknown ghost race event"))?;
+
+                match event {
+                    Event::Start(start) => {
+                        let owner = sharing_handler
+                            .authorize(
+                                username.clone(),
+                                start.athlete,
+                                SharePermission::Read,
+                            )
+                            .await?;
+
+                        let mut ghost = workout_handler
+                            .get_measurements(start.workout_id, &owner)
+                            .await
+                            .ok_or(Status::not_found("Workout not found"))?;
+                        if let Some(mask) =
+                            sharing_handler.privacy_mask(&username, &owner).await
+                        {
+                            mask.mask_workout(&mut ghost);
+                        }
+                        let alignment = GhostAlignment::try_from(start.alignment)
+                            .map_err(|_| Status::invalid_argument("Unknown alignment"))?;
+
+                        race = Some(GhostRace::new(ghost, alignment));
+                    }
+                    Event::Measurement(measurement) => {
+                        let race = race.as_mut().ok_or(Status::failed_precondition(
+                            "Ghost race wasn't started",
+                        ))?;
+
+                        yield race.add(&measurement);
+                    }
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::GhostRaceStream))
+    }
+
+    async fn get_weekly_totals(
+        &self,
+        request: Request<WeeklyTotalsRequest>,
+    ) -> GRPCResult<WeeklyTotals> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let request = request.into_inner();
+        let owner = self
+            .sharing_handler
+            .authorize(username, request.athlete, SharePermission::Read)
+            .await?;
+
+        let weeks = self
+            .workout_handler
+            .weekly_totals(&owner, request.from, request.to)
+            .await;
+
+        Ok(Response::new(WeeklyTotals { weeks }))
+    }
+
+    async fn get_route(&self, request: Request<WorkoutRequest>) -> GRPCResult<Route> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let workout_request = request.into_inner();
+        let owner = self
+            .sharing_handler
+            .authorize(
+                username.clone(),
+                workout_request.athlete,
+                SharePermission::Read,
+            )
+            .await?;
+
+        let mut measurements = self
+            .workout_handler
+            .get_measurements(workout_request.id, &owner)
+            .await
+            .ok_or(Status::not_found("Workout not found"))?;
+
+        if let Some(mask) = self.sharing_handler.privacy_mask(&username, &owner).await {
+            mask.mask_workout(&mut measurements);
+        }
+
+        Ok(Response::new(route(&measurements)))
+    }
+
+    async fn get_aerobic_trend(
+        &self,
+        request: Request<AerobicTrendRequest>,
+    ) -> GRPCResult<AerobicTrend> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let request = request.into_inner();
+        let owner = self
+            .sharing_handler
+            .authorize(username, request.athlete, SharePermission::Read)
+            .await?;
+
+        let points = self
+            .workout_handler
+            .aerobic_trend(&owner, request.from, request.to)
+            .await;
+
+        Ok(Response::new(AerobicTrend { points }))
+    }
+
+    async fn get_critical_power(
+        &self,
+        request: Request<CriticalPowerRequest>,
+    ) -> GRPCResult<CriticalPowerModel> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let request = request.into_inner();
+        let owner = self
+            .sharing_handler
+            .authorize(username, request.athlete, SharePermission::Read)
+            .await?;
+
+        let model = self
+            .workout_handler
+            .critical_power(&owner, request.from, request.to)
+            .await
+            .ok_or(Status::failed_precondition(
+                "Not enough efforts to fit a critical power model",
+            ))?;
+
+        Ok(Response::new(model))
     }
 }
@@ -0,0 +1,123 @@
+use tonic::{Request, Response, Status};
+
+use crate::cycling_tracker::{
+    workout_plans_server::WorkoutPlans, ExportPlanRequest, ListPlansRequest, PlanFile,
+    PlanRequest, PlanResult, WorkoutPlan, WorkoutPlanList,
+};
+use crate::handler::{PlanHandler, Scope, SessionHandler};
+
+type GRPCResult<T> = Result<Response<T>, Status>;
+
+pub struct PlanService {
+    plan_handler: PlanHandler,
+    session_handler: SessionHandler,
+}
+
+impl PlanService {
+    pub fn new(plan_handler: PlanHandler, session_handler: SessionHandler) -> Self {
+        Self {
+            plan_handler,
+            session_handler,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl WorkoutPlans for PlanService {
+    async fn create_plan(
+        &self,
+        request: Request<WorkoutPlan>,
+    ) -> GRPCResult<WorkoutPlan> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
+
+        let plan = self
+            .plan_handler
+            .create(&username, request.into_inner())
+            .await?;
+
+        Ok(Response::new(plan))
+    }
+
+    async fn list_plans(
+        &self,
+        request: Request<ListPlansRequest>,
+    ) -> GRPCResult<WorkoutPlanList> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        Ok(Response::new(WorkoutPlanList {
+            plans: self.plan_handler.list(&username).await,
+        }))
+    }
+
+    async fn get_plan(&self, request: Request<PlanRequest>) -> GRPCResult<WorkoutPlan> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let plan = self
+            .plan_handler
+            .get(request.into_inner().id, &username)
+            .await
+            .ok_or(Status::not_found("Plan not found"))?;
+
+        Ok(Response::new(plan))
+    }
+
+    async fn delete_plan(
+        &self,
+        request: Request<PlanRequest>,
+    ) -> GRPCResult<PlanResult> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
+
+        if self
+            .plan_handler
+            .delete(request.into_inner().id, &username)
+            .await
+        {
+            return Ok(Response::new(PlanResult { result: true }));
+        }
+
+        Err(Status::not_found("Plan not found"))
+    }
+
+    async fn import_plan(&self, request: Request<PlanFile>) -> GRPCResult<WorkoutPlan> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
+
+        let plan = self
+            .plan_handler
+            .import(&username, request.into_inner())
+            .await?;
+
+        Ok(Response::new(plan))
+    }
+
+    async fn export_plan(
+        &self,
+        request: Request<ExportPlanRequest>,
+    ) -> GRPCResult<PlanFile> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let file = self
+            .plan_handler
+            .export(&username, request.get_ref())
+            .await?;
+
+        Ok(Response::new(file))
+    }
+}
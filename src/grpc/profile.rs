@@ -0,0 +1,55 @@
+use tonic::{Request, Response, Status};
+
+use crate::cycling_tracker::{profile_server::Profile, ProfileRequest, UserProfile};
+use crate::handler::{ProfileHandler, Scope, SessionHandler};
+
+type GRPCResult<T> = Result<Response<T>, Status>;
+
+pub struct ProfileService {
+    profile_handler: ProfileHandler,
+    session_handler: SessionHandler,
+}
+
+impl ProfileService {
+    pub fn new(
+        profile_handler: ProfileHandler,
+        session_handler: SessionHandler,
+    ) -> Self {
+        Self {
+            profile_handler,
+            session_handler,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Profile for ProfileService {
+    async fn get_profile(
+        &self,
+        request: Request<ProfileRequest>,
+    ) -> GRPCResult<UserProfile> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+
+        Ok(Response::new(self.profile_handler.get(&username).await))
+    }
+
+    async fn update_profile(
+        &self,
+        request: Request<UserProfile>,
+    ) -> GRPCResult<UserProfile> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+
+        let profile = self
+            .profile_handler
+            .update(&username, request.into_inner())
+            .await?;
+
+        Ok(Response::new(profile))
+    }
+}
@@ -0,0 +1,63 @@
+use tonic::{Request, Response, Status};
+
+use crate::cycling_tracker::{
+    segments_server::Segments, CreateSegmentRequest, Segment, SegmentLeaderboard,
+    SegmentRequest,
+};
+use crate::handler::{Scope, SegmentHandler, SessionHandler};
+
+type GRPCResult<T> = Result<Response<T>, Status>;
+
+pub struct SegmentService {
+    segment_handler: SegmentHandler,
+    session_handler: SessionHandler,
+}
+
+impl SegmentService {
+    pub fn new(
+        segment_handler: SegmentHandler,
+        session_handler: SessionHandler,
+    ) -> Self {
+        Self {
+            segment_handler,
+            session_handler,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Segments for SegmentService {
+    async fn create_segment(
+        &self,
+        request: Request<CreateSegmentRequest>,
+    ) -> GRPCResult<Segment> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::WorkoutsWrite)
+            .await?;
+
+        let segment = self
+            .segment_handler
+            .create(&username, request.into_inner())
+            .await?;
+
+        Ok(Response::new(segment))
+    }
+
+    async fn get_segment_leaderboard(
+        &self,
+        request: Request<SegmentRequest>,
+    ) -> GRPCResult<SegmentLeaderboard> {
+        self.session_handler
+            .verify_session_token(&request, Scope::WorkoutsRead)
+            .await?;
+
+        let leaderboard = self
+            .segment_handler
+            .leaderboard(request.into_inner().id)
+            .await
+            .ok_or(Status::not_found("Segment not found"))?;
+
+        Ok(Response::new(leaderboard))
+    }
+}
@@ -0,0 +1,117 @@
+use tonic::{Request, Response, Status};
+
+use crate::cycling_tracker::{
+    sharing_server::Sharing, AcceptInvitationRequest, ListInvitationsRequest,
+    ListSharesRequest, RevokeShareRequest, ShareInvitation, ShareList, SharePermission,
+    ShareResult,
+};
+use crate::handler::{Scope, SessionHandler, SharingHandler};
+
+type GRPCResult<T> = Result<Response<T>, Status>;
+
+pub struct SharingService {
+    sharing_handler: SharingHandler,
+    session_handler: SessionHandler,
+}
+
+impl SharingService {
+    pub fn new(
+        sharing_handler: SharingHandler,
+        session_handler: SessionHandler,
+    ) -> Self {
+        Self {
+            sharing_handler,
+            session_handler,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Sharing for SharingService {
+    async fn invite_coach(
+        &self,
+        request: Request<ShareInvitation>,
+    ) -> GRPCResult<ShareResult> {
+        let athlete = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+        let invitation = request.into_inner();
+
+        let permission = SharePermission::try_from(invitation.permission)
+            .map_err(|_| Status::invalid_argument("Unknown permission"))?;
+
+        self.sharing_handler
+            .invite(&athlete, &invitation.coach, permission)
+            .await?;
+
+        println!(
+            "User {:?} invited coach {:?} with permission {:?}",
+            athlete, invitation.coach, permission
+        );
+
+        Ok(Response::new(ShareResult { result: true }))
+    }
+
+    async fn list_invitations(
+        &self,
+        request: Request<ListInvitationsRequest>,
+    ) -> GRPCResult<ShareList> {
+        let coach = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+
+        Ok(Response::new(ShareList {
+            shares: self.sharing_handler.invitations(&coach).await,
+        }))
+    }
+
+    async fn accept_invitation(
+        &self,
+        request: Request<AcceptInvitationRequest>,
+    ) -> GRPCResult<ShareResult> {
+        let coach = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+        let athlete = request.into_inner().athlete;
+
+        if self.sharing_handler.accept(&athlete, &coach).await {
+            return Ok(Response::new(ShareResult { result: true }));
+        }
+
+        Err(Status::not_found("Invitation not found"))
+    }
+
+    async fn list_shares(
+        &self,
+        request: Request<ListSharesRequest>,
+    ) -> GRPCResult<ShareList> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+
+        Ok(Response::new(ShareList {
+            shares: self.sharing_handler.shares(&username).await,
+        }))
+    }
+
+    async fn revoke_share(
+        &self,
+        request: Request<RevokeShareRequest>,
+    ) -> GRPCResult<ShareResult> {
+        let username = self
+            .session_handler
+            .verify_session_token(&request, Scope::Account)
+            .await?;
+        let other = request.into_inner().username;
+
+        if self.sharing_handler.revoke(&username, &other).await {
+            return Ok(Response::new(ShareResult { result: true }));
+        }
+
+        Err(Status::not_found("Share not found"))
+    }
+}
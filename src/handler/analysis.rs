@@ -0,0 +1,176 @@
+use crate::cycling_tracker::{
+    DetectIntervalsRequest, DetectedInterval, IntervalKind, Measurement,
+};
+
+// Normalized power is based on a 30 second rolling average
+const NP_WINDOW: usize = 30;
+
+const DEFAULT_THRESHOLD: f32 = 0.9;
+const DEFAULT_MIN_DURATION: u32 = 30;
+const DEFAULT_MAX_DROPOUT: u32 = 5;
+
+/// Parameters of interval detection, with durations in measurements.
+pub struct IntervalOptions {
+    /// Minimum watts of work intervals
+    pub threshold_watts: f32,
+    pub min_duration: usize,
+    pub max_dropout: usize,
+}
+
+impl From<&DetectIntervalsRequest> for IntervalOptions {
+    fn from(request: &DetectIntervalsRequest) -> Self {
+        Self {
+            threshold_watts: request.ftp as f32
+                * request.threshold.unwrap_or(DEFAULT_THRESHOLD),
+            min_duration: request.min_duration.unwrap_or(DEFAULT_MIN_DURATION) as usize,
+            max_dropout: request.max_dropout.unwrap_or(DEFAULT_MAX_DROPOUT) as usize,
+        }
+    }
+}
+
+/// Return the normalized power of measurements one second apart: the fourth root
+/// of the mean fourth power of the 30 second rolling average. Efforts shorter
+/// than 30 seconds are averaged over their whole length.
+pub fn normalized_power(measurements: &[Measurement]) -> i32 {
+    if measurements.is_empty() {
+        return 0;
+    }
+
+    let window = NP_WINDOW.min(measurements.len());
+    let watts: Vec<f64> = measurements.iter().map(|m| m.watts as f64).collect();
+
+    let rolling_averages: Vec<f64> = watts
+        .windows(window)
+        .map(|watts| watts.iter().sum::<f64>() / window as f64)
+        .collect();
+
+    let mean_fourth_power = rolling_averages.iter().map(|p| p.powi(4)).sum::<f64>()
+        / rolling_averages.len() as f64;
+
+    mean_fourth_power.powf(0.25).round() as i32
+}
+
+/// Power to heart rate metrics of a workout, telling how well an athlete's
+/// aerobic fitness holds up over a steady ride.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AerobicMetrics {
+    pub efficiency_factor: Option<f32>,
+    pub decoupling: Option<f32>,
+    pub heartrate_drift: Option<f32>,
+}
+
+/// Compare the first and second half of the measurements. Heart rates of zero
+/// are dropouts, and left out of the averages.
+pub fn aerobic_metrics(measurements: &[Measurement]) -> AerobicMetrics {
+    let (first_half, second_half) = measurements.split_at(measurements.len() / 2);
+
+    let decoupling = match (
+        efficiency_factor(first_half),
+        efficiency_factor(second_half),
+    ) {
+        (Some(first), Some(second)) => Some((first - second) / first * 100.0),
+        _ => None,
+    };
+    let heartrate_drift = match (
+        average_heartrate(first_half),
+        average_heartrate(second_half),
+    ) {
+        (Some(first), Some(second)) => Some((second - first) / first * 100.0),
+        _ => None,
+    };
+
+    AerobicMetrics {
+        efficiency_factor: efficiency_factor(measurements),
+        decoupling,
+        heartrate_drift,
+    }
+}
+
+fn efficiency_factor(measurements: &[Measurement]) -> Option<f32> {
+    let normalized_power = normalized_power(measurements);
+    if normalized_power <= 0 {
+        return None;
+    }
+
+    average_heartrate(measurements).map(|heartrate| normalized_power as f32 / heartrate)
+}
+
+fn average_heartrate(measurements: &[Measurement]) -> Option<f32> {
+    let heartrates: Vec<i32> = measurements
+        .iter()
+        .map(|m| m.heartrate)
+        .filter(|&heartrate| heartrate > 0)
+        .collect();
+
+    (!heartrates.is_empty())
+        .then(|| heartrates.iter().sum::<i32>() as f32 / heartrates.len() as f32)
+}
+
+/// Split the measurements into work intervals, where power stays above the
+/// threshold except for short dropouts, and the recovery intervals between them.
+/// Returns nothing if there's no work interval.
+pub fn detect_intervals(
+    measurements: &[Measurement],
+    options: &IntervalOptions,
+) -> Vec<DetectedInterval> {
+    // Efforts as [start, end) ranges, bridging dropouts
+    let mut efforts: Vec<(usize, usize)> = vec![];
+
+    for (index, measurement) in measurements.iter().enumerate() {
+        if (measurement.watts as f32) < options.threshold_watts {
+            continue;
+        }
+
+        match efforts.last_mut() {
+            Some((_, end)) if index - *end <= options.max_dropout => *end = index + 1,
+            _ => efforts.push((index, index + 1)),
+        }
+    }
+
+    efforts.retain(|(start, end)| end - start >= options.min_duration);
+
+    let mut intervals = vec![];
+    let mut position = 0;
+
+    for (start, end) in efforts {
+        if position < start {
+            intervals.push(interval(
+                IntervalKind::Recovery,
+                measurements,
+                position,
+                start,
+            ));
+        }
+        intervals.push(interval(IntervalKind::Work, measurements, start, end));
+        position = end;
+    }
+
+    if !intervals.is_empty() && position < measurements.len() {
+        intervals.push(interval(
+            IntervalKind::Recovery,
+            measurements,
+            position,
+            measurements.len(),
+        ));
+    }
+
+    intervals
+}
+
+fn interval(
+    kind: IntervalKind,
+    measurements: &[Measurement],
+    start: usize,
+    end: usize,
+) -> DetectedInterval {
+    let measurements = &measurements[start..end];
+    let total_watts: i32 = measurements.iter().map(|m| m.watts).sum();
+
+    DetectedInterval {
+        kind: kind.into(),
+        start_index: start as u32,
+        duration: measurements.len() as u32,
+        avg_watts: total_watts / measurements.len() as i32,
+        normalized_power: normalized_power(measurements),
+    }
+}
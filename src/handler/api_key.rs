@@ -0,0 +1,115 @@
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use data_encoding::{BASE32_NOPAD, HEXLOWER};
+use sha2::{Digest, Sha256};
+
+use crate::cycling_tracker::{ApiKey, CreatedApiKey};
+use crate::handler::SQLiteHandler;
+
+/// Prefix of every API key, used to tell them apart from session tokens.
+pub const API_KEY_PREFIX: &str = "ctk_";
+
+/// Permissions required by an RPC. Session tokens are granted every scope, API keys
+/// only the ones they were created with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    WorkoutsRead,
+    WorkoutsWrite,
+    /// Managing the account itself, never granted to API keys
+    Account,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::WorkoutsRead => "workouts:read",
+            Scope::WorkoutsWrite => "workouts:write",
+            Scope::Account => "account",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "workouts:read" => Ok(Scope::WorkoutsRead),
+            "workouts:write" => Ok(Scope::WorkoutsWrite),
+            _ => Err(format!("Unknown scope: {s}")),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiKeyHandler {
+    pub sqlite_handler: SQLiteHandler,
+}
+
+impl ApiKeyHandler {
+    pub async fn create(
+        &self,
+        username: &str,
+        name: String,
+        scopes: Vec<Scope>,
+    ) -> Option<CreatedApiKey> {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let key = format!(
+            "{API_KEY_PREFIX}{}",
+            BASE32_NOPAD.encode(&secret).to_lowercase()
+        );
+
+        let api_key = ApiKey {
+            id: uuid7::uuid7().to_string(),
+            name,
+            scopes: scopes.iter().map(|s| s.as_str().to_string()).collect(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+        };
+
+        if !self
+            .sqlite_handler
+            .save_api_key(username, &api_key, hash_key(&key))
+            .await
+        {
+            return None;
+        }
+
+        Some(CreatedApiKey {
+            api_key: Some(api_key),
+            key,
+        })
+    }
+
+    pub async fn list(&self, username: &str) -> Vec<ApiKey> {
+        self.sqlite_handler.get_api_keys(username).await
+    }
+
+    pub async fn revoke(&self, username: &str, id: &str) -> bool {
+        self.sqlite_handler.delete_api_key(username, id).await
+    }
+
+    /// Return the owner of the API key, if it exists and was granted the scope.
+    pub async fn verify(&self, key: &str, scope: Scope) -> Option<String> {
+        let (username, scopes) = self
+            .sqlite_handler
+            .get_api_key_owner(&hash_key(key))
+            .await?;
+
+        scopes
+            .iter()
+            .any(|s| s == scope.as_str())
+            .then_some(username)
+    }
+}
+
+// API keys are long random strings, so unlike passwords a fast hash is enough,
+// and lets keys be looked up by their hash.
+fn hash_key(key: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(key.as_bytes()))
+}
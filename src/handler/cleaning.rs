@@ -0,0 +1,246 @@
+use crate::cycling_tracker::{
+    CleanedSample, CleaningReason, Measurement, MeasurementField,
+};
+
+// Neighbors on either side a value is compared with to find spikes
+const SPIKE_NEIGHBORS: usize = 2;
+
+/// How a field of the measurements is cleaned.
+#[derive(Clone, Copy, Debug)]
+pub struct FieldCleaning {
+    /// Values outside this range are invalid
+    pub min: f32,
+    pub max: f32,
+    /// Values differing more than this from the median of their neighbors are
+    /// spikes. None keeps spikes.
+    pub max_spike: Option<f32>,
+    /// Whether zeros are sensor dropouts, rather than real readings
+    pub zero_is_dropout: bool,
+}
+
+/// Cleaning of raw measurements before they're stored. Invalid values, spikes
+/// and dropouts are interpolated across short gaps, and set to zero otherwise.
+#[derive(Clone, Copy, Debug)]
+pub struct DataCleaning {
+    pub speed: FieldCleaning,
+    pub watts: FieldCleaning,
+    pub rpm: FieldCleaning,
+    pub heartrate: FieldCleaning,
+    /// Longest run of invalid values that's interpolated, in measurements
+    pub max_gap: usize,
+}
+
+impl Default for DataCleaning {
+    fn default() -> Self {
+        Self {
+            speed: FieldCleaning {
+                min: 0.0,
+                max: 150.0,
+                max_spike: Some(30.0),
+                zero_is_dropout: false,
+            },
+            watts: FieldCleaning {
+                min: 0.0,
+                max: 3000.0,
+                max_spike: Some(1000.0),
+                zero_is_dropout: false,
+            },
+            rpm: FieldCleaning {
+                min: 0.0,
+                max: 250.0,
+                max_spike: Some(60.0),
+                zero_is_dropout: false,
+            },
+            heartrate: FieldCleaning {
+                min: 0.0,
+                max: 250.0,
+                max_spike: Some(40.0),
+                zero_is_dropout: true,
+            },
+            max_gap: 5,
+        }
+    }
+}
+
+impl DataCleaning {
+    /// Clean the measurements in place, returning the modified values in order
+    /// of their index.
+    pub fn clean(&self, measurements: &mut [Measurement]) -> Vec<CleanedSample> {
+        let mut cleaned = vec![];
+
+        for (field, cleaning) in [
+            (MeasurementField::Speed, self.speed),
+            (MeasurementField::Watts, self.watts),
+            (MeasurementField::Rpm, self.rpm),
+            (MeasurementField::Heartrate, self.heartrate),
+        ] {
+            let values: Vec<f32> = measurements.iter().map(|m| get(m, field)).collect();
+            let reasons = cleaning.invalid_values(&values);
+            let fixed = interpolate(&values, &reasons, self.max_gap);
+
+            for (index, reason) in reasons.into_iter().enumerate() {
+                // Zeros in long dropouts stay zero, as the field wasn't reported
+                let Some(reason) = reason.filter(|_| fixed[index] != values[index])
+                else {
+                    continue;
+                };
+
+                set(&mut measurements[index], field, fixed[index]);
+                cleaned.push(CleanedSample {
+                    index: index as u32,
+                    field: field.into(),
+                    reason: reason.into(),
+                    original_value: values[index],
+                    value: get(&measurements[index], field),
+                });
+            }
+        }
+
+        cleaned.sort_by_key(|sample| (sample.index, sample.field));
+        cleaned
+    }
+}
+
+impl FieldCleaning {
+    /// Why each value is invalid, or None if it's valid.
+    fn invalid_values(&self, values: &[f32]) -> Vec<Option<CleaningReason>> {
+        let mut reasons: Vec<Option<CleaningReason>> = values
+            .iter()
+            .map(|&value| {
+                if !(self.min..=self.max).contains(&value) {
+                    Some(CleaningReason::OutOfRange)
+                } else if self.zero_is_dropout && value == 0.0 {
+                    Some(CleaningReason::Dropout)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let Some(max_spike) = self.max_spike else {
+            return reasons;
+        };
+
+        // Spikes are found among the other values first, so that a spike doesn't
+        // hide its neighbor
+        let spikes: Vec<usize> = (0..values.len())
+            .filter(|&index| reasons[index].is_none())
+            .filter(|&index| {
+                let start = index.saturating_sub(SPIKE_NEIGHBORS);
+                let end = (index + SPIKE_NEIGHBORS + 1).min(values.len());
+                let mut neighbors: Vec<f32> = (start..end)
+                    .filter(|&i| i != index && reasons[i].is_none())
+                    .map(|i| values[i])
+                    .collect();
+
+                median(&mut neighbors)
+                    .is_some_and(|median| (values[index] - median).abs() > max_spike)
+            })
+            .collect();
+
+        for index in spikes {
+            reasons[index] = Some(CleaningReason::Spike);
+        }
+
+        reasons
+    }
+}
+
+/// Median of at least two values
+fn median(values: &mut [f32]) -> Option<f32> {
+    if values.len() < 2 {
+        return None;
+    }
+
+    values.sort_by(f32::total_cmp);
+    let middle = values.len() / 2;
+
+    Some(match values.len() % 2 {
+        0 => (values[middle - 1] + values[middle]) / 2.0,
+        _ => values[middle],
+    })
+}
+
+/// Replace runs of invalid values of up to max_gap with a straight line between
+/// the valid values around them, and other invalid values with zero.
+fn interpolate(
+    values: &[f32],
+    reasons: &[Option<CleaningReason>],
+    max_gap: usize,
+) -> Vec<f32> {
+    let mut fixed = values.to_vec();
+    let mut index = 0;
+
+    while index < values.len() {
+        if reasons[index].is_none() {
+            index += 1;
+            continue;
+        }
+
+        let start = index;
+        while index < values.len() && reasons[index].is_some() {
+            index += 1;
+        }
+
+        let gap = index - start;
+        let bounds = (
+            start.checked_sub(1),
+            (index < values.len()).then_some(index),
+        );
+        for (offset, value) in fixed[start..index].iter_mut().enumerate() {
+            *value = match bounds {
+                (Some(before), Some(after)) if gap <= max_gap => {
+                    let share = (offset + 1) as f32 / (gap + 1) as f32;
+                    values[before] + (values[after] - values[before]) * share
+                }
+                _ => 0.0,
+            };
+        }
+    }
+
+    fixed
+}
+
+pub(crate) fn get(measurement: &Measurement, field: MeasurementField) -> f32 {
+    match field {
+        MeasurementField::Unspecified => 0.0,
+        MeasurementField::Speed => measurement.speed,
+        MeasurementField::Watts => measurement.watts as f32,
+        MeasurementField::Rpm => measurement.rpm as f32,
+        MeasurementField::Heartrate => measurement.heartrate as f32,
+    }
+}
+
+/// Set a field, rounding to whole numbers where the field is an integer.
+fn set(measurement: &mut Measurement, field: MeasurementField, value: f32) {
+    match field {
+        MeasurementField::Unspecified => {}
+        MeasurementField::Speed => measurement.speed = value,
+        MeasurementField::Watts => measurement.watts = value.round() as i32,
+        MeasurementField::Rpm => measurement.rpm = value.round() as i32,
+        MeasurementField::Heartrate => measurement.heartrate = value.round() as i32,
+    }
+}
+
+impl MeasurementField {
+    pub fn as_db_str(&self) -> &'static str {
+        match self {
+            MeasurementField::Unspecified => "unspecified",
+            MeasurementField::Speed => "speed",
+            MeasurementField::Watts => "watts",
+            MeasurementField::Rpm => "rpm",
+            MeasurementField::Heartrate => "heartrate",
+        }
+    }
+}
+
+impl CleaningReason {
+    pub fn as_db_str(&self) -> &'static str {
+        match self {
+            CleaningReason::Unspecified => "unspecified",
+            CleaningReason::OutOfRange => "out_of_range",
+            CleaningReason::Spike => "spike",
+            CleaningReason::Dropout => "dropout",
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::cycling_tracker::{CriticalPowerModel, MeanMaxPower, Measurement};
+
+/// Durations in seconds of the efforts the model is fitted to. Shorter efforts
+/// are limited by other systems, and longer ones by fatigue.
+pub const MODEL_DURATIONS: [u32; 5] = [120, 180, 300, 600, 1200];
+
+/// The highest average power over the given number of measurements, which are
+/// assumed to be one second apart. None if the workout is shorter.
+pub fn mean_max_power(measurements: &[Measurement], duration: u32) -> Option<f32> {
+    let duration = duration as usize;
+    if duration == 0 || measurements.len() < duration {
+        return None;
+    }
+
+    let watts: Vec<i64> = measurements.iter().map(|m| m.watts.max(0) as i64).collect();
+    let mut sum: i64 = watts[..duration].iter().sum();
+    let mut max = sum;
+
+    for index in duration..watts.len() {
+        sum += watts[index] - watts[index - duration];
+        max = max.max(sum);
+    }
+
+    Some(max as f32 / duration as f32)
+}
+
+/// Fit the work of the mean-max efforts linearly to their duration, so that
+/// work = critical power * duration + W′. Returns None with fewer than two
+/// efforts, or if the fit doesn't give a positive critical power and W′.
+pub fn fit(mean_max_power: Vec<MeanMaxPower>) -> Option<CriticalPowerModel> {
+    if mean_max_power.len() < 2 {
+        return None;
+    }
+
+    let points: Vec<(f64, f64)> = mean_max_power
+        .iter()
+        .map(|p| (p.duration as f64, p.duration as f64 * p.watts as f64))
+        .collect();
+    let count = points.len() as f64;
+    let mean_duration = points.iter().map(|(t, _)| t).sum::<f64>() / count;
+    let mean_work = points.iter().map(|(_, w)| w).sum::<f64>() / count;
+
+    let mut covariance = 0.0;
+    let mut duration_variance = 0.0;
+    let mut work_variance = 0.0;
+    for (duration, work) in points.iter() {
+        covariance += (duration - mean_duration) * (work - mean_work);
+        duration_variance += (duration - mean_duration).powi(2);
+        work_variance += (work - mean_work).powi(2);
+    }
+
+    let critical_power = covariance / duration_variance;
+    let w_prime = mean_work - critical_power * mean_duration;
+    if critical_power <= 0.0 || w_prime <= 0.0 {
+        return None;
+    }
+
+    Some(CriticalPowerModel {
+        critical_power: critical_power as f32,
+        w_prime: w_prime as f32,
+        r_squared: (covariance.powi(2) / (duration_variance * work_variance)) as f32,
+        mean_max_power,
+    })
+}
+
+/// W′ left during a ride, using the differential model of Skiba et al.: W′ is
+/// spent above critical power, and recovers exponentially below it.
+#[derive(Clone, Copy, Debug)]
+pub struct WPrimeBalance {
+    critical_power: f64,
+    w_prime: f64,
+    balance: f64,
+}
+
+impl WPrimeBalance {
+    pub fn new(model: &CriticalPowerModel) -> Self {
+        Self {
+            critical_power: model.critical_power as f64,
+            w_prime: model.w_prime as f64,
+            balance: model.w_prime as f64,
+        }
+    }
+
+    /// Update the balance with a measurement one second after the last.
+    pub fn add(&mut self, measurement: &Measurement) {
+        let watts = measurement.watts.max(0) as f64;
+
+        if watts > self.critical_power {
+            self.balance -= watts - self.critical_power;
+        } else {
+            self.balance += (self.w_prime - self.balance)
+                * (self.critical_power - watts)
+                / self.w_prime;
+        }
+    }
+
+    /// Joules left, negative if the rider went deeper than the model predicts
+    pub fn balance(&self) -> f32 {
+        self.balance as f32
+    }
+}
@@ -0,0 +1,51 @@
+use crate::cycling_tracker::{Measurement, Sex, UserProfile};
+
+pub const DEFAULT_GROSS_EFFICIENCY: f32 = 0.24;
+
+const KJ_PER_KCAL: f32 = 4.184;
+
+/// Energy spent in kcal, from the work in kJ if the workout has power, otherwise
+/// from heart rate. Measurements are assumed to be one second apart.
+pub fn calories(
+    work: f32,
+    measurements: &[Measurement],
+    profile: &UserProfile,
+) -> Option<f32> {
+    if work > 0.0 {
+        let gross_efficiency =
+            profile.gross_efficiency.unwrap_or(DEFAULT_GROSS_EFFICIENCY);
+
+        return Some(work / gross_efficiency / KJ_PER_KCAL);
+    }
+
+    heart_rate_calories(measurements, profile)
+}
+
+/// Estimate energy from heart rate, age, weight and sex with the formulas of
+/// Keytel et al. (2005), over the measurements reporting heart rate.
+fn heart_rate_calories(
+    measurements: &[Measurement],
+    profile: &UserProfile,
+) -> Option<f32> {
+    let age = profile.age? as f32;
+    let weight = profile.weight?;
+    let (intercept, per_heartbeat, per_kg, per_year) = match profile.sex() {
+        Sex::Male => (-55.0969, 0.6309, 0.1988, 0.2017),
+        Sex::Female => (-20.4022, 0.4472, -0.1263, 0.074),
+        Sex::Unspecified => return None,
+    };
+
+    let mut seconds = 0;
+    let mut work = 0.0;
+    for measurement in measurements.iter().filter(|m| m.heartrate > 0) {
+        let kj_per_minute = intercept
+            + per_heartbeat * measurement.heartrate as f32
+            + per_kg * weight
+            + per_year * age;
+
+        seconds += 1;
+        work += kj_per_minute.max(0.0) / 60.0;
+    }
+
+    (seconds > 0).then_some(work / KJ_PER_KCAL)
+}
@@ -0,0 +1,80 @@
+use crate::cycling_tracker::{GhostAlignment, GhostUpdate, Measurement};
+use crate::handler::route::distance_from_speed;
+
+/// Races a live ride against a stored workout, both one measurement per second.
+#[derive(Clone, Debug)]
+pub struct GhostRace {
+    alignment: GhostAlignment,
+    ghost: Vec<Measurement>,
+    /// Meters the ghost covered after each second, starting at 0
+    ghost_distances: Vec<f32>,
+    elapsed_time: u32,
+    distance: f32,
+}
+
+impl GhostRace {
+    pub fn new(ghost: Vec<Measurement>, alignment: GhostAlignment) -> Self {
+        let ghost_distances = std::iter::once(0.0)
+            .chain(ghost.iter().scan(0.0, |distance, measurement| {
+                *distance += distance_from_speed(measurement);
+                Some(*distance)
+            }))
+            .collect();
+
+        Self {
+            alignment,
+            ghost,
+            ghost_distances,
+            elapsed_time: 0,
+            distance: 0.0,
+        }
+    }
+
+    /// Add a measurement of the rider, returning the ghost's measurement at the same
+    /// elapsed time or distance, and the gaps between them.
+    pub fn add(&mut self, measurement: &Measurement) -> GhostUpdate {
+        self.elapsed_time += 1;
+        self.distance += distance_from_speed(measurement);
+
+        let ghost_distance = self.ghost_distance_at(self.elapsed_time);
+        let ghost_time = self.ghost_time_at(self.distance);
+
+        let index = match self.alignment {
+            GhostAlignment::Time => Some(self.elapsed_time as usize - 1),
+            // The measurement during which the ghost reached the distance
+            GhostAlignment::Distance => {
+                ghost_time.map(|time| (time.ceil() as usize).saturating_sub(1))
+            }
+        };
+
+        GhostUpdate {
+            ghost: index.and_then(|index| self.ghost.get(index).cloned()),
+            elapsed_time: self.elapsed_time,
+            distance: self.distance,
+            ghost_distance,
+            seconds_ahead: ghost_time.map(|time| time - self.elapsed_time as f32),
+            meters_ahead: self.distance - ghost_distance,
+        }
+    }
+
+    /// Meters the ghost covered after the given seconds, all of them once it's done.
+    fn ghost_distance_at(&self, seconds: u32) -> f32 {
+        let index = (seconds as usize).min(self.ghost_distances.len() - 1);
+
+        self.ghost_distances[index]
+    }
+
+    /// Seconds the ghost took to cover the given distance, interpolated within a
+    /// second, or None if it never did.
+    fn ghost_time_at(&self, distance: f32) -> Option<f32> {
+        let index = self.ghost_distances.partition_point(|&d| d < distance);
+        if index == 0 {
+            return Some(0.0);
+        }
+
+        let after = *self.ghost_distances.get(index)?;
+        let before = self.ghost_distances[index - 1];
+
+        Some((index - 1) as f32 + (distance - before) / (after - before))
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use prost::Message;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_stream::{Stream, StreamExt};
+use tracing::warn;
+
+use crate::cycling_tracker::LiveWorkoutUpdate;
+use crate::handler::RedisHandler;
+
+const LOCAL_CHANNEL_CAPACITY: usize = 64;
+
+pub type LiveWorkoutStream = Pin<Box<dyn Stream<Item = LiveWorkoutUpdate> + Send>>;
+
+/// Fans out live workout updates to everyone watching an athlete.
+///
+/// Updates go over Redis pub/sub, so watchers connected to other server instances
+/// receive them too. If Redis is unavailable, updates are only delivered to
+/// watchers connected to this instance.
+#[derive(Clone)]
+pub struct LiveHandler {
+    pub redis_handler: RedisHandler,
+    local: Arc<Mutex<HashMap<String, broadcast::Sender<LiveWorkoutUpdate>>>>,
+}
+
+impl LiveHandler {
+    pub fn new(redis_handler: RedisHandler) -> Self {
+        Self {
+            redis_handler,
+            local: Arc::default(),
+        }
+    }
+
+    pub async fn publish(&self, athlete: &str, update: LiveWorkoutUpdate) {
+        if self
+            .redis_handler
+            .publish(&live_channel(athlete), &update.encode_to_vec())
+            .await
+        {
+            return;
+        }
+
+        let mut local = self.local.lock().unwrap();
+        if let Some(sender) = local.get(athlete) {
+            if sender.send(update).is_err() {
+                // Nobody is watching anymore
+                local.remove(athlete);
+            }
+        }
+    }
+
+    pub async fn subscribe(&self, athlete: &str) -> LiveWorkoutStream {
+        match self.subscribe_redis(athlete).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Falling back to in-process live updates: {:?}", e);
+                self.subscribe_local(athlete)
+            }
+        }
+    }
+
+    async fn subscribe_redis(
+        &self,
+        athlete: &str,
+    ) -> redis::RedisResult<LiveWorkoutStream> {
+        let mut pubsub = self.redis_handler.client.get_async_pubsub().await?;
+        pubsub.subscribe(live_channel(athlete)).await?;
+
+        Ok(Box::pin(pubsub.into_on_message().filter_map(|msg| {
+            LiveWorkoutUpdate::decode(msg.get_payload_bytes()).ok()
+        })))
+    }
+
+    fn subscribe_local(&self, athlete: &str) -> LiveWorkoutStream {
+        let mut receiver = self
+            .local
+            .lock()
+            .unwrap()
+            .entry(athlete.to_string())
+            .or_insert_with(|| broadcast::channel(LOCAL_CHANNEL_CAPACITY).0)
+            .subscribe();
+
+        Box::pin(async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(update) => yield update,
+                    // Slow watchers skip the updates they missed
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+fn live_channel(athlete: &str) -> String {
+    format!("live:{athlete}")
+}
@@ -1,11 +1,40 @@
+pub mod analysis;
+pub mod api_key;
+pub mod cleaning;
+pub mod critical_power;
+pub mod energy;
+pub mod ghost;
+pub mod live;
+pub mod notifier;
+pub mod plan;
+pub mod plan_file;
+pub mod profile;
+pub mod recording;
 pub mod redis;
+pub mod resample;
+pub mod route;
+pub mod segment;
 pub mod session;
+pub mod sharing;
 pub mod sqlite;
+pub mod summary;
+pub mod totp;
 pub mod user;
 pub mod workout;
 
+pub use api_key::{ApiKeyHandler, Scope};
+pub use cleaning::DataCleaning;
+pub use live::LiveHandler;
+pub use notifier::{FileNotifier, LogNotifier, Notifier};
+pub use plan::PlanHandler;
+pub use profile::ProfileHandler;
+pub use recording::RecordingHandler;
 pub use redis::RedisHandler;
+pub use segment::SegmentHandler;
 pub use session::SessionHandler;
+pub use sharing::SharingHandler;
 pub use sqlite::SQLiteHandler;
+pub use summary::SummaryAccumulator;
+pub use totp::TotpHandler;
 pub use user::UserHandler;
-pub use workout::WorkoutHandler;
+pub use workout::{AutoPause, WorkoutHandler};
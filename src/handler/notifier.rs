@@ -0,0 +1,42 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use tracing::info;
+
+/// Delivers out-of-band messages, like password reset tokens, to users.
+pub trait Notifier: Send + Sync {
+    fn send_password_reset(&self, username: &str, reset_token: &str);
+}
+
+/// Writes notifications to the log, meant for local development.
+#[derive(Clone, Default)]
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn send_password_reset(&self, username: &str, reset_token: &str) {
+        info!(
+            "Password reset token for user {:?}: {}",
+            username, reset_token
+        );
+    }
+}
+
+/// Appends notifications to a file, one per line.
+#[derive(Clone)]
+pub struct FileNotifier {
+    pub path: PathBuf,
+}
+
+impl Notifier for FileNotifier {
+    fn send_password_reset(&self, username: &str, reset_token: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .expect("Failed to open notification file");
+
+        writeln!(file, "password-reset {} {}", username, reset_token)
+            .expect("Failed to write notification");
+    }
+}
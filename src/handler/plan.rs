@@ -0,0 +1,267 @@
+use tonic::Status;
+
+use crate::cycling_tracker::{
+    plan_step::{Power, PowerEnd},
+    ExportPlanRequest, Measurement, PlanFile, PlanFileFormat, PlanStep, PlanTarget,
+    StepCompliance, TargetRange, WorkoutPlan,
+};
+use crate::handler::plan_file;
+use crate::handler::summary::MeasurementStats;
+use crate::handler::SQLiteHandler;
+
+// Power within 10% of the target is on target, since trainers in ERG mode
+// fluctuate around it
+const POWER_TOLERANCE: f32 = 0.1;
+
+#[derive(Clone)]
+pub struct PlanHandler {
+    pub sqlite_handler: SQLiteHandler,
+}
+
+impl PlanHandler {
+    pub async fn create(
+        &self,
+        username: &str,
+        plan: WorkoutPlan,
+    ) -> Result<WorkoutPlan, Status> {
+        validate(&plan)?;
+
+        let id = self
+            .sqlite_handler
+            .save_plan(username, &plan)
+            .await
+            .ok_or(Status::internal("Failed to save plan"))?;
+
+        Ok(WorkoutPlan {
+            id: Some(id),
+            ..plan
+        })
+    }
+
+    pub async fn list(&self, username: &str) -> Vec<WorkoutPlan> {
+        self.sqlite_handler.get_plans(username).await
+    }
+
+    /// Return a plan, if it belongs to the given user.
+    pub async fn get(&self, plan_id: i32, username: &str) -> Option<WorkoutPlan> {
+        self.sqlite_handler.get_plan(plan_id, username).await
+    }
+
+    /// Delete a plan, if it belongs to the given user.
+    pub async fn delete(&self, plan_id: i32, username: &str) -> bool {
+        self.sqlite_handler.delete_plan(plan_id, username).await
+    }
+
+    /// Parse a workout file and save it as a plan of the user.
+    pub async fn import(
+        &self,
+        username: &str,
+        file: PlanFile,
+    ) -> Result<WorkoutPlan, Status> {
+        let format = PlanFileFormat::try_from(file.format)
+            .map_err(|_| Status::invalid_argument("Unknown file format"))?;
+
+        let plan = plan_file::parse(format, &file.content)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let plan = match file.name {
+            Some(name) => WorkoutPlan { name, ..plan },
+            None => plan,
+        };
+
+        self.create(username, plan).await
+    }
+
+    /// Return a plan of the user as a workout file.
+    pub async fn export(
+        &self,
+        username: &str,
+        request: &ExportPlanRequest,
+    ) -> Result<PlanFile, Status> {
+        let format = PlanFileFormat::try_from(request.format)
+            .map_err(|_| Status::invalid_argument("Unknown file format"))?;
+
+        let plan = self
+            .get(request.id, username)
+            .await
+            .ok_or(Status::not_found("Plan not found"))?;
+
+        let content = plan_file::export(&plan, format, request.ftp)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        Ok(PlanFile {
+            format: format.into(),
+            content,
+            name: Some(plan.name),
+        })
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn validate(plan: &WorkoutPlan) -> Result<(), Status> {
+    if plan.name.is_empty() {
+        return Err(Status::invalid_argument("Plan name can't be empty"));
+    }
+
+    if plan.steps.is_empty() {
+        return Err(Status::invalid_argument("Plan has no steps"));
+    }
+
+    for step in plan.steps.iter() {
+        if step.duration == 0 {
+            return Err(Status::invalid_argument("Step duration must be positive"));
+        }
+
+        match step.power {
+            Some(Power::Watts(watts)) if watts <= 0 => {
+                return Err(Status::invalid_argument("Target power must be positive"))
+            }
+            Some(Power::FtpShare(share)) if share.is_nan() || share <= 0.0 => {
+                return Err(Status::invalid_argument("Target power must be positive"))
+            }
+            _ => {}
+        }
+
+        match (&step.power, &step.power_end) {
+            (_, None) => {}
+            (Some(Power::Watts(_)), Some(PowerEnd::EndWatts(watts))) if *watts > 0 => {}
+            (Some(Power::FtpShare(_)), Some(PowerEnd::EndFtpShare(share)))
+                if *share > 0.0 => {}
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Ramps must end at a positive power in the unit they start with",
+                ))
+            }
+        }
+
+        let ranges = [step.heartrate.as_ref(), step.rpm.as_ref()];
+        if ranges.into_iter().flatten().any(|r| r.min > r.max) {
+            return Err(Status::invalid_argument(
+                "Target range minimum is above its maximum",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Follows a plan as measurements come in, one second apart.
+#[derive(Clone, Debug)]
+pub struct PlanExecution {
+    targets: Vec<PlanTarget>,
+    step_index: usize,
+    stats: MeasurementStats,
+    on_target: u32,
+}
+
+impl PlanExecution {
+    /// Resolve the targets of a plan, returning None if a step targets a share of
+    /// FTP and no FTP is given.
+    pub fn new(plan: &WorkoutPlan, ftp: i32) -> Option<Self> {
+        let targets = plan
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(step_index, step)| resolve(step_index as u32, step, ftp))
+            .collect::<Option<_>>()?;
+
+        Some(Self {
+            targets,
+            step_index: 0,
+            stats: MeasurementStats::default(),
+            on_target: 0,
+        })
+    }
+
+    /// Return the targets of the current step, or None if the plan is over.
+    pub fn target(&self) -> Option<PlanTarget> {
+        self.targets.get(self.step_index).cloned()
+    }
+
+    /// Add a measurement to the current step, returning the step's compliance if
+    /// it was its last measurement.
+    pub fn add(&mut self, measurement: &Measurement) -> Option<StepCompliance> {
+        let target = self.targets.get(self.step_index)?;
+
+        let second = self.stats.count() as u32;
+        self.stats.add(measurement);
+        if is_on_target(target, second, measurement) {
+            self.on_target += 1;
+        }
+
+        if self.stats.count() < target.duration as u64 {
+            return None;
+        }
+
+        let averages = self.stats.averages();
+        let compliance = StepCompliance {
+            step_index: target.step_index,
+            compliance: self.on_target as f32 / self.stats.count() as f32,
+            avg_watts: averages.watts,
+            avg_rpm: averages.rpm,
+            avg_heartrate: averages.heartrate,
+        };
+
+        self.step_index += 1;
+        self.stats = MeasurementStats::default();
+        self.on_target = 0;
+
+        Some(compliance)
+    }
+}
+
+fn resolve(step_index: u32, step: &PlanStep, ftp: i32) -> Option<PlanTarget> {
+    let uses_ftp = matches!(step.power, Some(Power::FtpShare(_)))
+        || matches!(step.power_end, Some(PowerEnd::EndFtpShare(_)));
+    if uses_ftp && ftp <= 0 {
+        return None;
+    }
+
+    let watts = match step.power {
+        Some(Power::Watts(watts)) => Some(watts),
+        Some(Power::FtpShare(share)) => Some((share * ftp as f32).round() as i32),
+        None => None,
+    };
+    let end_watts = match step.power_end {
+        Some(PowerEnd::EndWatts(watts)) => Some(watts),
+        Some(PowerEnd::EndFtpShare(share)) => Some((share * ftp as f32).round() as i32),
+        None => None,
+    };
+
+    Some(PlanTarget {
+        step_index,
+        duration: step.duration,
+        watts,
+        heartrate: step.heartrate.clone(),
+        rpm: step.rpm.clone(),
+        end_watts,
+    })
+}
+
+/// Whether a measurement at the given second of a step meets all of its targets.
+fn is_on_target(target: &PlanTarget, second: u32, measurement: &Measurement) -> bool {
+    let in_range = |range: &Option<TargetRange>, value: i32| match range {
+        Some(range) => (range.min..=range.max).contains(&value),
+        None => true,
+    };
+
+    // Ramps change linearly over the step
+    let watts = match (target.watts, target.end_watts) {
+        (Some(start), Some(end)) => Some(
+            start as f32
+                + (end - start) as f32 * second as f32 / target.duration as f32,
+        ),
+        (watts, _) => watts.map(|watts| watts as f32),
+    };
+
+    let power_on_target = match watts {
+        Some(watts) => {
+            (measurement.watts as f32 - watts).abs() <= watts * POWER_TOLERANCE
+        }
+        None => true,
+    };
+
+    power_on_target
+        && in_range(&target.heartrate, measurement.heartrate)
+        && in_range(&target.rpm, measurement.rpm)
+}
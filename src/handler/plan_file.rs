@@ -0,0 +1,441 @@
+use std::str::FromStr;
+
+use roxmltree::{Document, Node};
+use thiserror::Error;
+
+use crate::cycling_tracker::{
+    plan_step::{Power, PowerEnd},
+    PlanFileFormat, PlanStep, TargetRange, WorkoutPlan,
+};
+
+// ZWO cadence targets are single values, so they're widened into a range
+const CADENCE_TOLERANCE: i32 = 5;
+
+// Repeated intervals are expanded, so their number of steps is limited
+const MAX_STEPS: usize = 1000;
+
+const DEFAULT_NAME: &str = "Imported workout";
+
+#[derive(Debug, Error)]
+pub enum PlanFileError {
+    #[error("Invalid XML: {0}")]
+    InvalidXml(#[from] roxmltree::Error),
+
+    #[error("Invalid workout file: {0}")]
+    InvalidFile(String),
+
+    #[error("FTP is required to convert between watts and shares of FTP")]
+    FtpRequired,
+}
+
+/// Unit of power targets in workout files
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unit {
+    Watts,
+    FtpShare,
+}
+
+/// Parse a workout file into a plan, with ramps and free rides as single steps and
+/// repeated intervals expanded into steps.
+pub fn parse(
+    format: PlanFileFormat,
+    content: &str,
+) -> Result<WorkoutPlan, PlanFileError> {
+    let plan = match format {
+        PlanFileFormat::Zwo => parse_zwo(content)?,
+        PlanFileFormat::Erg => parse_course(content, Unit::Watts)?,
+        PlanFileFormat::Mrc => parse_course(content, Unit::FtpShare)?,
+    };
+
+    if plan.steps.is_empty() {
+        return Err(invalid("Workout has no steps"));
+    }
+
+    Ok(plan)
+}
+
+/// Write a plan as a workout file. Heart rate targets are left out, since none of
+/// the formats support them. The FTP is required if the plan's power targets are
+/// in a different unit than the format's.
+pub fn export(
+    plan: &WorkoutPlan,
+    format: PlanFileFormat,
+    ftp: Option<i32>,
+) -> Result<String, PlanFileError> {
+    let ftp = ftp.filter(|&ftp| ftp > 0);
+
+    match format {
+        PlanFileFormat::Zwo => export_zwo(plan, ftp),
+        PlanFileFormat::Erg => export_course(plan, Unit::Watts, ftp),
+        PlanFileFormat::Mrc => export_course(plan, Unit::FtpShare, ftp),
+    }
+}
+
+fn parse_zwo(content: &str) -> Result<WorkoutPlan, PlanFileError> {
+    let document = Document::parse(content)?;
+    let root = document.root_element();
+    if !root.has_tag_name("workout_file") {
+        return Err(invalid("Missing workout_file element"));
+    }
+
+    let name = child(root, "name")
+        .and_then(|name| name.text())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or(DEFAULT_NAME)
+        .to_string();
+
+    let workout = child(root, "workout").ok_or(invalid("Missing workout element"))?;
+
+    let mut steps = vec![];
+    for element in workout.children().filter(Node::is_element) {
+        let cadence = zwo_cadence(element, "Cadence")?;
+        let step_duration = || duration(element, "Duration");
+
+        match element.tag_name().name() {
+            "SteadyState" => {
+                // Some editors write steady states as a power range
+                let power = match optional_attribute(element, "Power")? {
+                    Some(power) => power,
+                    None => {
+                        let low: f32 = attribute(element, "PowerLow")?;
+                        let high: f32 = attribute(element, "PowerHigh")?;
+                        (low + high) / 2.0
+                    }
+                };
+                steps.push(zwo_step(step_duration()?, Some((power, power)), cadence));
+            }
+            // Cooldowns go from PowerLow to PowerHigh as well, despite the names
+            "Warmup" | "Cooldown" | "Ramp" => {
+                let low = attribute(element, "PowerLow")?;
+                let high = attribute(element, "PowerHigh")?;
+                steps.push(zwo_step(step_duration()?, Some((low, high)), cadence));
+            }
+            "IntervalsT" => {
+                let repeat: usize = attribute(element, "Repeat")?;
+                if repeat > MAX_STEPS || steps.len() + repeat * 2 > MAX_STEPS {
+                    return Err(invalid("Too many steps"));
+                }
+
+                let on_power = attribute(element, "OnPower")?;
+                let off_power = attribute(element, "OffPower")?;
+                let on = zwo_step(
+                    duration(element, "OnDuration")?,
+                    Some((on_power, on_power)),
+                    cadence,
+                );
+                let off = zwo_step(
+                    duration(element, "OffDuration")?,
+                    Some((off_power, off_power)),
+                    zwo_cadence(element, "CadenceResting")?,
+                );
+
+                for _ in 0..repeat {
+                    steps.push(on.clone());
+                    steps.push(off.clone());
+                }
+            }
+            "FreeRide" | "MaxEffort" => {
+                steps.push(zwo_step(step_duration()?, None, cadence));
+            }
+            other => return Err(invalid(&format!("Unsupported element {other}"))),
+        }
+
+        if steps.len() > MAX_STEPS {
+            return Err(invalid("Too many steps"));
+        }
+    }
+
+    Ok(WorkoutPlan {
+        id: None,
+        name,
+        steps,
+    })
+}
+
+fn zwo_step(
+    duration: u32,
+    power: Option<(f32, f32)>,
+    rpm: Option<TargetRange>,
+) -> PlanStep {
+    PlanStep {
+        duration,
+        power: power.map(|(start, _)| Power::FtpShare(start)),
+        heartrate: None,
+        rpm,
+        power_end: power
+            .filter(|(start, end)| start != end)
+            .map(|(_, end)| PowerEnd::EndFtpShare(end)),
+    }
+}
+
+fn zwo_cadence(node: Node, name: &str) -> Result<Option<TargetRange>, PlanFileError> {
+    let cadence: Option<f32> = optional_attribute(node, name)?;
+
+    Ok(cadence.map(|cadence| TargetRange {
+        min: cadence.round() as i32 - CADENCE_TOLERANCE,
+        max: cadence.round() as i32 + CADENCE_TOLERANCE,
+    }))
+}
+
+/// Parse an ERG or MRC file, whose power targets are points in time, linearly
+/// changing between them. The unit is taken from the header, if given.
+fn parse_course(
+    content: &str,
+    default_unit: Unit,
+) -> Result<WorkoutPlan, PlanFileError> {
+    let mut unit = default_unit;
+    let mut description = None;
+    let mut file_name = None;
+    let mut points: Vec<(u32, f32)> = vec![];
+
+    let mut section = "";
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if line.starts_with('[') {
+            section = line;
+            continue;
+        }
+
+        match section {
+            "[COURSE HEADER]" => match line.split_once('=') {
+                Some((key, value)) => match key.trim() {
+                    "DESCRIPTION" => description = Some(value.trim()),
+                    "FILE NAME" => file_name = Some(value.trim()),
+                    _ => {}
+                },
+                // Column names, such as MINUTES WATTS
+                None => match line.split_whitespace().nth(1) {
+                    Some("WATTS") => unit = Unit::Watts,
+                    Some("PERCENT") => unit = Unit::FtpShare,
+                    _ => {}
+                },
+            },
+            "[COURSE DATA]" => {
+                let mut values = line.split_whitespace().map(f32::from_str);
+                let (Some(Ok(minutes)), Some(Ok(value))) =
+                    (values.next(), values.next())
+                else {
+                    return Err(invalid(&format!("Invalid course data {line:?}")));
+                };
+
+                points.push(((minutes * 60.0).round() as u32, value));
+            }
+            _ => {}
+        }
+
+        if points.len() > MAX_STEPS {
+            return Err(invalid("Too many steps"));
+        }
+    }
+
+    let scale = match unit {
+        Unit::Watts => 1.0,
+        Unit::FtpShare => 0.01,
+    };
+
+    // Points at the same time are jumps between steps
+    let steps = points
+        .windows(2)
+        .filter(|pair| pair[1].0 > pair[0].0)
+        .map(|pair| {
+            let ((start_time, start), (end_time, end)) = (pair[0], pair[1]);
+            course_step(end_time - start_time, start * scale, end * scale, unit)
+        })
+        .collect();
+
+    let name = description
+        .or(file_name)
+        .filter(|name| !name.is_empty())
+        .unwrap_or(DEFAULT_NAME)
+        .to_string();
+
+    Ok(WorkoutPlan {
+        id: None,
+        name,
+        steps,
+    })
+}
+
+/// Steps at zero power are free rides.
+fn course_step(duration: u32, start: f32, end: f32, unit: Unit) -> PlanStep {
+    let (power, power_end) = match unit {
+        _ if start == 0.0 && end == 0.0 => (None, None),
+        Unit::Watts => (
+            Some(Power::Watts(start.round() as i32)),
+            Some(PowerEnd::EndWatts(end.round() as i32)),
+        ),
+        Unit::FtpShare => (
+            Some(Power::FtpShare(start)),
+            Some(PowerEnd::EndFtpShare(end)),
+        ),
+    };
+
+    PlanStep {
+        duration,
+        power,
+        heartrate: None,
+        rpm: None,
+        power_end: power_end.filter(|_| start != end),
+    }
+}
+
+fn export_zwo(plan: &WorkoutPlan, ftp: Option<i32>) -> Result<String, PlanFileError> {
+    let mut workout = String::new();
+
+    for step in plan.steps.iter() {
+        let cadence = step
+            .rpm
+            .as_ref()
+            .map(|rpm| format!(" Cadence=\"{}\"", (rpm.min + rpm.max) / 2))
+            .unwrap_or_default();
+
+        let element = match step_power(step, Unit::FtpShare, ftp)? {
+            None => format!("<FreeRide Duration=\"{}\"{cadence}/>", step.duration),
+            Some((start, end)) if start == end => format!(
+                "<SteadyState Duration=\"{}\" Power=\"{}\"{cadence}/>",
+                step.duration,
+                rounded(start, 3)
+            ),
+            Some((start, end)) => format!(
+                "<Ramp Duration=\"{}\" PowerLow=\"{}\" PowerHigh=\"{}\"{cadence}/>",
+                step.duration,
+                rounded(start, 3),
+                rounded(end, 3)
+            ),
+        };
+        workout.push_str(&format!("        {element}\n"));
+    }
+
+    Ok(format!(
+        "<workout_file>\n    <name>{}</name>\n    <sportType>bike</sportType>\n    \
+        <workout>\n{workout}    </workout>\n</workout_file>\n",
+        escape_xml(&plan.name)
+    ))
+}
+
+fn export_course(
+    plan: &WorkoutPlan,
+    unit: Unit,
+    ftp: Option<i32>,
+) -> Result<String, PlanFileError> {
+    let (columns, scale, decimals) = match unit {
+        Unit::Watts => ("MINUTES WATTS", 1.0, 0),
+        Unit::FtpShare => ("MINUTES PERCENT", 100.0, 1),
+    };
+
+    let mut data = String::new();
+    let mut seconds = 0;
+    for step in plan.steps.iter() {
+        // Free rides are written as zero power
+        let (start, end) = step_power(step, unit, ftp)?.unwrap_or((0.0, 0.0));
+
+        data.push_str(&format!(
+            "{:.2}\t{}\n",
+            seconds as f32 / 60.0,
+            rounded(start * scale, decimals)
+        ));
+        seconds += step.duration;
+        data.push_str(&format!(
+            "{:.2}\t{}\n",
+            seconds as f32 / 60.0,
+            rounded(end * scale, decimals)
+        ));
+    }
+
+    let name = plan.name.replace(['\r', '\n'], " ");
+    let ftp = match (unit, ftp) {
+        (Unit::Watts, Some(ftp)) => format!("FTP = {ftp}\n"),
+        _ => String::new(),
+    };
+
+    Ok(format!(
+        "[COURSE HEADER]\nVERSION = 2\nUNITS = ENGLISH\nDESCRIPTION = {name}\n\
+        FILE NAME = {name}\n{ftp}{columns}\n[END COURSE HEADER]\n\
+        [COURSE DATA]\n{data}[END COURSE DATA]\n"
+    ))
+}
+
+/// Return the power at the start and end of a step in the given unit, or None
+/// for free rides.
+fn step_power(
+    step: &PlanStep,
+    unit: Unit,
+    ftp: Option<i32>,
+) -> Result<Option<(f32, f32)>, PlanFileError> {
+    let (start, step_unit) = match step.power {
+        Some(Power::Watts(watts)) => (watts as f32, Unit::Watts),
+        Some(Power::FtpShare(share)) => (share, Unit::FtpShare),
+        None => return Ok(None),
+    };
+    let end = match step.power_end {
+        Some(PowerEnd::EndWatts(watts)) => watts as f32,
+        Some(PowerEnd::EndFtpShare(share)) => share,
+        None => start,
+    };
+
+    let convert = |power: f32| -> Result<f32, PlanFileError> {
+        match (step_unit, unit) {
+            (from, to) if from == to => Ok(power),
+            (Unit::Watts, _) => {
+                Ok(power / ftp.ok_or(PlanFileError::FtpRequired)? as f32)
+            }
+            (Unit::FtpShare, _) => {
+                Ok(power * ftp.ok_or(PlanFileError::FtpRequired)? as f32)
+            }
+        }
+    };
+
+    Ok(Some((convert(start)?, convert(end)?)))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn attribute<T: FromStr>(node: Node, name: &str) -> Result<T, PlanFileError> {
+    optional_attribute(node, name)?.ok_or(invalid(&format!(
+        "Missing {name} of {}",
+        node.tag_name().name()
+    )))
+}
+
+fn optional_attribute<T: FromStr>(
+    node: Node,
+    name: &str,
+) -> Result<Option<T>, PlanFileError> {
+    node.attribute(name)
+        .map(|value| {
+            value.trim().parse().map_err(|_| {
+                invalid(&format!("Invalid {name} of {}", node.tag_name().name()))
+            })
+        })
+        .transpose()
+}
+
+/// Durations are seconds, but some editors write them as decimals.
+fn duration(node: Node, name: &str) -> Result<u32, PlanFileError> {
+    let seconds: f32 = attribute(node, name)?;
+
+    Ok(seconds.round() as u32)
+}
+
+fn rounded(value: f32, decimals: i32) -> f32 {
+    let factor = 10f32.powi(decimals);
+
+    (value * factor).round() / factor
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn invalid(reason: &str) -> PlanFileError {
+    PlanFileError::InvalidFile(reason.to_string())
+}
@@ -0,0 +1,84 @@
+use tonic::Status;
+
+use crate::cycling_tracker::{PrivacyZone, Sex, UserProfile};
+use crate::handler::SQLiteHandler;
+
+// Zones are meant to hide places, not whole regions
+const MAX_ZONE_RADIUS: f32 = 5000.0;
+const MAX_ZONES: usize = 10;
+
+const MAX_AGE: u32 = 120;
+// Kilograms
+const MAX_WEIGHT: f32 = 500.0;
+
+#[derive(Clone)]
+pub struct ProfileHandler {
+    pub sqlite_handler: SQLiteHandler,
+}
+
+impl ProfileHandler {
+    pub async fn get(&self, username: &str) -> UserProfile {
+        self.sqlite_handler.get_profile(username).await
+    }
+
+    pub async fn update(
+        &self,
+        username: &str,
+        profile: UserProfile,
+    ) -> Result<UserProfile, Status> {
+        if profile.privacy_zones.len() > MAX_ZONES {
+            return Err(Status::invalid_argument("Too many privacy zones"));
+        }
+        if !profile.privacy_zones.iter().all(is_valid_zone) {
+            return Err(Status::invalid_argument("Invalid privacy zone"));
+        }
+        if profile.age.is_some_and(|age| age == 0 || age > MAX_AGE) {
+            return Err(Status::invalid_argument("Invalid age"));
+        }
+        if profile
+            .weight
+            .is_some_and(|weight| !(weight > 0.0 && weight <= MAX_WEIGHT))
+        {
+            return Err(Status::invalid_argument("Invalid weight"));
+        }
+        if profile
+            .gross_efficiency
+            .is_some_and(|efficiency| !(efficiency > 0.0 && efficiency <= 1.0))
+        {
+            return Err(Status::invalid_argument(
+                "Gross efficiency must be between 0 and 1",
+            ));
+        }
+
+        if !self.sqlite_handler.save_profile(username, &profile).await {
+            return Err(Status::internal("Failed to save profile"));
+        }
+
+        Ok(profile)
+    }
+}
+
+fn is_valid_zone(zone: &PrivacyZone) -> bool {
+    (-90.0..=90.0).contains(&zone.latitude)
+        && (-180.0..=180.0).contains(&zone.longitude)
+        && zone.radius > 0.0
+        && zone.radius <= MAX_ZONE_RADIUS
+}
+
+impl Sex {
+    pub fn as_db_str(&self) -> &'static str {
+        match self {
+            Sex::Unspecified => "unspecified",
+            Sex::Male => "male",
+            Sex::Female => "female",
+        }
+    }
+
+    pub fn from_db_str(sex: &str) -> Self {
+        match sex {
+            "male" => Sex::Male,
+            "female" => Sex::Female,
+            _ => Sex::Unspecified,
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+use tracing::{info, warn};
+
+use crate::cycling_tracker::{Measurement, Workout, WorkoutSummary};
+use crate::handler::{RedisHandler, WorkoutHandler};
+
+// Checkpoints are dropped after a day, in case they're never finalized
+const RECORDING_EXPIRY: u64 = 86400;
+
+// Recordings without new measurements for 10 minutes are considered orphaned
+pub const RECORDING_TIMEOUT: Duration = Duration::from_secs(600);
+
+const RECORDINGS_KEY: &str = "recordings";
+
+/// Checkpoints in-progress recordings, so they survive a dropped connection.
+///
+/// Each recording keeps its measurements in a list and the time of its last
+/// measurement in a separate key. Whoever takes the latter finalizes the
+/// recording, so it's saved exactly once, whether it's finished by its client or
+/// by the orphan finalizer.
+#[derive(Clone)]
+pub struct RecordingHandler {
+    pub redis_handler: RedisHandler,
+    pub workout_handler: WorkoutHandler,
+    pub timeout: Duration,
+}
+
+impl RecordingHandler {
+    /// Open a new recording, returning false if one with the same id exists.
+    pub fn start(&self, username: &str, recording_id: &str) -> bool {
+        if !self.redis_handler.set_key_if_absent(
+            &active_key(username, recording_id),
+            &now().to_string(),
+            RECORDING_EXPIRY,
+        ) {
+            return false;
+        }
+
+        self.redis_handler.add_to_set(
+            RECORDINGS_KEY,
+            &recordings_member(username, recording_id),
+            RECORDING_EXPIRY,
+        );
+
+        true
+    }
+
+    pub fn is_open(&self, username: &str, recording_id: &str) -> bool {
+        self.redis_handler
+            .get_key(&active_key(username, recording_id))
+            .is_some()
+    }
+
+    /// Checkpoint a measurement, returning false if the recording isn't open.
+    pub async fn append(
+        &self,
+        username: &str,
+        recording_id: &str,
+        measurement: &Measurement,
+    ) -> bool {
+        self.push_if_open(
+            username,
+            recording_id,
+            &recording_key(username, recording_id),
+            &measurement.encode_to_vec(),
+        )
+        .await
+    }
+
+    /// Return the number of measurements checkpointed so far.
+    pub fn acknowledged(&self, username: &str, recording_id: &str) -> Option<u64> {
+        if !self.is_open(username, recording_id) {
+            return None;
+        }
+
+        Some(
+            self.redis_handler
+                .get_list_len(&recording_key(username, recording_id)),
+        )
+    }
+
+    /// Checkpoint the start of a new lap, returning false if the recording isn't
+    /// open.
+    pub async fn add_lap(
+        &self,
+        username: &str,
+        recording_id: &str,
+        start_index: u32,
+    ) -> bool {
+        self.push_if_open(
+            username,
+            recording_id,
+            &laps_key(username, recording_id),
+            start_index.to_string().as_bytes(),
+        )
+        .await
+    }
+
+    /// Return the measurements and laps checkpointed so far, with the distance
+    /// ridden in them.
+    pub fn workout(&self, username: &str, recording_id: &str) -> Workout {
+        let measurements: Vec<Measurement> = self
+            .redis_handler
+            .get_list(&recording_key(username, recording_id))
+            .into_iter()
+            .filter_map(|measurement| Measurement::decode(measurement.as_slice()).ok())
+            .collect();
+
+        let laps = self
+            .redis_handler
+            .get_list(&laps_key(username, recording_id))
+            .into_iter()
+            .filter_map(|lap| String::from_utf8(lap).ok()?.parse().ok())
+            .collect();
+
+        Workout {
+            km_ridden: measurements.iter().map(|m| m.speed).sum(),
+            measurements,
+            laps,
+            ..Default::default()
+        }
+    }
+
+    /// Save the recording as a workout, returning None if it was already
+    /// finalized.
+    pub async fn finish(
+        &self,
+        username: &str,
+        recording_id: &str,
+    ) -> Option<WorkoutSummary> {
+        self.redis_handler
+            .take_key(&active_key(username, recording_id))?;
+
+        let workout = self.workout(username, recording_id);
+
+        self.redis_handler
+            .delete_key(&recording_key(username, recording_id));
+        self.redis_handler
+            .delete_key(&laps_key(username, recording_id));
+        self.redis_handler.remove_from_set(
+            RECORDINGS_KEY,
+            &recordings_member(username, recording_id),
+        );
+
+        // Recordings are saved regardless, taking timestamps that can't be
+        // resampled to be one second apart
+        match self.workout_handler.save_workout(&workout, username).await {
+            Ok(summary) => Some(summary),
+            Err(_) => {
+                let workout = Workout {
+                    measurements: workout
+                        .measurements
+                        .into_iter()
+                        .map(|m| Measurement {
+                            timestamp: None,
+                            ..m
+                        })
+                        .collect(),
+                    ..workout
+                };
+
+                self.workout_handler
+                    .save_workout(&workout, username)
+                    .await
+                    .ok()
+            }
+        }
+    }
+
+    /// Finalize recordings that haven't received measurements within the timeout.
+    pub async fn finalize_orphans(&self) {
+        for member in self.redis_handler.get_set_members(RECORDINGS_KEY) {
+            let Some((recording_id, username)) = member.split_once(':') else {
+                continue;
+            };
+
+            let last_active = self
+                .redis_handler
+                .get_key(&active_key(username, recording_id))
+                .and_then(|time| time.parse::<u128>().ok());
+
+            match last_active {
+                Some(time) if now() < time + self.timeout.as_millis() => {}
+                Some(_) => {
+                    if let Some(summary) = self.finish(username, recording_id).await {
+                        info!(
+                            "Finalized orphaned recording {:?} of user {:?} as workout {:?}",
+                            recording_id, username, summary.id
+                        );
+                    }
+                }
+                // Finished or expired
+                None => self.redis_handler.remove_from_set(RECORDINGS_KEY, &member),
+            }
+        }
+    }
+
+    /// Periodically finalize orphaned recordings, forever. Redis errors panic, so
+    /// each pass runs in its own task, and a failed pass is retried with the next.
+    pub async fn run_finalizer(self) {
+        let mut interval = tokio::time::interval(self.timeout);
+
+        loop {
+            interval.tick().await;
+
+            let handler = self.clone();
+            let pass = tokio::spawn(async move { handler.finalize_orphans().await });
+            if let Err(e) = pass.await {
+                warn!("Failed to finalize orphaned recordings: {:?}", e);
+            }
+        }
+    }
+
+    /// Push to one of the recording's lists and update the time of its last
+    /// measurement, in one step so nothing is pushed once it's been finalized.
+    async fn push_if_open(
+        &self,
+        username: &str,
+        recording_id: &str,
+        key: &str,
+        value: &[u8],
+    ) -> bool {
+        self.redis_handler
+            .push_to_list_if_exists(
+                &active_key(username, recording_id),
+                &now().to_string(),
+                key,
+                value,
+                RECORDING_EXPIRY,
+            )
+            .await
+    }
+}
+
+/// Recording ids are chosen by clients, so they're restricted to characters that
+/// can't be confused with key separators.
+pub fn is_valid_recording_id(recording_id: &str) -> bool {
+    !recording_id.is_empty()
+        && recording_id.len() <= 64
+        && recording_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Milliseconds since the UNIX epoch
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or_else(|e| {
+            warn!("System time before UNIX epoch: {:?}", e);
+            0
+        })
+}
+
+fn recording_key(username: &str, recording_id: &str) -> String {
+    format!("recording:{username}:{recording_id}")
+}
+
+fn laps_key(username: &str, recording_id: &str) -> String {
+    format!("recording-laps:{username}:{recording_id}")
+}
+
+fn active_key(username: &str, recording_id: &str) -> String {
+    format!("recording-active:{username}:{recording_id}")
+}
+
+// Ids can't contain ':', so they go first
+fn recordings_member(username: &str, recording_id: &str) -> String {
+    format!("{recording_id}:{username}")
+}
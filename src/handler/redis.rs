@@ -1,11 +1,39 @@
-use redis::Commands;
+use std::sync::Arc;
 
+use redis::{
+    aio::MultiplexedConnection, AsyncCommands, Commands, ExistenceCheck, RedisResult,
+    Script, SetExpiry, SetOptions,
+};
+use tokio::sync::Mutex;
+
+// Appends to a list and refreshes a guard key, only while the guard key exists
+const PUSH_IF_EXISTS_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return 0
+end
+redis.call('RPUSH', KEYS[2], ARGV[2])
+redis.call('EXPIRE', KEYS[2], ARGV[3])
+redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[3])
+return 1
+"#;
+
+/// Commands sent for every live measurement share one multiplexed connection,
+/// which is opened on first use and reopened after it breaks. Other commands
+/// open a connection each.
 #[derive(Clone)]
 pub struct RedisHandler {
     pub client: redis::Client,
+    shared: Arc<Mutex<Option<MultiplexedConnection>>>,
 }
 
 impl RedisHandler {
+    pub fn new(client: redis::Client) -> Self {
+        Self {
+            client,
+            shared: Arc::default(),
+        }
+    }
+
     pub fn set_key(&self, key: &String, value: &String, expiry: Option<u64>) {
         let mut con = self
             .client
@@ -25,15 +53,160 @@ impl RedisHandler {
         }
     }
 
+    /// Set a key only if it doesn't exist, returning whether it was set.
+    pub fn set_key_if_absent(&self, key: &str, value: &str, expiry: u64) -> bool {
+        let mut con = self
+            .client
+            .get_connection()
+            .expect("Failed to open connection with redis");
+
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(expiry));
+        con.set_options::<&str, &str, Option<String>>(key, value, options)
+            .expect("Failed to set Redis key")
+            .is_some()
+    }
+
     pub fn get_key(&self, key: &str) -> Option<String> {
         let mut con = self
             .client
             .get_connection()
             .expect("Failed to open connection with redis");
 
-        match con.get(key) {
-            Ok(value) => Some(value),
-            Err(_) => None,
+        con.get(key).ok()
+    }
+
+    pub fn delete_key(&self, key: &str) {
+        let mut con = self
+            .client
+            .get_connection()
+            .expect("Failed to open connection with redis");
+
+        con.del::<&str, ()>(key)
+            .expect("Failed to delete Redis key");
+    }
+
+    /// Add a member to a set, resetting the expiry of the whole set.
+    pub fn add_to_set(&self, key: &str, member: &str, expiry: u64) {
+        let mut con = self
+            .client
+            .get_connection()
+            .expect("Failed to open connection with redis");
+
+        con.sadd::<&str, &str, ()>(key, member)
+            .expect("Failed to add to Redis set");
+        con.expire::<&str, ()>(key, expiry as i64)
+            .expect("Failed to set Redis key expiry");
+    }
+
+    pub fn get_set_members(&self, key: &str) -> Vec<String> {
+        let mut con = self
+            .client
+            .get_connection()
+            .expect("Failed to open connection with redis");
+
+        con.smembers(key).unwrap_or_default()
+    }
+
+    pub fn remove_from_set(&self, key: &str, member: &str) {
+        let mut con = self
+            .client
+            .get_connection()
+            .expect("Failed to open connection with redis");
+
+        con.srem::<&str, &str, ()>(key, member)
+            .expect("Failed to remove from Redis set");
+    }
+
+    /// Get and delete a key, so only one caller can ever take its value.
+    pub fn take_key(&self, key: &str) -> Option<String> {
+        let mut con = self
+            .client
+            .get_connection()
+            .expect("Failed to open connection with redis");
+
+        con.get_del(key).ok()
+    }
+
+    /// Atomically append a value to a list and set a guard key, only if the guard
+    /// key exists, resetting the expiry of both. Returns whether it was appended.
+    pub async fn push_to_list_if_exists(
+        &self,
+        guard_key: &str,
+        guard_value: &str,
+        key: &str,
+        value: &[u8],
+        expiry: u64,
+    ) -> bool {
+        let mut con = self
+            .shared_connection()
+            .await
+            .expect("Failed to open connection with redis");
+
+        let result = Script::new(PUSH_IF_EXISTS_SCRIPT)
+            .key(guard_key)
+            .key(key)
+            .arg(guard_value)
+            .arg(value)
+            .arg(expiry)
+            .invoke_async::<bool>(&mut con)
+            .await;
+
+        self.check_shared(result)
+            .await
+            .expect("Failed to push to Redis list")
+    }
+
+    pub fn get_list(&self, key: &str) -> Vec<Vec<u8>> {
+        let mut con = self
+            .client
+            .get_connection()
+            .expect("Failed to open connection with redis");
+
+        con.lrange(key, 0, -1).unwrap_or_default()
+    }
+
+    pub fn get_list_len(&self, key: &str) -> u64 {
+        let mut con = self
+            .client
+            .get_connection()
+            .expect("Failed to open connection with redis");
+
+        con.llen(key).unwrap_or_default()
+    }
+
+    /// Publish a message to a channel, returning whether it was published.
+    pub async fn publish(&self, channel: &str, payload: &[u8]) -> bool {
+        let Ok(mut con) = self.shared_connection().await else {
+            return false;
+        };
+
+        let result = con.publish::<&str, &[u8], ()>(channel, payload).await;
+        self.check_shared(result).await.is_ok()
+    }
+
+    async fn shared_connection(&self) -> RedisResult<MultiplexedConnection> {
+        let mut shared = self.shared.lock().await;
+        if let Some(con) = shared.as_ref() {
+            return Ok(con.clone());
         }
+
+        let con = self.client.get_multiplexed_tokio_connection().await?;
+        *shared = Some(con.clone());
+
+        Ok(con)
+    }
+
+    /// Drop the shared connection if the command failed because it broke, so
+    /// the next command reopens it.
+    async fn check_shared<T>(&self, result: RedisResult<T>) -> RedisResult<T> {
+        if let Err(e) = &result {
+            if e.is_io_error() || e.is_connection_dropped() {
+                self.shared.lock().await.take();
+            }
+        }
+
+        result
     }
 }
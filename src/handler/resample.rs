@@ -0,0 +1,311 @@
+use thiserror::Error;
+
+use crate::cycling_tracker::{Aggregation, Measurement, MeasurementField, Workout};
+use crate::handler::cleaning::get;
+use crate::handler::summary::MeasurementStats;
+
+// Seconds without measurements after which the rider is taken to have stopped,
+// rather than the device recording less often
+const MAX_RESAMPLE_GAP: i64 = 30;
+
+// Resampled workouts are limited to two days of measurements
+const MAX_RESAMPLED_SECONDS: i64 = 2 * 86400;
+
+#[derive(Debug, Error)]
+pub enum ResampleError {
+    #[error("Measurements span more than {MAX_RESAMPLED_SECONDS} seconds")]
+    SpanTooLong,
+}
+
+/// Resample a workout whose measurements all have timestamps to one measurement
+/// per second, moving its laps along. Returns None if any measurement lacks a
+/// timestamp, as the measurements are then taken to be one second apart.
+pub fn resample_to_1hz(workout: &Workout) -> Result<Option<Workout>, ResampleError> {
+    let Some(timestamps) = workout
+        .measurements
+        .iter()
+        .map(|m| m.timestamp)
+        .collect::<Option<Vec<i64>>>()
+    else {
+        return Ok(None);
+    };
+    let Some(&first) = timestamps.first() else {
+        return Ok(None);
+    };
+
+    // Out of order measurements are left out
+    let mut samples: Vec<(i64, &Measurement)> = vec![];
+    for (&timestamp, measurement) in timestamps.iter().zip(workout.measurements.iter())
+    {
+        if samples.last().is_none_or(|&(last, _)| timestamp > last) {
+            samples.push((timestamp, measurement));
+        }
+    }
+    let last = samples.last().map_or(first, |&(last, _)| last);
+
+    let span = last
+        .checked_sub(first)
+        .map(|span| span / 1000)
+        .filter(|&span| span <= MAX_RESAMPLED_SECONDS)
+        .ok_or(ResampleError::SpanTooLong)?;
+
+    let mut measurements = vec![];
+    let mut next = 0;
+    for second in 0..=span {
+        let time = first + second * 1000;
+        while next + 1 < samples.len() && samples[next + 1].0 <= time {
+            next += 1;
+        }
+
+        let (before_time, before) = samples[next];
+        let measurement = match samples.get(next + 1) {
+            _ if before_time == time => before.clone(),
+            Some(&(after_time, after))
+                if after_time - before_time <= MAX_RESAMPLE_GAP * 1000 =>
+            {
+                let share =
+                    (time - before_time) as f32 / (after_time - before_time) as f32;
+                interpolate(before, after, share)
+            }
+            _ => Measurement::default(),
+        };
+
+        measurements.push(Measurement {
+            timestamp: None,
+            ..measurement
+        });
+    }
+
+    let laps = workout
+        .laps
+        .iter()
+        .filter_map(|&start| timestamps.get(start as usize))
+        .filter(|&&timestamp| (first..=last).contains(&timestamp))
+        .map(|timestamp| ((timestamp - first + 999) / 1000) as u32)
+        .collect();
+
+    Ok(Some(Workout {
+        measurements,
+        laps,
+        start_time: workout.start_time.or(Some(first.div_euclid(1000))),
+        ..workout.clone()
+    }))
+}
+
+/// A measurement between two others, interpolating linearly where both have a
+/// value, and taking the closest value otherwise.
+fn interpolate(before: &Measurement, after: &Measurement, share: f32) -> Measurement {
+    let closest = if share < 0.5 { before } else { after };
+
+    let lerp = |before: f32, after: f32| before + (after - before) * share;
+    let lerp_int =
+        |before: i32, after: i32| lerp(before as f32, after as f32).round() as i32;
+    let lerp_option = |before: Option<f32>,
+                       after: Option<f32>,
+                       closest: Option<f32>| match (before, after)
+    {
+        (Some(before), Some(after)) => Some(lerp(before, after)),
+        _ => closest,
+    };
+    let lerp_position = |before: Option<f64>,
+                         after: Option<f64>,
+                         closest: Option<f64>| match (
+        before, after,
+    ) {
+        (Some(before), Some(after)) => Some(before + (after - before) * share as f64),
+        _ => closest,
+    };
+
+    Measurement {
+        speed: lerp(before.speed, after.speed),
+        watts: lerp_int(before.watts, after.watts),
+        rpm: lerp_int(before.rpm, after.rpm),
+        heartrate: lerp_int(before.heartrate, after.heartrate),
+        left_right_balance: lerp_option(
+            before.left_right_balance,
+            after.left_right_balance,
+            closest.left_right_balance,
+        ),
+        left_torque_effectiveness: lerp_option(
+            before.left_torque_effectiveness,
+            after.left_torque_effectiveness,
+            closest.left_torque_effectiveness,
+        ),
+        right_torque_effectiveness: lerp_option(
+            before.right_torque_effectiveness,
+            after.right_torque_effectiveness,
+            closest.right_torque_effectiveness,
+        ),
+        left_pedal_smoothness: lerp_option(
+            before.left_pedal_smoothness,
+            after.left_pedal_smoothness,
+            closest.left_pedal_smoothness,
+        ),
+        right_pedal_smoothness: lerp_option(
+            before.right_pedal_smoothness,
+            after.right_pedal_smoothness,
+            closest.right_pedal_smoothness,
+        ),
+        left_watts: lerp_option(
+            before.left_watts.map(|w| w as f32),
+            after.left_watts.map(|w| w as f32),
+            closest.left_watts.map(|w| w as f32),
+        )
+        .map(|w| w.round() as i32),
+        right_watts: lerp_option(
+            before.right_watts.map(|w| w as f32),
+            after.right_watts.map(|w| w as f32),
+            closest.right_watts.map(|w| w as f32),
+        )
+        .map(|w| w.round() as i32),
+        channels: closest.channels.clone(),
+        latitude: lerp_position(before.latitude, after.latitude, closest.latitude),
+        longitude: lerp_position(before.longitude, after.longitude, closest.longitude),
+        altitude: lerp_option(before.altitude, after.altitude, closest.altitude),
+        timestamp: None,
+        index: None,
+    }
+}
+
+/// Reduce the measurements to at most max_points, setting the index each
+/// returned measurement starts at. Mean and max aggregate buckets of
+/// consecutive measurements, taking positions and custom channels from the
+/// first measurement of each bucket. LTTB picks the measurements that best keep
+/// the shape of the given field.
+pub fn downsample(
+    measurements: &[Measurement],
+    max_points: usize,
+    aggregation: Aggregation,
+    field: MeasurementField,
+) -> Vec<(usize, Measurement)> {
+    if measurements.len() <= max_points {
+        return measurements.iter().cloned().enumerate().collect();
+    }
+
+    match aggregation {
+        Aggregation::Unspecified | Aggregation::Mean => {
+            buckets(measurements, max_points)
+                .map(|(start, bucket)| (start, mean(bucket)))
+                .collect()
+        }
+        Aggregation::Max => buckets(measurements, max_points)
+            .map(|(start, bucket)| (start, max(bucket)))
+            .collect(),
+        Aggregation::Lttb => lttb(measurements, max_points, field)
+            .into_iter()
+            .map(|index| (index, measurements[index].clone()))
+            .collect(),
+    }
+}
+
+/// Split the measurements into count buckets of about the same size.
+fn buckets(
+    measurements: &[Measurement],
+    count: usize,
+) -> impl Iterator<Item = (usize, &[Measurement])> {
+    (0..count).map(move |bucket| {
+        let start = bucket * measurements.len() / count;
+        let end = (bucket + 1) * measurements.len() / count;
+
+        (start, &measurements[start..end])
+    })
+}
+
+fn mean(bucket: &[Measurement]) -> Measurement {
+    let mut stats = MeasurementStats::default();
+    for measurement in bucket {
+        stats.add(measurement);
+    }
+
+    with_position(stats.averages(), &bucket[0])
+}
+
+fn max(bucket: &[Measurement]) -> Measurement {
+    let max_f32 = |value: fn(&Measurement) -> Option<f32>| {
+        bucket.iter().filter_map(value).reduce(f32::max)
+    };
+    let max_i32 =
+        |value: fn(&Measurement) -> Option<i32>| bucket.iter().filter_map(value).max();
+
+    let maximums = Measurement {
+        speed: max_f32(|m| Some(m.speed)).unwrap_or_default(),
+        watts: max_i32(|m| Some(m.watts)).unwrap_or_default(),
+        rpm: max_i32(|m| Some(m.rpm)).unwrap_or_default(),
+        heartrate: max_i32(|m| Some(m.heartrate)).unwrap_or_default(),
+        left_right_balance: max_f32(|m| m.left_right_balance),
+        left_torque_effectiveness: max_f32(|m| m.left_torque_effectiveness),
+        right_torque_effectiveness: max_f32(|m| m.right_torque_effectiveness),
+        left_pedal_smoothness: max_f32(|m| m.left_pedal_smoothness),
+        right_pedal_smoothness: max_f32(|m| m.right_pedal_smoothness),
+        left_watts: max_i32(|m| m.left_watts),
+        right_watts: max_i32(|m| m.right_watts),
+        altitude: max_f32(|m| m.altitude),
+        ..Default::default()
+    };
+
+    with_position(maximums, &bucket[0])
+}
+
+fn with_position(aggregate: Measurement, first: &Measurement) -> Measurement {
+    Measurement {
+        channels: first.channels.clone(),
+        latitude: first.latitude,
+        longitude: first.longitude,
+        altitude: aggregate.altitude.or(first.altitude),
+        ..aggregate
+    }
+}
+
+/// Largest-Triangle-Three-Buckets: keep the first and last measurement, and
+/// from every bucket in between the one forming the largest triangle with the
+/// one kept before it and the average of the next bucket.
+fn lttb(
+    measurements: &[Measurement],
+    max_points: usize,
+    field: MeasurementField,
+) -> Vec<usize> {
+    let field = match field {
+        MeasurementField::Unspecified => MeasurementField::Watts,
+        field => field,
+    };
+    let value = |index: usize| get(&measurements[index], field) as f64;
+
+    let last = measurements.len() - 1;
+    if max_points < 3 {
+        return [0, last].into_iter().take(max_points).collect();
+    }
+
+    // The measurements between the first and last, in max_points - 2 buckets
+    let middle = last - 1;
+    let bucket_count = max_points - 2;
+    let bucket = |bucket: usize| {
+        (1 + bucket * middle / bucket_count)..(1 + (bucket + 1) * middle / bucket_count)
+    };
+
+    let mut selected = vec![0];
+    for index in 0..bucket_count {
+        let next = if index + 1 < bucket_count {
+            bucket(index + 1)
+        } else {
+            last..last + 1
+        };
+        let next_x = next.clone().map(|i| i as f64).sum::<f64>() / next.len() as f64;
+        let next_y = next.clone().map(value).sum::<f64>() / next.len() as f64;
+
+        let previous = *selected.last().unwrap();
+        let (previous_x, previous_y) = (previous as f64, value(previous));
+
+        let area = |i: usize| {
+            ((previous_x - next_x) * (value(i) - previous_y)
+                - (previous_x - i as f64) * (next_y - previous_y))
+                .abs()
+        };
+        let best = bucket(index)
+            .max_by(|&a, &b| area(a).total_cmp(&area(b)))
+            .unwrap();
+        selected.push(best);
+    }
+    selected.push(last);
+
+    selected
+}
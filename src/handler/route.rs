@@ -0,0 +1,380 @@
+use std::collections::VecDeque;
+
+use crate::cycling_tracker::{
+    BoundingBox, LiveWorkoutUpdate, Measurement, PrivacyZone, Route,
+};
+
+// Changes in altitude smaller than this are noise, rather than climbs or descents
+const ELEVATION_HYSTERESIS: f32 = 3.0;
+
+// Grades over shorter distances are mostly noise
+const MIN_GRADE_DISTANCE: f32 = 100.0;
+
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+// Meters hidden at the start and end of workouts of users with privacy zones
+const PRIVACY_TRIM: f64 = 200.0;
+
+/// Meters covered in a one second measurement, given its speed in km/h.
+pub(crate) fn distance_from_speed(measurement: &Measurement) -> f32 {
+    measurement.speed * 1000.0 / 3600.0
+}
+
+/// Great-circle distance in meters between two positions in degrees.
+pub(crate) fn haversine((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Latitude and longitude of a measurement, if it has a valid position.
+pub(crate) fn position(measurement: &Measurement) -> Option<(f64, f64)> {
+    let (latitude, longitude) = (measurement.latitude?, measurement.longitude?);
+
+    ((-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude))
+        .then_some((latitude, longitude))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum Trend {
+    #[default]
+    Flat,
+    Up,
+    Down,
+}
+
+/// Total ascent and descent, only counting climbs and descents once they
+/// exceed the hysteresis.
+#[derive(Clone, Copy, Debug, Default)]
+struct Elevation {
+    /// Altitude of the last turning point, or the first altitude
+    reference: Option<f32>,
+    /// Highest or lowest altitude of the ongoing climb or descent
+    extreme: f32,
+    trend: Trend,
+    ascent: f32,
+    descent: f32,
+}
+
+impl Elevation {
+    fn add(&mut self, altitude: f32) {
+        let Some(reference) = self.reference else {
+            self.reference = Some(altitude);
+            return;
+        };
+
+        match self.trend {
+            Trend::Up if altitude >= self.extreme => self.extreme = altitude,
+            Trend::Down if altitude <= self.extreme => self.extreme = altitude,
+            Trend::Up if self.extreme - altitude >= ELEVATION_HYSTERESIS => {
+                self.ascent += self.extreme - reference;
+                self.turn(Trend::Down, altitude);
+            }
+            Trend::Down if altitude - self.extreme >= ELEVATION_HYSTERESIS => {
+                self.descent += reference - self.extreme;
+                self.turn(Trend::Up, altitude);
+            }
+            Trend::Flat if altitude - reference >= ELEVATION_HYSTERESIS => {
+                self.trend = Trend::Up;
+                self.extreme = altitude;
+            }
+            Trend::Flat if reference - altitude >= ELEVATION_HYSTERESIS => {
+                self.trend = Trend::Down;
+                self.extreme = altitude;
+            }
+            _ => {}
+        }
+    }
+
+    /// Start a climb or descent at the extreme of the previous one.
+    fn turn(&mut self, trend: Trend, altitude: f32) {
+        self.reference = Some(self.extreme);
+        self.trend = trend;
+        self.extreme = altitude;
+    }
+
+    /// Ascent and descent, including the ongoing climb or descent
+    fn totals(&self) -> (f32, f32) {
+        let reference = self.reference.unwrap_or_default();
+
+        match self.trend {
+            Trend::Flat => (self.ascent, self.descent),
+            Trend::Up => (self.ascent + self.extreme - reference, self.descent),
+            Trend::Down => (self.ascent, self.descent + reference - self.extreme),
+        }
+    }
+}
+
+/// Elevation, grade and area of a ride, from the optional position and altitude
+/// of its measurements.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RouteStats {
+    elevation: Elevation,
+    has_altitude: bool,
+    /// Meters covered so far, from positions where available, otherwise speed
+    distance: f64,
+    last_position: Option<(f64, f64)>,
+    /// Distance and altitude of the points up to MIN_GRADE_DISTANCE back
+    grade_window: VecDeque<(f64, f32)>,
+    max_grade: Option<f32>,
+    bounding_box: Option<BoundingBox>,
+}
+
+impl RouteStats {
+    pub(crate) fn add(&mut self, measurement: &Measurement) {
+        let position = position(measurement);
+
+        self.distance += match (self.last_position, position) {
+            (Some(last), Some(position)) => haversine(last, position),
+            _ => distance_from_speed(measurement) as f64,
+        };
+        if position.is_some() {
+            self.last_position = position;
+        }
+
+        if let Some((latitude, longitude)) = position {
+            self.extend_bounding_box(latitude, longitude);
+        }
+
+        if let Some(altitude) = measurement.altitude.filter(|a| a.is_finite()) {
+            self.has_altitude = true;
+            self.elevation.add(altitude);
+            self.add_grade_point(altitude);
+        }
+    }
+
+    fn extend_bounding_box(&mut self, latitude: f64, longitude: f64) {
+        let bounding_box = self.bounding_box.get_or_insert(BoundingBox {
+            min_latitude: latitude,
+            min_longitude: longitude,
+            max_latitude: latitude,
+            max_longitude: longitude,
+        });
+
+        bounding_box.min_latitude = bounding_box.min_latitude.min(latitude);
+        bounding_box.min_longitude = bounding_box.min_longitude.min(longitude);
+        bounding_box.max_latitude = bounding_box.max_latitude.max(latitude);
+        bounding_box.max_longitude = bounding_box.max_longitude.max(longitude);
+    }
+
+    /// Update the max grade with the grade since the closest point at least
+    /// MIN_GRADE_DISTANCE back.
+    fn add_grade_point(&mut self, altitude: f32) {
+        self.grade_window.push_back((self.distance, altitude));
+
+        while self.grade_window.len() > 2
+            && self.distance - self.grade_window[1].0 >= MIN_GRADE_DISTANCE as f64
+        {
+            self.grade_window.pop_front();
+        }
+
+        let (start_distance, start_altitude) = self.grade_window[0];
+        let distance = (self.distance - start_distance) as f32;
+        if distance < MIN_GRADE_DISTANCE {
+            return;
+        }
+
+        let grade = (altitude - start_altitude) / distance * 100.0;
+        self.max_grade = Some(self.max_grade.map_or(grade, |max| max.max(grade)));
+    }
+
+    /// Total ascent and descent in meters, if any measurement had an altitude
+    pub(crate) fn elevation(&self) -> Option<(f32, f32)> {
+        self.has_altitude.then(|| self.elevation.totals())
+    }
+
+    /// Steepest grade in percent, if the ride was long enough to tell
+    pub(crate) fn max_grade(&self) -> Option<f32> {
+        self.max_grade
+    }
+
+    pub(crate) fn bounding_box(&self) -> Option<BoundingBox> {
+        self.bounding_box.clone()
+    }
+}
+
+/// The route of a workout, leaving out measurements without a valid position.
+pub fn route(measurements: &[Measurement]) -> Route {
+    let positions: Vec<(f64, f64)> = measurements.iter().filter_map(position).collect();
+
+    let mut stats = RouteStats::default();
+    for &(latitude, longitude) in positions.iter() {
+        stats.extend_bounding_box(latitude, longitude);
+    }
+
+    Route {
+        polyline: encode_polyline(&positions),
+        point_count: positions.len() as u32,
+        bounding_box: stats.bounding_box(),
+    }
+}
+
+/// Hides the positions of a user's workouts inside their privacy zones, and at
+/// the start and end of workouts, from everyone else.
+#[derive(Clone, Debug)]
+pub struct PrivacyMask {
+    zones: Vec<PrivacyZone>,
+    /// Meters covered so far by the current live workout
+    live_distance: f64,
+    last_position: Option<(f64, f64)>,
+    /// Live updates held back until PRIVACY_TRIM meters were covered after them,
+    /// with the distance at which they were received
+    pending: VecDeque<(f64, LiveWorkoutUpdate)>,
+}
+
+impl PrivacyMask {
+    /// Return a mask for the given zones, or None if there's nothing to hide.
+    pub fn new(zones: Vec<PrivacyZone>) -> Option<Self> {
+        (!zones.is_empty()).then_some(Self {
+            zones,
+            live_distance: 0.0,
+            last_position: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Mask a stored workout, hiding its first and last PRIVACY_TRIM meters.
+    pub fn mask_workout(&self, measurements: &mut [Measurement]) {
+        trim(measurements.iter_mut());
+        trim(measurements.iter_mut().rev());
+
+        for measurement in measurements.iter_mut() {
+            self.mask_zones(measurement);
+        }
+    }
+
+    /// Mask the next update of a live workout, returning the updates that can
+    /// be sent on. The end of the workout isn't known yet, so updates are held
+    /// back until PRIVACY_TRIM meters later, and the positions of those still
+    /// held back when the workout finishes are hidden.
+    pub fn mask_live(
+        &mut self,
+        mut update: LiveWorkoutUpdate,
+    ) -> Vec<LiveWorkoutUpdate> {
+        if update.finished {
+            let mut released: Vec<LiveWorkoutUpdate> = self
+                .pending
+                .drain(..)
+                .map(|(_, mut update)| {
+                    if let Some(measurement) = update.measurement.as_mut() {
+                        hide_position(measurement);
+                    }
+                    update
+                })
+                .collect();
+            released.push(update);
+
+            // The next workout on the same stream starts a new trim
+            self.live_distance = 0.0;
+            self.last_position = None;
+            return released;
+        }
+
+        if let Some(measurement) = update.measurement.as_mut() {
+            self.add_live_distance(measurement);
+            if self.live_distance < PRIVACY_TRIM {
+                hide_position(measurement);
+            }
+            self.mask_zones(measurement);
+        }
+        self.pending.push_back((self.live_distance, update));
+
+        let mut released = Vec::new();
+        while let Some((distance, _)) = self.pending.front() {
+            if self.live_distance - distance < PRIVACY_TRIM {
+                break;
+            }
+            released.extend(self.pending.pop_front().map(|(_, update)| update));
+        }
+        released
+    }
+
+    /// Add the meters covered since the last measurement, from positions where
+    /// available, otherwise speed.
+    fn add_live_distance(&mut self, measurement: &Measurement) {
+        let position = position(measurement);
+
+        self.live_distance += match (self.last_position, position) {
+            (Some(last), Some(position)) => haversine(last, position),
+            _ => distance_from_speed(measurement) as f64,
+        };
+        if position.is_some() {
+            self.last_position = position;
+        }
+    }
+
+    /// Hide the position if it's inside a zone or invalid.
+    fn mask_zones(&self, measurement: &mut Measurement) {
+        let hidden = position(measurement).is_none_or(|position| {
+            self.zones.iter().any(|zone| {
+                haversine(position, (zone.latitude, zone.longitude))
+                    <= zone.radius as f64
+            })
+        });
+
+        if hidden {
+            hide_position(measurement);
+        }
+    }
+}
+
+/// Hide positions until PRIVACY_TRIM meters were covered.
+fn trim<'a>(measurements: impl Iterator<Item = &'a mut Measurement>) {
+    let mut distance = 0.0;
+    let mut last_position = None;
+
+    for measurement in measurements {
+        let Some(position) = position(measurement) else {
+            continue;
+        };
+
+        if let Some(last) = last_position {
+            distance += haversine(last, position);
+        }
+        last_position = Some(position);
+
+        if distance >= PRIVACY_TRIM {
+            return;
+        }
+        hide_position(measurement);
+    }
+}
+
+fn hide_position(measurement: &mut Measurement) {
+    measurement.latitude = None;
+    measurement.longitude = None;
+}
+
+/// Encode positions in degrees with the encoded polyline algorithm, at five
+/// decimals of precision.
+pub fn encode_polyline(positions: &[(f64, f64)]) -> String {
+    let mut polyline = String::new();
+    let mut last = (0, 0);
+
+    for &(latitude, longitude) in positions {
+        let point = (
+            (latitude * 1e5).round() as i64,
+            (longitude * 1e5).round() as i64,
+        );
+        encode_value(point.0 - last.0, &mut polyline);
+        encode_value(point.1 - last.1, &mut polyline);
+        last = point;
+    }
+
+    polyline
+}
+
+fn encode_value(value: i64, polyline: &mut String) {
+    let mut value = if value < 0 { !(value << 1) } else { value << 1 };
+
+    while value >= 0x20 {
+        polyline.push(char::from((((value & 0x1f) | 0x20) + 63) as u8));
+        value >>= 5;
+    }
+    polyline.push(char::from((value + 63) as u8));
+}
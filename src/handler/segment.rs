@@ -0,0 +1,248 @@
+use tonic::Status;
+use tracing::info;
+
+use crate::cycling_tracker::{
+    BoundingBox, CreateSegmentRequest, Measurement, Segment, SegmentEffort,
+    SegmentLeaderboard,
+};
+use crate::handler::route::{haversine, position, route, PrivacyMask};
+use crate::handler::SQLiteHandler;
+
+// Meters within which a position passes a point of a segment, allowing for
+// GPS inaccuracy
+const SEGMENT_TOLERANCE: f64 = 25.0;
+
+// Meters away from the segment after which a rider left it
+const MAX_DEVIATION: f64 = 50.0;
+
+#[derive(Clone)]
+pub struct SegmentHandler {
+    pub sqlite_handler: SQLiteHandler,
+}
+
+impl SegmentHandler {
+    /// Define a segment from a workout of the user, which counts as its first
+    /// effort.
+    pub async fn create(
+        &self,
+        username: &str,
+        request: CreateSegmentRequest,
+    ) -> Result<Segment, Status> {
+        if request.name.is_empty() {
+            return Err(Status::invalid_argument("Segment name can't be empty"));
+        }
+
+        let measurements = match self
+            .sqlite_handler
+            .get_workout_owner(request.workout_id)
+            .await
+        {
+            Some(owner) if owner == username => self
+                .sqlite_handler
+                .get_measurements(request.workout_id)
+                .await
+                .unwrap_or_default(),
+            _ => return Err(Status::not_found("Workout not found")),
+        };
+
+        let (start, end) = (request.start_index as usize, request.end_index as usize);
+        if start >= end || end >= measurements.len() {
+            return Err(Status::invalid_argument("Invalid segment range"));
+        }
+
+        // Segments are public, so they can't reveal what the user hides
+        let mut masked = measurements.clone();
+        if let Some(mask) =
+            PrivacyMask::new(self.sqlite_handler.get_privacy_zones(username).await)
+        {
+            mask.mask_workout(&mut masked);
+        }
+        let segment_measurements = &measurements[start..=end];
+        if masked[start..=end] != *segment_measurements {
+            return Err(Status::invalid_argument("Segment overlaps a privacy zone"));
+        }
+
+        let points: Vec<(f64, f64)> =
+            segment_measurements.iter().filter_map(position).collect();
+        if points.len() < 2 {
+            return Err(Status::invalid_argument("Segment has no route"));
+        }
+
+        let route = route(segment_measurements);
+        let mut segment = Segment {
+            id: 0,
+            name: request.name,
+            distance: points
+                .windows(2)
+                .map(|p| haversine(p[0], p[1]))
+                .sum::<f64>() as f32,
+            polyline: route.polyline,
+            bounding_box: route.bounding_box,
+        };
+
+        segment.id = self
+            .sqlite_handler
+            .save_segment(username, &segment, &points)
+            .await
+            .ok_or(Status::internal("Failed to save segment"))?;
+
+        let effort = effort(username, request.workout_id, &measurements, start, end);
+        self.sqlite_handler
+            .save_segment_effort(segment.id, &effort)
+            .await;
+
+        Ok(segment)
+    }
+
+    pub async fn leaderboard(&self, segment_id: i32) -> Option<SegmentLeaderboard> {
+        let segment = self.sqlite_handler.get_segment(segment_id).await?;
+
+        Some(SegmentLeaderboard {
+            segment: Some(segment),
+            efforts: self
+                .sqlite_handler
+                .get_segment_leaderboard(segment_id)
+                .await,
+        })
+    }
+
+    /// Record the efforts of a saved workout on the segments it overlaps.
+    pub async fn match_workout(
+        &self,
+        workout_id: i32,
+        username: &str,
+        measurements: &[Measurement],
+        bounding_box: &BoundingBox,
+    ) {
+        // Efforts are public, so positions the user hides can't match
+        let mut measurements = measurements.to_vec();
+        if let Some(mask) =
+            PrivacyMask::new(self.sqlite_handler.get_privacy_zones(username).await)
+        {
+            mask.mask_workout(&mut measurements);
+        }
+
+        for segment_id in self
+            .sqlite_handler
+            .get_overlapping_segments(bounding_box)
+            .await
+        {
+            let points = self.sqlite_handler.get_segment_points(segment_id).await;
+
+            for (start, end) in match_segment(&points, &measurements) {
+                let effort = effort(username, workout_id, &measurements, start, end);
+                info!(
+                    "Workout {:?} matched segment {:?} in {:?}s",
+                    workout_id, segment_id, effort.elapsed_time
+                );
+
+                self.sqlite_handler
+                    .save_segment_effort(segment_id, &effort)
+                    .await;
+            }
+        }
+    }
+}
+
+fn effort(
+    username: &str,
+    workout_id: i32,
+    measurements: &[Measurement],
+    start: usize,
+    end: usize,
+) -> SegmentEffort {
+    let watts: i64 = measurements[start..=end]
+        .iter()
+        .map(|m| m.watts as i64)
+        .sum();
+
+    SegmentEffort {
+        username: username.to_string(),
+        workout_id,
+        start_index: start as u32,
+        elapsed_time: (end - start) as u32,
+        avg_watts: (watts / (end - start + 1) as i64) as i32,
+    }
+}
+
+fn is_near(measurement: &Measurement, point: (f64, f64)) -> bool {
+    position(measurement).is_some_and(|p| haversine(p, point) <= SEGMENT_TOLERANCE)
+}
+
+/// Index of the measurement closest to the point, among the first run of
+/// measurements near it from the given index on.
+fn closest(
+    measurements: &[Measurement],
+    point: (f64, f64),
+    from: usize,
+) -> Option<usize> {
+    let first =
+        (from..measurements.len()).find(|&i| is_near(&measurements[i], point))?;
+
+    (first..measurements.len())
+        .take_while(|&i| is_near(&measurements[i], point))
+        .min_by(|&a, &b| {
+            let distance =
+                |i: usize| haversine(position(&measurements[i]).unwrap(), point);
+            distance(a).total_cmp(&distance(b))
+        })
+}
+
+/// Return the start and end indices of every pass of the measurements over the
+/// segment's points, in order, without leaving it.
+pub fn match_segment(
+    points: &[(f64, f64)],
+    measurements: &[Measurement],
+) -> Vec<(usize, usize)> {
+    let mut efforts = vec![];
+    let (Some(&first), Some(&last)) = (points.first(), points.last()) else {
+        return efforts;
+    };
+
+    let mut from = 0;
+    while let Some(start) = closest(measurements, first, from) {
+        match follow(points, measurements, start) {
+            Some(end) => {
+                let end = closest(measurements, last, end).unwrap_or(end);
+                efforts.push((start, end));
+                from = end + 1;
+            }
+            None => from = start + 1,
+        }
+    }
+
+    efforts
+}
+
+/// Follow the segment from the given measurement, returning the index of the
+/// first measurement near its last point, or None if the rider left it.
+fn follow(
+    points: &[(f64, f64)],
+    measurements: &[Measurement],
+    start: usize,
+) -> Option<usize> {
+    let mut next = 1;
+
+    for (index, measurement) in measurements.iter().enumerate().skip(start) {
+        let Some(position) = position(measurement) else {
+            continue;
+        };
+
+        while next < points.len()
+            && haversine(position, points[next]) <= SEGMENT_TOLERANCE
+        {
+            next += 1;
+        }
+        if next == points.len() {
+            return Some(index);
+        }
+
+        if haversine(position, points[next - 1]) > MAX_DEVIATION
+            && haversine(position, points[next]) > MAX_DEVIATION
+        {
+            return None;
+        }
+    }
+
+    None
+}
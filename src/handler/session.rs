@@ -1,19 +1,42 @@
+use std::future::Future;
+
 use tonic::{Request, Status};
 
-use crate::handler::RedisHandler;
+use crate::handler::api_key::{Scope, API_KEY_PREFIX};
+use crate::handler::user::{hash_secret, verify_secret};
+use crate::handler::{ApiKeyHandler, RedisHandler};
+
+// Password reset tokens expire in 15 minutes
+const PASSWORD_RESET_EXPIRY: u64 = 900;
+
+// Session tokens expire in 5 minutes
+const SESSION_EXPIRY: u64 = 300;
+
+// Second factor challenges expire in 5 minutes
+const CHALLENGE_EXPIRY: u64 = 300;
 
 #[derive(Clone)]
 pub struct SessionHandler {
     pub redis_handler: RedisHandler,
+    pub api_key_handler: ApiKeyHandler,
 }
 
 impl SessionHandler {
     pub fn start(&self, user_name: String) -> String {
         let session_token = uuid7::uuid7().to_string();
 
-        // Session token expires in 5 minutes
-        self.redis_handler
-            .set_key(&session_token, &user_name, Some(300));
+        self.redis_handler.set_key(
+            &session_key(&session_token),
+            &user_name,
+            Some(SESSION_EXPIRY),
+        );
+
+        // Keep track of the user's sessions, so they can all be ended at once
+        self.redis_handler.add_to_set(
+            &sessions_key(&user_name),
+            &session_token,
+            SESSION_EXPIRY,
+        );
 
         println!(
             "Created session token {:?} for user {:?}",
@@ -23,27 +46,125 @@ impl SessionHandler {
         session_token
     }
 
+    /// End all sessions of the user.
+    pub fn end_all(&self, user_name: &str) {
+        let key = sessions_key(user_name);
+
+        for session_token in self.redis_handler.get_set_members(&key) {
+            self.redis_handler.delete_key(&session_key(&session_token));
+        }
+        self.redis_handler.delete_key(&key);
+    }
+
+    /// Create a challenge token for a user who still has to provide a second
+    /// factor. Challenge tokens are not valid session tokens.
+    pub fn start_challenge(&self, user_name: &str) -> String {
+        let challenge_token = uuid7::uuid4().to_string();
+
+        self.redis_handler.set_key(
+            &challenge_key(&challenge_token),
+            &user_name.to_string(),
+            Some(CHALLENGE_EXPIRY),
+        );
+
+        challenge_token
+    }
+
+    /// Return the user a challenge token was issued for.
+    pub fn get_challenge(&self, challenge_token: &str) -> Option<String> {
+        self.redis_handler.get_key(&challenge_key(challenge_token))
+    }
+
+    pub fn cancel_challenge(&self, challenge_token: &str) {
+        self.redis_handler
+            .delete_key(&challenge_key(challenge_token));
+    }
+
+    /// Invalidate a challenge token and start a full session for its user.
+    pub fn complete_challenge(&self, challenge_token: &str) -> Option<String> {
+        let user_name = self.get_challenge(challenge_token)?;
+        self.cancel_challenge(challenge_token);
+
+        Some(self.start(user_name))
+    }
+
+    /// Create a one-time password reset token for the user. Only its hash is stored,
+    /// replacing any reset previously requested for the same user.
+    pub fn start_password_reset(&self, user_name: &str) -> String {
+        let reset_token = uuid7::uuid4().to_string();
+
+        self.redis_handler.set_key(
+            &password_reset_key(user_name),
+            &hash_secret(&reset_token),
+            Some(PASSWORD_RESET_EXPIRY),
+        );
+
+        reset_token
+    }
+
+    /// Check a password reset token. The reset is consumed by any attempt, so
+    /// a token can neither be used twice nor guessed more than once.
+    pub fn consume_password_reset(&self, user_name: &str, reset_token: &str) -> bool {
+        self.redis_handler
+            .take_key(&password_reset_key(user_name))
+            .is_some_and(|token_hash| verify_secret(reset_token, &token_hash))
+    }
+
+    /// Return the user authenticated by the request's session token or API key.
+    /// API keys are only accepted if they were granted the scope.
+    // The request is only borrowed before the returned future starts, since
+    // streaming requests can't be shared across threads.
     pub fn verify_session_token<RT>(
         &self,
         request: &Request<RT>,
-    ) -> Result<String, Status> {
+        scope: Scope,
+    ) -> impl Future<Output = Result<String, Status>> + Send + '_ {
         let session_token = request
             .metadata()
             .get("Authorization")
-            .ok_or(Status::unauthenticated("Session token not provided"))?
-            .to_str()
-            .unwrap();
+            .ok_or(Status::unauthenticated("Session token not provided"))
+            .map(|token| token.to_str().unwrap_or_default().to_string());
 
-        let user_name = self
-            .redis_handler
-            .get_key(session_token)
-            .ok_or(Status::unauthenticated("Invalid session token"))?;
+        async move {
+            let session_token = session_token?;
 
-        println!(
-            "Session token {:?} correlates to user {:?}",
-            &session_token, &user_name
-        );
+            if session_token.starts_with(API_KEY_PREFIX) {
+                return self
+                    .api_key_handler
+                    .verify(&session_token, scope)
+                    .await
+                    .ok_or(Status::permission_denied("Invalid API key for this call"));
+            }
+
+            let user_name = self
+                .redis_handler
+                .get_key(&session_key(&session_token))
+                .ok_or(Status::unauthenticated("Invalid session token"))?;
+
+            println!(
+                "Session token {:?} correlates to user {:?}",
+                &session_token, &user_name
+            );
 
-        Ok(user_name)
+            Ok(user_name)
+        }
     }
 }
+
+// Every kind of token gets its own key prefix, so a token of one kind can't be
+// passed off as another
+fn session_key(session_token: &str) -> String {
+    format!("session:{session_token}")
+}
+
+fn sessions_key(user_name: &str) -> String {
+    format!("sessions:{user_name}")
+}
+
+fn challenge_key(challenge_token: &str) -> String {
+    format!("challenge:{challenge_token}")
+}
+
+fn password_reset_key(user_name: &str) -> String {
+    format!("password-reset:{user_name}")
+}
@@ -0,0 +1,125 @@
+use tonic::Status;
+
+use crate::cycling_tracker::{Role, Share, SharePermission};
+use crate::handler::route::PrivacyMask;
+use crate::handler::SQLiteHandler;
+
+#[derive(Clone)]
+pub struct SharingHandler {
+    pub sqlite_handler: SQLiteHandler,
+}
+
+impl SharingHandler {
+    pub async fn invite(
+        &self,
+        athlete: &str,
+        coach: &str,
+        permission: SharePermission,
+    ) -> Result<(), Status> {
+        if athlete == coach {
+            return Err(Status::invalid_argument(
+                "Can't share workouts with yourself",
+            ));
+        }
+
+        match self.sqlite_handler.get_user(coach).await {
+            Some(user) if user.role() == Role::Coach => {}
+            _ => return Err(Status::not_found("Coach not found")),
+        }
+
+        if !self
+            .sqlite_handler
+            .save_share(athlete, coach, permission)
+            .await
+        {
+            return Err(Status::internal("Failed to save invitation"));
+        }
+
+        Ok(())
+    }
+
+    pub async fn accept(&self, athlete: &str, coach: &str) -> bool {
+        self.sqlite_handler.accept_share(athlete, coach).await
+    }
+
+    /// Return invitations sent to the coach, which are yet to be accepted.
+    pub async fn invitations(&self, coach: &str) -> Vec<Share> {
+        self.sqlite_handler
+            .get_shares(coach, false)
+            .await
+            .into_iter()
+            .filter(|share| share.coach == coach)
+            .collect()
+    }
+
+    /// Return accepted grants, both given and received by the user.
+    pub async fn shares(&self, username: &str) -> Vec<Share> {
+        self.sqlite_handler.get_shares(username, true).await
+    }
+
+    /// Remove the grant between two users, regardless of who's the athlete.
+    pub async fn revoke(&self, username: &str, other: &str) -> bool {
+        let as_athlete = self.sqlite_handler.delete_share(username, other).await;
+        let as_coach = self.sqlite_handler.delete_share(other, username).await;
+
+        as_athlete || as_coach
+    }
+
+    /// Return whose data the caller is accessing: their own if no athlete is given,
+    /// otherwise the athlete's, given they granted the required permission.
+    /// Write permission also grants read access.
+    pub async fn authorize(
+        &self,
+        caller: String,
+        athlete: Option<String>,
+        permission: SharePermission,
+    ) -> Result<String, Status> {
+        let athlete = match athlete {
+            Some(athlete) if athlete != caller => athlete,
+            _ => return Ok(caller),
+        };
+
+        let granted = self
+            .sqlite_handler
+            .get_share_permission(&athlete, &caller)
+            .await;
+
+        match (granted, permission) {
+            (Some(SharePermission::Write), _)
+            | (Some(SharePermission::Read), SharePermission::Read) => Ok(athlete),
+            _ => Err(Status::permission_denied(
+                "No access to this athlete's workouts",
+            )),
+        }
+    }
+
+    /// Return the mask hiding the athlete's positions from the caller, or None if
+    /// the caller may see them.
+    pub async fn privacy_mask(
+        &self,
+        caller: &str,
+        athlete: &str,
+    ) -> Option<PrivacyMask> {
+        if caller == athlete {
+            return None;
+        }
+
+        PrivacyMask::new(self.sqlite_handler.get_privacy_zones(athlete).await)
+    }
+}
+
+impl SharePermission {
+    pub fn as_db_str(&self) -> &'static str {
+        match self {
+            SharePermission::Read => "read",
+            SharePermission::Write => "write",
+        }
+    }
+
+    pub fn from_db_str(permission: &str) -> Self {
+        match permission {
+            "write" => SharePermission::Write,
+            _ => SharePermission::Read,
+        }
+    }
+}
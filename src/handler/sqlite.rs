@@ -1,7 +1,14 @@
 use sqlx::SqlitePool;
 use thiserror::Error;
 
-use crate::cycling_tracker::{Measurement, WorkoutSummary};
+use crate::cycling_tracker::{
+    plan_step::{Power, PowerEnd},
+    AerobicPoint, ApiKey, BoundingBox, ChannelValue, MeanMaxPower, Measurement,
+    PlanStep, PrivacyZone, Role, Segment, SegmentEffort, ServerStats, Sex, Share,
+    SharePermission, TargetRange, User, UserProfile, WeekTotal, WorkoutPlan,
+    WorkoutSummary,
+};
+use crate::handler::analysis::AerobicMetrics;
 
 #[derive(Clone)]
 pub struct SQLiteHandler {
@@ -10,9 +17,13 @@ pub struct SQLiteHandler {
 
 impl SQLiteHandler {
     pub async fn create_user(&self, username: String, password: String) -> bool {
-        match sqlx::query!("INSERT INTO USER VALUES ($1, $2)", username, password)
-            .execute(&self.db)
-            .await
+        match sqlx::query!(
+            "INSERT INTO USER (username, password) VALUES ($1, $2)",
+            username,
+            password
+        )
+        .execute(&self.db)
+        .await
         {
             Ok(_) => true,
             Err(e) => {
@@ -32,28 +43,539 @@ impl SQLiteHandler {
         }
     }
 
-    pub async fn save_workout(&self, summary: &WorkoutSummary) -> i32 {
+    pub async fn update_password(&self, username: String, password: String) -> bool {
+        match sqlx::query!(
+            "UPDATE USER SET password = $1 WHERE username = $2",
+            password,
+            username
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => result.rows_affected() == 1,
+            Err(e) => {
+                println!("Failed to update password: {:?}", e);
+                false
+            }
+        }
+    }
+
+    pub async fn get_user(&self, username: &str) -> Option<User> {
+        match sqlx::query!(
+            "SELECT username, role, disabled FROM USER WHERE username = $1",
+            username
+        )
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(record) => Some(User {
+                username: record.username,
+                role: Role::from_db_str(&record.role).into(),
+                disabled: record.disabled,
+            }),
+            Err(_) => None,
+        }
+    }
+
+    pub async fn list_users(&self) -> Vec<User> {
+        sqlx::query!("SELECT username, role, disabled FROM USER ORDER BY username")
+            .fetch_all(&self.db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| User {
+                username: r.username,
+                role: Role::from_db_str(&r.role).into(),
+                disabled: r.disabled,
+            })
+            .collect()
+    }
+
+    pub async fn set_user_disabled(&self, username: &str, disabled: bool) -> bool {
+        match sqlx::query!(
+            "UPDATE USER SET disabled = $1 WHERE username = $2",
+            disabled,
+            username
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => result.rows_affected() == 1,
+            Err(_) => false,
+        }
+    }
+
+    pub async fn set_user_role(&self, username: &str, role: Role) -> bool {
+        let role = role.as_db_str();
+        match sqlx::query!(
+            "UPDATE USER SET role = $1 WHERE username = $2",
+            role,
+            username
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => result.rows_affected() == 1,
+            Err(_) => false,
+        }
+    }
+
+    pub async fn get_server_stats(&self) -> ServerStats {
+        let record = sqlx::query!(
+            r#"SELECT
+                (SELECT COUNT(*) FROM USER) AS "user_count!: i64",
+                (SELECT COUNT(*) FROM WORKOUT_SUMMARY) AS "workout_count!: i64",
+                (SELECT COUNT(*) FROM MEASUREMENTS) AS "measurement_count!: i64",
+                (SELECT page_count * page_size
+                    FROM pragma_page_count(), pragma_page_size()
+                ) AS "storage_used!: i64""#
+        )
+        .fetch_one(&self.db)
+        .await
+        .unwrap();
+
+        ServerStats {
+            user_count: record.user_count,
+            workout_count: record.workout_count,
+            measurement_count: record.measurement_count,
+            storage_used: record.storage_used,
+        }
+    }
+
+    /// Store a (not yet enabled) TOTP secret, replacing any previous secret and
+    /// recovery codes of the user.
+    pub async fn save_totp_enrollment(
+        &self,
+        username: String,
+        secret: String,
+        recovery_codes: Vec<String>,
+    ) -> bool {
+        let mut tx = self.db.begin().await.unwrap();
+
         let result = sqlx::query!(
-            "INSERT INTO WORKOUT_SUMMARY VALUES (Null, $1, $2, $3, $4, $5)",
+            "INSERT OR REPLACE INTO USER_TOTP (username, secret, enabled)
+            VALUES ($1, $2, FALSE)",
+            username,
+            secret
+        )
+        .execute(&mut *tx)
+        .await;
+
+        if let Err(e) = result {
+            println!("Failed to save TOTP secret: {:?}", e);
+            return false;
+        }
+
+        sqlx::query!(
+            "DELETE FROM TOTP_RECOVERY_CODE WHERE username = $1",
+            username
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        for code in recovery_codes {
+            sqlx::query!(
+                "INSERT INTO TOTP_RECOVERY_CODE VALUES ($1, $2)",
+                username,
+                code
+            )
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        }
+
+        tx.commit().await.is_ok()
+    }
+
+    /// Return the TOTP secret of the user, and whether it's enabled.
+    pub async fn get_totp_secret(&self, username: &str) -> Option<(String, bool)> {
+        match sqlx::query!(
+            "SELECT secret, enabled FROM USER_TOTP WHERE username = $1",
+            username
+        )
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(record) => Some((record.secret, record.enabled)),
+            Err(_) => None,
+        }
+    }
+
+    /// Record the time step of an accepted TOTP code, returning false if a code
+    /// of the same or a later step was accepted before.
+    pub async fn use_totp_step(&self, username: &str, step: i64) -> bool {
+        match sqlx::query!(
+            "UPDATE USER_TOTP SET last_step = $1
+            WHERE username = $2 AND (last_step IS NULL OR last_step < $1)",
+            step,
+            username
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => result.rows_affected() == 1,
+            Err(_) => false,
+        }
+    }
+
+    pub async fn enable_totp(&self, username: &str) -> bool {
+        match sqlx::query!(
+            "UPDATE USER_TOTP SET enabled = TRUE WHERE username = $1",
+            username
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => result.rows_affected() == 1,
+            Err(_) => false,
+        }
+    }
+
+    pub async fn get_recovery_codes(&self, username: &str) -> Vec<String> {
+        sqlx::query!(
+            "SELECT code FROM TOTP_RECOVERY_CODE WHERE username = $1",
+            username
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.code)
+        .collect()
+    }
+
+    pub async fn delete_recovery_code(&self, username: &str, code: &str) -> bool {
+        match sqlx::query!(
+            "DELETE FROM TOTP_RECOVERY_CODE WHERE username = $1 AND code = $2",
+            username,
+            code
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => result.rows_affected() == 1,
+            Err(_) => false,
+        }
+    }
+
+    pub async fn save_api_key(
+        &self,
+        username: &str,
+        api_key: &ApiKey,
+        key_hash: String,
+    ) -> bool {
+        let scopes = api_key.scopes.join(",");
+        match sqlx::query!(
+            "INSERT INTO API_KEY VALUES ($1, $2, $3, $4, $5, $6)",
+            api_key.id,
+            username,
+            api_key.name,
+            key_hash,
+            scopes,
+            api_key.created_at,
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Failed to save API key: {:?}", e);
+                false
+            }
+        }
+    }
+
+    pub async fn get_api_keys(&self, username: &str) -> Vec<ApiKey> {
+        sqlx::query!(
+            "SELECT id, name, scopes, created_at FROM API_KEY WHERE username = $1
+            ORDER BY created_at",
+            username
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| ApiKey {
+            id: r.id,
+            name: r.name,
+            scopes: r.scopes.split(',').map(str::to_string).collect(),
+            created_at: r.created_at,
+        })
+        .collect()
+    }
+
+    /// Return the owner and scopes of the API key with the given hash.
+    pub async fn get_api_key_owner(
+        &self,
+        key_hash: &str,
+    ) -> Option<(String, Vec<String>)> {
+        match sqlx::query!(
+            "SELECT API_KEY.username, scopes FROM API_KEY
+            JOIN USER ON USER.username = API_KEY.username
+            WHERE key_hash = $1 AND NOT USER.disabled",
+            key_hash
+        )
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(record) => Some((
+                record.username,
+                record.scopes.split(',').map(str::to_string).collect(),
+            )),
+            Err(_) => None,
+        }
+    }
+
+    pub async fn delete_api_key(&self, username: &str, id: &str) -> bool {
+        match sqlx::query!(
+            "DELETE FROM API_KEY WHERE username = $1 AND id = $2",
+            username,
+            id
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => result.rows_affected() == 1,
+            Err(_) => false,
+        }
+    }
+
+    /// Create an invitation, or update the permission of an existing grant.
+    pub async fn save_share(
+        &self,
+        athlete: &str,
+        coach: &str,
+        permission: SharePermission,
+    ) -> bool {
+        let permission = permission.as_db_str();
+        match sqlx::query!(
+            "INSERT INTO WORKOUT_SHARE (athlete, coach, permission) VALUES ($1, $2, $3)
+            ON CONFLICT (athlete, coach) DO UPDATE SET permission = excluded.permission",
+            athlete,
+            coach,
+            permission
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Failed to save share: {:?}", e);
+                false
+            }
+        }
+    }
+
+    pub async fn accept_share(&self, athlete: &str, coach: &str) -> bool {
+        match sqlx::query!(
+            "UPDATE WORKOUT_SHARE SET accepted = TRUE WHERE athlete = $1 AND coach = $2",
+            athlete,
+            coach
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => result.rows_affected() == 1,
+            Err(_) => false,
+        }
+    }
+
+    /// Return shares where the user is either the athlete or the coach.
+    pub async fn get_shares(&self, username: &str, accepted: bool) -> Vec<Share> {
+        sqlx::query!(
+            "SELECT athlete, coach, permission, accepted FROM WORKOUT_SHARE
+            WHERE (athlete = $1 OR coach = $1) AND accepted = $2
+            ORDER BY athlete, coach",
+            username,
+            accepted
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| Share {
+            athlete: r.athlete,
+            coach: r.coach,
+            permission: SharePermission::from_db_str(&r.permission).into(),
+            accepted: r.accepted,
+        })
+        .collect()
+    }
+
+    /// Return the permission an athlete granted to a coach, if accepted.
+    pub async fn get_share_permission(
+        &self,
+        athlete: &str,
+        coach: &str,
+    ) -> Option<SharePermission> {
+        sqlx::query!(
+            "SELECT permission FROM WORKOUT_SHARE
+            WHERE athlete = $1 AND coach = $2 AND accepted",
+            athlete,
+            coach
+        )
+        .fetch_optional(&self.db)
+        .await
+        .unwrap()
+        .map(|r| SharePermission::from_db_str(&r.permission))
+    }
+
+    pub async fn delete_share(&self, athlete: &str, coach: &str) -> bool {
+        match sqlx::query!(
+            "DELETE FROM WORKOUT_SHARE WHERE athlete = $1 AND coach = $2",
+            athlete,
+            coach
+        )
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => result.rows_affected() == 1,
+            Err(_) => false,
+        }
+    }
+
+    pub async fn save_workout(&self, summary: &WorkoutSummary, username: &str) -> i32 {
+        let bounding_box = summary.bounding_box.as_ref();
+        let min_latitude = bounding_box.map(|b| b.min_latitude);
+        let min_longitude = bounding_box.map(|b| b.min_longitude);
+        let max_latitude = bounding_box.map(|b| b.max_latitude);
+        let max_longitude = bounding_box.map(|b| b.max_longitude);
+        let result = sqlx::query!(
+            "INSERT INTO WORKOUT_SUMMARY
+            (km_ridden, avg_speed, avg_watts, avg_rpm, avg_heartrate, username,
+            elapsed_time, moving_time, moving_avg_speed, moving_avg_watts,
+            moving_avg_rpm, moving_avg_heartrate, stddev_speed, stddev_watts,
+            stddev_rpm, stddev_heartrate, avg_left_right_balance,
+            avg_left_torque_effectiveness, avg_right_torque_effectiveness,
+            avg_left_pedal_smoothness, avg_right_pedal_smoothness, avg_left_watts,
+            avg_right_watts, total_ascent, total_descent, max_grade, min_latitude,
+            min_longitude, max_latitude, max_longitude, work, calories, start_time,
+            nonzero_avg_watts, nonzero_avg_rpm, nonzero_avg_heartrate)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15,
+            $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30,
+            $31, $32, $33, $34, $35, $36)",
             summary.km_ridden,
             summary.avg_speed,
             summary.avg_watts,
             summary.avg_rpm,
             summary.avg_heartrate,
+            username,
+            summary.elapsed_time,
+            summary.moving_time,
+            summary.moving_avg_speed,
+            summary.moving_avg_watts,
+            summary.moving_avg_rpm,
+            summary.moving_avg_heartrate,
+            summary.stddev_speed,
+            summary.stddev_watts,
+            summary.stddev_rpm,
+            summary.stddev_heartrate,
+            summary.avg_left_right_balance,
+            summary.avg_left_torque_effectiveness,
+            summary.avg_right_torque_effectiveness,
+            summary.avg_left_pedal_smoothness,
+            summary.avg_right_pedal_smoothness,
+            summary.avg_left_watts,
+            summary.avg_right_watts,
+            summary.total_ascent,
+            summary.total_descent,
+            summary.max_grade,
+            min_latitude,
+            min_longitude,
+            max_latitude,
+            max_longitude,
+            summary.work,
+            summary.calories,
+            summary.start_time,
+            summary.nonzero_avg_watts,
+            summary.nonzero_avg_rpm,
+            summary.nonzero_avg_heartrate,
         )
         .execute(&self.db)
         .await;
 
         let summary_id = result.unwrap().last_insert_rowid();
 
+        for channel in summary.channels.iter() {
+            let _ = sqlx::query!(
+                "INSERT INTO CUSTOM_CHANNEL VALUES ($1, $2, $3, $4, $5, $6)",
+                summary_id,
+                channel.name,
+                channel.unit,
+                channel.min,
+                channel.avg,
+                channel.max,
+            )
+            .execute(&self.db)
+            .await;
+        }
+
+        for (sample_index, measurement) in summary.measurements.iter().enumerate() {
+            let sample_index = sample_index as i64;
+            for value in measurement.channels.iter() {
+                // Names are unique within a measurement, so duplicates are dropped
+                let _ = sqlx::query!(
+                    "INSERT OR IGNORE INTO CUSTOM_CHANNEL_VALUE VALUES ($1, $2, $3, $4)",
+                    summary_id,
+                    value.name,
+                    sample_index,
+                    value.value,
+                )
+                .execute(&self.db)
+                .await;
+            }
+        }
+
         for measurement in summary.measurements.clone() {
             let _ = sqlx::query!(
-                "INSERT INTO MEASUREMENTS VALUES ($1, $2, $3, $4, $5)",
+                "INSERT INTO MEASUREMENTS
+                (speed, watts, rpm, heartrate, workout_id, left_right_balance,
+                left_torque_effectiveness, right_torque_effectiveness,
+                left_pedal_smoothness, right_pedal_smoothness, left_watts, right_watts,
+                latitude, longitude, altitude)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
                 measurement.speed,
                 measurement.watts,
                 measurement.rpm,
                 measurement.heartrate,
                 summary_id,
+                measurement.left_right_balance,
+                measurement.left_torque_effectiveness,
+                measurement.right_torque_effectiveness,
+                measurement.left_pedal_smoothness,
+                measurement.right_pedal_smoothness,
+                measurement.left_watts,
+                measurement.right_watts,
+                measurement.latitude,
+                measurement.longitude,
+                measurement.altitude,
+            )
+            .execute(&self.db)
+            .await;
+        }
+
+        for lap in summary.laps.iter() {
+            let _ = sqlx::query!(
+                "INSERT INTO LAP (workout_id, start_index) VALUES ($1, $2)",
+                summary_id,
+                lap.start_index,
+            )
+            .execute(&self.db)
+            .await;
+        }
+
+        for sample in summary.cleaned_samples.iter() {
+            let field = sample.field().as_db_str();
+            let reason = sample.reason().as_db_str();
+            let _ = sqlx::query!(
+                "INSERT INTO CLEANED_SAMPLE VALUES ($1, $2, $3, $4, $5, $6)",
+                summary_id,
+                sample.index,
+                field,
+                reason,
+                sample.original_value,
+                sample.value,
             )
             .execute(&self.db)
             .await;
@@ -64,11 +586,229 @@ impl SQLiteHandler {
         summary_id as i32
     }
 
+    /// Replace the laps of a workout with ones starting at the given indices.
+    pub async fn replace_laps(&self, workout_id: i32, start_indices: &[u32]) -> bool {
+        let mut tx = self.db.begin().await.unwrap();
+
+        sqlx::query!("DELETE FROM LAP WHERE workout_id = $1", workout_id)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        for start_index in start_indices {
+            sqlx::query!(
+                "INSERT INTO LAP (workout_id, start_index) VALUES ($1, $2)",
+                workout_id,
+                start_index,
+            )
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        }
+
+        tx.commit().await.is_ok()
+    }
+
+    /// Return the totals of the user's workouts per week, starting on Monday in
+    /// UTC, most recent first. Workouts without a start time are left out.
+    pub async fn get_weekly_totals(
+        &self,
+        username: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Vec<WeekTotal> {
+        // The UNIX epoch was on a Thursday
+        sqlx::query!(
+            r#"SELECT
+                (start_time / 86400 - (start_time / 86400 + 3) % 7) * 86400
+                    AS "week_start!: i64",
+                COUNT(*) AS "workout_count!: i64",
+                SUM(km_ridden) AS "km_ridden!: f64",
+                SUM(elapsed_time) AS "elapsed_time!: i64",
+                SUM(moving_time) AS "moving_time!: i64",
+                SUM(work) AS "work!: f64",
+                TOTAL(calories) AS "calories!: f64"
+            FROM WORKOUT_SUMMARY
+            WHERE username = $1 AND start_time IS NOT NULL
+            AND ($2 IS NULL OR start_time >= $2) AND ($3 IS NULL OR start_time < $3)
+            GROUP BY 1
+            ORDER BY 1 DESC"#,
+            username,
+            from,
+            to,
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| WeekTotal {
+            week_start: r.week_start,
+            workout_count: r.workout_count as u32,
+            km_ridden: r.km_ridden as f32,
+            elapsed_time: r.elapsed_time as u32,
+            moving_time: r.moving_time as u32,
+            work: r.work as f32,
+            calories: r.calories as f32,
+        })
+        .collect()
+    }
+
+    /// Store the aerobic metrics and mean-max power of a workout, marking it as
+    /// analysed.
+    pub async fn save_workout_analysis(
+        &self,
+        workout_id: i32,
+        metrics: &AerobicMetrics,
+        mean_max_power: &[MeanMaxPower],
+    ) {
+        let mut tx = self.db.begin().await.unwrap();
+
+        sqlx::query!(
+            "UPDATE WORKOUT_SUMMARY
+            SET efficiency_factor = $1, decoupling = $2, heartrate_drift = $3,
+            analysed = TRUE
+            WHERE id = $4",
+            metrics.efficiency_factor,
+            metrics.decoupling,
+            metrics.heartrate_drift,
+            workout_id,
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        for effort in mean_max_power {
+            sqlx::query!(
+                "INSERT OR REPLACE INTO MEAN_MAX_POWER VALUES ($1, $2, $3)",
+                workout_id,
+                effort.duration,
+                effort.watts,
+            )
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        }
+
+        tx.commit().await.unwrap();
+    }
+
+    /// Return the ids of the user's workouts that haven't been analysed yet.
+    pub async fn get_unanalysed_workouts(&self, username: &str) -> Vec<i32> {
+        sqlx::query!(
+            r#"SELECT id AS "id!: i64" FROM WORKOUT_SUMMARY
+            WHERE username = $1 AND NOT analysed"#,
+            username,
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.id as i32)
+        .collect()
+    }
+
+    /// Return the aerobic metrics of the user's workouts, oldest first. Workouts
+    /// without a start time or heart rate are left out.
+    pub async fn get_aerobic_trend(
+        &self,
+        username: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Vec<AerobicPoint> {
+        sqlx::query!(
+            r#"SELECT id AS "id!: i64", start_time AS "start_time!: i64",
+            efficiency_factor, decoupling, heartrate_drift
+            FROM WORKOUT_SUMMARY
+            WHERE username = $1 AND start_time IS NOT NULL
+            AND ($2 IS NULL OR start_time >= $2) AND ($3 IS NULL OR start_time < $3)
+            AND (efficiency_factor IS NOT NULL OR heartrate_drift IS NOT NULL)
+            ORDER BY start_time, id"#,
+            username,
+            from,
+            to,
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| AerobicPoint {
+            workout_id: r.id as i32,
+            start_time: r.start_time,
+            efficiency_factor: r.efficiency_factor.map(|v| v as f32),
+            decoupling: r.decoupling.map(|v| v as f32),
+            heartrate_drift: r.heartrate_drift.map(|v| v as f32),
+        })
+        .collect()
+    }
+
+    /// Return the user's highest positive mean-max power of each duration, taking
+    /// the oldest workout on ties. Workouts without a start time are left out.
+    pub async fn get_best_mean_max_power(
+        &self,
+        username: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Vec<MeanMaxPower> {
+        sqlx::query!(
+            r#"SELECT duration AS "duration!: i64", watts AS "watts!: f64",
+            workout_id AS "workout_id!: i64"
+            FROM (
+                SELECT m.duration, m.watts, m.workout_id, ROW_NUMBER() OVER (
+                    PARTITION BY m.duration ORDER BY m.watts DESC, w.start_time, w.id
+                ) AS rank
+                FROM MEAN_MAX_POWER m JOIN WORKOUT_SUMMARY w ON w.id = m.workout_id
+                WHERE w.username = $1 AND w.start_time IS NOT NULL AND m.watts > 0
+                AND ($2 IS NULL OR w.start_time >= $2)
+                AND ($3 IS NULL OR w.start_time < $3)
+            )
+            WHERE rank = 1
+            ORDER BY duration"#,
+            username,
+            from,
+            to,
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| MeanMaxPower {
+            duration: r.duration as u32,
+            watts: r.watts as f32,
+            workout_id: r.workout_id as i32,
+        })
+        .collect()
+    }
+
+    /// Return the start time of a workout, if it exists and has one.
+    pub async fn get_start_time(&self, workout_id: i32) -> Option<i64> {
+        sqlx::query!(
+            "SELECT start_time FROM WORKOUT_SUMMARY WHERE id = $1",
+            workout_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .unwrap()
+        .and_then(|r| r.start_time)
+    }
+
+    /// Return the owner of a workout, if it exists and has one.
+    pub async fn get_workout_owner(&self, workout_id: i32) -> Option<String> {
+        sqlx::query!(
+            "SELECT username FROM WORKOUT_SUMMARY WHERE id = $1",
+            workout_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .unwrap()
+        .and_then(|r| r.username)
+    }
+
     pub async fn get_measurements(&self, workout_id: i32) -> Option<Vec<Measurement>> {
         // We can't use query_as, because the db fields are 64 bits by default,
-        // and therefore we have to cast the values by hand
+        // and therefore we have to cast the values by hand. Measurements are
+        // inserted in order, so their rowid keeps it.
         let records = sqlx::query!(
-            "SELECT * FROM MEASUREMENTS WHERE workout_id = $1",
+            "SELECT * FROM MEASUREMENTS WHERE workout_id = $1 ORDER BY rowid",
             workout_id
         )
         .fetch_all(&self.db)
@@ -82,11 +822,467 @@ impl SQLiteHandler {
                 watts: r.watts as i32,
                 rpm: r.rpm as i32,
                 heartrate: r.heartrate as i32,
+                left_right_balance: r.left_right_balance.map(|v| v as f32),
+                left_torque_effectiveness: r
+                    .left_torque_effectiveness
+                    .map(|v| v as f32),
+                right_torque_effectiveness: r
+                    .right_torque_effectiveness
+                    .map(|v| v as f32),
+                left_pedal_smoothness: r.left_pedal_smoothness.map(|v| v as f32),
+                right_pedal_smoothness: r.right_pedal_smoothness.map(|v| v as f32),
+                left_watts: r.left_watts.map(|v| v as i32),
+                right_watts: r.right_watts.map(|v| v as i32),
+                channels: vec![],
+                latitude: r.latitude,
+                longitude: r.longitude,
+                altitude: r.altitude.map(|v| v as f32),
+                timestamp: None,
+                index: None,
             })
             .collect();
 
-        Some(measurements)
+        Some(self.add_channel_values(workout_id, measurements).await)
     }
+
+    /// Attach the custom channel values of a workout to its measurements.
+    async fn add_channel_values(
+        &self,
+        workout_id: i32,
+        mut measurements: Vec<Measurement>,
+    ) -> Vec<Measurement> {
+        let records = sqlx::query!(
+            "SELECT name, unit, sample_index, value FROM CUSTOM_CHANNEL_VALUE
+            JOIN CUSTOM_CHANNEL ON CUSTOM_CHANNEL.workout_id = CUSTOM_CHANNEL_VALUE.workout_id
+            AND CUSTOM_CHANNEL.name = CUSTOM_CHANNEL_VALUE.channel
+            WHERE CUSTOM_CHANNEL_VALUE.workout_id = $1
+            ORDER BY sample_index, name",
+            workout_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap();
+
+        for r in records {
+            if let Some(measurement) = measurements.get_mut(r.sample_index as usize) {
+                measurement.channels.push(ChannelValue {
+                    name: r.name,
+                    value: r.value as f32,
+                    unit: r.unit,
+                });
+            }
+        }
+
+        measurements
+    }
+
+    pub async fn save_plan(&self, username: &str, plan: &WorkoutPlan) -> Option<i32> {
+        let mut tx = self.db.begin().await.ok()?;
+
+        let plan_id = sqlx::query!(
+            "INSERT INTO WORKOUT_PLAN (username, name) VALUES ($1, $2)",
+            username,
+            plan.name,
+        )
+        .execute(&mut *tx)
+        .await
+        .ok()?
+        .last_insert_rowid();
+
+        for (position, step) in plan.steps.iter().enumerate() {
+            let position = position as i64;
+            let (target_watts, target_ftp_share) = match step.power {
+                Some(Power::Watts(watts)) => (Some(watts), None),
+                Some(Power::FtpShare(share)) => (None, Some(share)),
+                None => (None, None),
+            };
+            let (target_end_watts, target_end_ftp_share) = match step.power_end {
+                Some(PowerEnd::EndWatts(watts)) => (Some(watts), None),
+                Some(PowerEnd::EndFtpShare(share)) => (None, Some(share)),
+                None => (None, None),
+            };
+            let heartrate = step.heartrate.as_ref();
+            let (min_heartrate, max_heartrate) =
+                (heartrate.map(|r| r.min), heartrate.map(|r| r.max));
+            let rpm = step.rpm.as_ref();
+            let (min_rpm, max_rpm) = (rpm.map(|r| r.min), rpm.map(|r| r.max));
+
+            sqlx::query!(
+                "INSERT INTO PLAN_STEP VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10,
+                $11)",
+                plan_id,
+                position,
+                step.duration,
+                target_watts,
+                target_ftp_share,
+                min_heartrate,
+                max_heartrate,
+                min_rpm,
+                max_rpm,
+                target_end_watts,
+                target_end_ftp_share,
+            )
+            .execute(&mut *tx)
+            .await
+            .ok()?;
+        }
+
+        tx.commit().await.ok()?;
+
+        Some(plan_id as i32)
+    }
+
+    pub async fn get_plans(&self, username: &str) -> Vec<WorkoutPlan> {
+        let records = sqlx::query!(
+            "SELECT id, name FROM WORKOUT_PLAN WHERE username = $1 ORDER BY id",
+            username
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap();
+
+        let mut plans = vec![];
+        for r in records {
+            plans.push(WorkoutPlan {
+                id: Some(r.id as i32),
+                name: r.name,
+                steps: self.get_plan_steps(r.id).await,
+            });
+        }
+
+        plans
+    }
+
+    /// Return a plan, if it belongs to the given user.
+    pub async fn get_plan(&self, plan_id: i32, username: &str) -> Option<WorkoutPlan> {
+        let record = sqlx::query!(
+            "SELECT id, name FROM WORKOUT_PLAN WHERE id = $1 AND username = $2",
+            plan_id,
+            username
+        )
+        .fetch_optional(&self.db)
+        .await
+        .unwrap()?;
+
+        Some(WorkoutPlan {
+            id: Some(record.id as i32),
+            name: record.name,
+            steps: self.get_plan_steps(record.id).await,
+        })
+    }
+
+    async fn get_plan_steps(&self, plan_id: i64) -> Vec<PlanStep> {
+        sqlx::query!(
+            "SELECT duration, target_watts, target_ftp_share, min_heartrate,
+            max_heartrate, min_rpm, max_rpm, target_end_watts, target_end_ftp_share
+            FROM PLAN_STEP
+            WHERE plan_id = $1 ORDER BY position",
+            plan_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| PlanStep {
+            duration: r.duration as u32,
+            power: match (r.target_watts, r.target_ftp_share) {
+                (Some(watts), _) => Some(Power::Watts(watts as i32)),
+                (None, Some(share)) => Some(Power::FtpShare(share as f32)),
+                (None, None) => None,
+            },
+            heartrate: target_range(r.min_heartrate, r.max_heartrate),
+            rpm: target_range(r.min_rpm, r.max_rpm),
+            power_end: match (r.target_end_watts, r.target_end_ftp_share) {
+                (Some(watts), _) => Some(PowerEnd::EndWatts(watts as i32)),
+                (None, Some(share)) => Some(PowerEnd::EndFtpShare(share as f32)),
+                (None, None) => None,
+            },
+        })
+        .collect()
+    }
+
+    /// Delete a plan, if it belongs to the given user.
+    pub async fn delete_plan(&self, plan_id: i32, username: &str) -> bool {
+        let mut tx = self.db.begin().await.unwrap();
+
+        sqlx::query!(
+            "DELETE FROM PLAN_STEP WHERE plan_id IN
+            (SELECT id FROM WORKOUT_PLAN WHERE id = $1 AND username = $2)",
+            plan_id,
+            username
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        let deleted = sqlx::query!(
+            "DELETE FROM WORKOUT_PLAN WHERE id = $1 AND username = $2",
+            plan_id,
+            username
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap()
+        .rows_affected()
+            == 1;
+
+        deleted && tx.commit().await.is_ok()
+    }
+
+    /// Replace the profile of a user, including their privacy zones.
+    pub async fn save_profile(&self, username: &str, profile: &UserProfile) -> bool {
+        let mut tx = self.db.begin().await.unwrap();
+
+        let sex = match profile.sex() {
+            Sex::Unspecified => None,
+            sex => Some(sex.as_db_str()),
+        };
+        let saved = sqlx::query!(
+            "INSERT OR REPLACE INTO USER_PROFILE
+            (username, age, weight, sex, gross_efficiency)
+            VALUES ($1, $2, $3, $4, $5)",
+            username,
+            profile.age,
+            profile.weight,
+            sex,
+            profile.gross_efficiency,
+        )
+        .execute(&mut *tx)
+        .await;
+
+        if saved.is_err() {
+            return false;
+        }
+
+        sqlx::query!("DELETE FROM PRIVACY_ZONE WHERE username = $1", username)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        for zone in profile.privacy_zones.iter() {
+            let inserted = sqlx::query!(
+                "INSERT INTO PRIVACY_ZONE (username, latitude, longitude, radius)
+                VALUES ($1, $2, $3, $4)",
+                username,
+                zone.latitude,
+                zone.longitude,
+                zone.radius,
+            )
+            .execute(&mut *tx)
+            .await;
+
+            if inserted.is_err() {
+                return false;
+            }
+        }
+
+        tx.commit().await.is_ok()
+    }
+
+    pub async fn get_profile(&self, username: &str) -> UserProfile {
+        let record = sqlx::query!(
+            "SELECT age, weight, sex, gross_efficiency FROM USER_PROFILE
+            WHERE username = $1",
+            username
+        )
+        .fetch_optional(&self.db)
+        .await
+        .unwrap();
+
+        let mut profile = UserProfile {
+            privacy_zones: self.get_privacy_zones(username).await,
+            ..Default::default()
+        };
+
+        if let Some(r) = record {
+            profile.age = r.age.map(|v| v as u32);
+            profile.weight = r.weight.map(|v| v as f32);
+            profile
+                .set_sex(r.sex.as_deref().map_or(Sex::Unspecified, Sex::from_db_str));
+            profile.gross_efficiency = r.gross_efficiency.map(|v| v as f32);
+        }
+
+        profile
+    }
+
+    pub async fn get_privacy_zones(&self, username: &str) -> Vec<PrivacyZone> {
+        sqlx::query!(
+            "SELECT latitude, longitude, radius FROM PRIVACY_ZONE
+            WHERE username = $1 ORDER BY id",
+            username
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| PrivacyZone {
+            latitude: r.latitude,
+            longitude: r.longitude,
+            radius: r.radius as f32,
+        })
+        .collect()
+    }
+
+    /// Save a segment and its points, returning its id.
+    pub async fn save_segment(
+        &self,
+        username: &str,
+        segment: &Segment,
+        points: &[(f64, f64)],
+    ) -> Option<i32> {
+        let bounding_box = segment.bounding_box.clone().unwrap_or_default();
+        let mut tx = self.db.begin().await.ok()?;
+
+        let segment_id = sqlx::query!(
+            "INSERT INTO SEGMENT
+            (username, name, distance, polyline, min_latitude, min_longitude,
+            max_latitude, max_longitude)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            username,
+            segment.name,
+            segment.distance,
+            segment.polyline,
+            bounding_box.min_latitude,
+            bounding_box.min_longitude,
+            bounding_box.max_latitude,
+            bounding_box.max_longitude,
+        )
+        .execute(&mut *tx)
+        .await
+        .ok()?
+        .last_insert_rowid();
+
+        for (position, (latitude, longitude)) in points.iter().enumerate() {
+            let position = position as i64;
+            sqlx::query!(
+                "INSERT INTO SEGMENT_POINT VALUES ($1, $2, $3, $4)",
+                segment_id,
+                position,
+                latitude,
+                longitude,
+            )
+            .execute(&mut *tx)
+            .await
+            .ok()?;
+        }
+
+        tx.commit().await.ok()?;
+
+        Some(segment_id as i32)
+    }
+
+    pub async fn get_segment(&self, segment_id: i32) -> Option<Segment> {
+        sqlx::query!("SELECT * FROM SEGMENT WHERE id = $1", segment_id)
+            .fetch_optional(&self.db)
+            .await
+            .unwrap()
+            .map(|r| Segment {
+                id: r.id as i32,
+                name: r.name,
+                distance: r.distance as f32,
+                polyline: r.polyline,
+                bounding_box: Some(BoundingBox {
+                    min_latitude: r.min_latitude,
+                    min_longitude: r.min_longitude,
+                    max_latitude: r.max_latitude,
+                    max_longitude: r.max_longitude,
+                }),
+            })
+    }
+
+    pub async fn get_segment_points(&self, segment_id: i32) -> Vec<(f64, f64)> {
+        sqlx::query!(
+            "SELECT latitude, longitude FROM SEGMENT_POINT
+            WHERE segment_id = $1 ORDER BY position",
+            segment_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.latitude, r.longitude))
+        .collect()
+    }
+
+    /// Return the ids of the segments whose bounding box overlaps the given one.
+    pub async fn get_overlapping_segments(
+        &self,
+        bounding_box: &BoundingBox,
+    ) -> Vec<i32> {
+        sqlx::query!(
+            "SELECT id FROM SEGMENT
+            WHERE max_latitude >= $1 AND max_longitude >= $2
+            AND min_latitude <= $3 AND min_longitude <= $4
+            ORDER BY id",
+            bounding_box.min_latitude,
+            bounding_box.min_longitude,
+            bounding_box.max_latitude,
+            bounding_box.max_longitude,
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.id as i32)
+        .collect()
+    }
+
+    pub async fn save_segment_effort(
+        &self,
+        segment_id: i32,
+        effort: &SegmentEffort,
+    ) -> bool {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO SEGMENT_EFFORT
+            (segment_id, workout_id, start_index, username, elapsed_time, avg_watts)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+            segment_id,
+            effort.workout_id,
+            effort.start_index,
+            effort.username,
+            effort.elapsed_time,
+            effort.avg_watts,
+        )
+        .execute(&self.db)
+        .await
+        .is_ok()
+    }
+
+    /// Return the best effort of every user on a segment, fastest first. Ties go
+    /// to the earlier effort.
+    pub async fn get_segment_leaderboard(&self, segment_id: i32) -> Vec<SegmentEffort> {
+        sqlx::query!(
+            "SELECT e.username, e.workout_id, e.start_index, e.elapsed_time, e.avg_watts
+            FROM SEGMENT_EFFORT e
+            WHERE e.segment_id = $1 AND NOT EXISTS (
+                SELECT 1 FROM SEGMENT_EFFORT o
+                WHERE o.segment_id = e.segment_id AND o.username = e.username
+                AND (o.elapsed_time < e.elapsed_time
+                    OR (o.elapsed_time = e.elapsed_time AND o.rowid < e.rowid))
+            )
+            ORDER BY e.elapsed_time, e.rowid",
+            segment_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| SegmentEffort {
+            username: r.username,
+            workout_id: r.workout_id as i32,
+            start_index: r.start_index as u32,
+            elapsed_time: r.elapsed_time as u32,
+            avg_watts: r.avg_watts as i32,
+        })
+        .collect()
+    }
+}
+
+fn target_range(min: Option<i64>, max: Option<i64>) -> Option<TargetRange> {
+    Some(TargetRange {
+        min: min? as i32,
+        max: max? as i32,
+    })
 }
 
 #[derive(Debug, Error)]
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+
+use crate::cycling_tracker::{
+    ChannelSummary, ChannelValue, LapSummary, Measurement, Workout, WorkoutSummary,
+};
+use crate::handler::route::RouteStats;
+use crate::handler::AutoPause;
+
+/// Running mean and variance of a value, using Welford's algorithm.
+#[derive(Clone, Copy, Debug, Default)]
+struct RunningStat {
+    count: u64,
+    sum: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStat {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Undo adding a value.
+    fn remove(&mut self, value: f64) {
+        if self.count <= 1 {
+            *self = Self::default();
+            return;
+        }
+
+        let mean = self.mean;
+        self.count -= 1;
+        self.sum -= value;
+        self.mean = (mean * (self.count + 1) as f64 - value) / self.count as f64;
+        self.m2 = (self.m2 - (value - self.mean) * (value - mean)).max(0.0);
+    }
+
+    fn mean(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => self.sum / count as f64,
+        }
+    }
+
+    /// Mean of a channel that's not always reported, if it ever was
+    fn optional_mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.mean())
+    }
+
+    /// Population standard deviation
+    fn std_dev(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => (self.m2 / count as f64).sqrt(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct MeasurementStats {
+    speed: RunningStat,
+    watts: RunningStat,
+    rpm: RunningStat,
+    heartrate: RunningStat,
+    /// Power and cadence leaving out zeros, such as when coasting, and heart
+    /// rate leaving out sensor dropouts. Stops are left out of speed by the
+    /// moving averages instead.
+    nonzero_watts: RunningStat,
+    nonzero_rpm: RunningStat,
+    nonzero_heartrate: RunningStat,
+    /// Optional channels only count measurements reporting them
+    left_right_balance: RunningStat,
+    left_torque_effectiveness: RunningStat,
+    right_torque_effectiveness: RunningStat,
+    left_pedal_smoothness: RunningStat,
+    right_pedal_smoothness: RunningStat,
+    left_watts: RunningStat,
+    right_watts: RunningStat,
+}
+
+impl MeasurementStats {
+    pub(crate) fn add(&mut self, measurement: &Measurement) {
+        self.speed.add(measurement.speed as f64);
+        self.watts.add(measurement.watts as f64);
+        self.rpm.add(measurement.rpm as f64);
+        self.heartrate.add(measurement.heartrate as f64);
+        if measurement.watts != 0 {
+            self.nonzero_watts.add(measurement.watts as f64);
+        }
+        if measurement.rpm != 0 {
+            self.nonzero_rpm.add(measurement.rpm as f64);
+        }
+        if measurement.heartrate != 0 {
+            self.nonzero_heartrate.add(measurement.heartrate as f64);
+        }
+
+        for (stat, value) in self.optional_channels(measurement) {
+            if let Some(value) = value {
+                stat.add(value);
+            }
+        }
+    }
+
+    fn remove(&mut self, measurement: &Measurement) {
+        self.speed.remove(measurement.speed as f64);
+        self.watts.remove(measurement.watts as f64);
+        self.rpm.remove(measurement.rpm as f64);
+        self.heartrate.remove(measurement.heartrate as f64);
+        if measurement.watts != 0 {
+            self.nonzero_watts.remove(measurement.watts as f64);
+        }
+        if measurement.rpm != 0 {
+            self.nonzero_rpm.remove(measurement.rpm as f64);
+        }
+        if measurement.heartrate != 0 {
+            self.nonzero_heartrate.remove(measurement.heartrate as f64);
+        }
+
+        for (stat, value) in self.optional_channels(measurement) {
+            if let Some(value) = value {
+                stat.remove(value);
+            }
+        }
+    }
+
+    fn optional_channels(
+        &mut self,
+        measurement: &Measurement,
+    ) -> [(&mut RunningStat, Option<f64>); 7] {
+        [
+            (
+                &mut self.left_right_balance,
+                measurement.left_right_balance.map(f64::from),
+            ),
+            (
+                &mut self.left_torque_effectiveness,
+                measurement.left_torque_effectiveness.map(f64::from),
+            ),
+            (
+                &mut self.right_torque_effectiveness,
+                measurement.right_torque_effectiveness.map(f64::from),
+            ),
+            (
+                &mut self.left_pedal_smoothness,
+                measurement.left_pedal_smoothness.map(f64::from),
+            ),
+            (
+                &mut self.right_pedal_smoothness,
+                measurement.right_pedal_smoothness.map(f64::from),
+            ),
+            (&mut self.left_watts, measurement.left_watts.map(f64::from)),
+            (
+                &mut self.right_watts,
+                measurement.right_watts.map(f64::from),
+            ),
+        ]
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.speed.count
+    }
+
+    /// Averages are truncated to the type of the field
+    pub(crate) fn averages(&self) -> Measurement {
+        Measurement {
+            speed: self.speed.mean() as f32,
+            watts: self.watts.mean() as i32,
+            rpm: self.rpm.mean() as i32,
+            heartrate: self.heartrate.mean() as i32,
+            left_right_balance: self
+                .left_right_balance
+                .optional_mean()
+                .map(|m| m as f32),
+            left_torque_effectiveness: self
+                .left_torque_effectiveness
+                .optional_mean()
+                .map(|m| m as f32),
+            right_torque_effectiveness: self
+                .right_torque_effectiveness
+                .optional_mean()
+                .map(|m| m as f32),
+            left_pedal_smoothness: self
+                .left_pedal_smoothness
+                .optional_mean()
+                .map(|m| m as f32),
+            right_pedal_smoothness: self
+                .right_pedal_smoothness
+                .optional_mean()
+                .map(|m| m as f32),
+            left_watts: self.left_watts.optional_mean().map(|m| m as i32),
+            right_watts: self.right_watts.optional_mean().map(|m| m as i32),
+            channels: vec![],
+            latitude: None,
+            longitude: None,
+            altitude: None,
+            timestamp: None,
+            index: None,
+        }
+    }
+}
+
+/// Range and mean of a custom sensor channel
+#[derive(Clone, Debug)]
+struct ChannelStats {
+    unit: String,
+    min: f32,
+    max: f32,
+    stat: RunningStat,
+}
+
+impl ChannelStats {
+    fn new(value: &ChannelValue) -> Self {
+        Self {
+            unit: value.unit.clone(),
+            min: value.value,
+            max: value.value,
+            stat: RunningStat::default(),
+        }
+    }
+
+    fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.stat.add(value as f64);
+    }
+}
+
+/// Summarizes measurements as they come in, taking constant time and memory per
+/// measurement, apart from the laps.
+#[derive(Clone, Debug)]
+pub struct SummaryAccumulator {
+    auto_pause: AutoPause,
+    all: MeasurementStats,
+    moving: MeasurementStats,
+    /// Ongoing stop, until it's long enough to pause
+    stop: Vec<Measurement>,
+    paused: bool,
+    /// Start index and stats of every lap, if a lap was ever started
+    laps: Vec<(u32, MeasurementStats)>,
+    /// Custom sensor channels by name
+    channels: BTreeMap<String, ChannelStats>,
+    route: RouteStats,
+    /// Joules, as measurements are one second apart
+    work: f64,
+}
+
+impl SummaryAccumulator {
+    pub fn new(auto_pause: AutoPause) -> Self {
+        Self {
+            auto_pause,
+            all: MeasurementStats::default(),
+            moving: MeasurementStats::default(),
+            stop: vec![],
+            paused: false,
+            laps: vec![],
+            channels: BTreeMap::new(),
+            route: RouteStats::default(),
+            work: 0.0,
+        }
+    }
+
+    /// Accumulate a whole workout, starting laps at its lap boundaries. Lap
+    /// boundaries outside the workout are ignored.
+    pub fn from_workout(auto_pause: AutoPause, workout: &Workout) -> Self {
+        let mut laps = workout.laps.clone();
+        laps.sort_unstable();
+        laps.dedup();
+        let mut laps = laps.into_iter().peekable();
+
+        let mut accumulator = Self::new(auto_pause);
+        for (index, measurement) in workout.measurements.iter().enumerate() {
+            if laps.next_if(|&start| start as usize <= index).is_some() {
+                accumulator.start_lap();
+            }
+            accumulator.add(measurement);
+        }
+
+        accumulator
+    }
+
+    pub fn count(&self) -> u64 {
+        self.all.count()
+    }
+
+    pub fn add(&mut self, measurement: &Measurement) {
+        self.all.add(measurement);
+        if let Some((_, lap)) = self.laps.last_mut() {
+            lap.add(measurement);
+        }
+
+        for value in measurement.channels.iter() {
+            if !value.value.is_finite() {
+                continue;
+            }
+
+            self.channels
+                .entry(value.name.clone())
+                .or_insert_with(|| ChannelStats::new(value))
+                .add(value.value);
+        }
+        self.route.add(measurement);
+        self.work += measurement.watts.max(0) as f64;
+
+        if !self.auto_pause.is_stopped(measurement) {
+            self.stop.clear();
+            self.paused = false;
+            self.moving.add(measurement);
+            return;
+        }
+
+        if self.paused {
+            return;
+        }
+
+        // Stops count as moving until they last long enough
+        self.moving.add(measurement);
+        self.stop.push(measurement.clone());
+
+        if self.stop.len() >= self.auto_pause.delay {
+            for measurement in self.stop.drain(..) {
+                self.moving.remove(&measurement);
+            }
+            self.paused = true;
+        }
+    }
+
+    /// Start a new lap at the next measurement, returning its start index. The
+    /// first lap starts implicitly, and empty laps are skipped.
+    pub fn start_lap(&mut self) -> Option<u32> {
+        let start_index = self.count() as u32;
+
+        match self.laps.last() {
+            _ if start_index == 0 => return None,
+            Some((start, _)) if *start == start_index => return None,
+            Some(_) => {}
+            None => self.laps.push((0, self.all)),
+        }
+
+        self.laps.push((start_index, MeasurementStats::default()));
+
+        Some(start_index)
+    }
+
+    /// Return the summary of the measurements so far, without the measurements.
+    pub fn summary(&self, km_ridden: f32) -> WorkoutSummary {
+        if self.count() == 0 {
+            return WorkoutSummary {
+                km_ridden: 0.0,
+                ..Default::default()
+            };
+        }
+
+        let averages = self.all.averages();
+        let moving_averages = self.moving.averages();
+        let elevation = self.route.elevation();
+
+        WorkoutSummary {
+            id: None,
+            km_ridden,
+            avg_speed: averages.speed,
+            avg_watts: averages.watts,
+            avg_rpm: averages.rpm,
+            avg_heartrate: averages.heartrate,
+            measurements: vec![],
+            laps: self.lap_summaries(),
+            elapsed_time: self.count() as u32,
+            moving_time: self.moving.count() as u32,
+            moving_avg_speed: moving_averages.speed,
+            moving_avg_watts: moving_averages.watts,
+            moving_avg_rpm: moving_averages.rpm,
+            moving_avg_heartrate: moving_averages.heartrate,
+            paused: self.paused,
+            stddev_speed: self.all.speed.std_dev() as f32,
+            stddev_watts: self.all.watts.std_dev().round() as i32,
+            stddev_rpm: self.all.rpm.std_dev().round() as i32,
+            stddev_heartrate: self.all.heartrate.std_dev().round() as i32,
+            avg_left_right_balance: averages.left_right_balance,
+            avg_left_torque_effectiveness: averages.left_torque_effectiveness,
+            avg_right_torque_effectiveness: averages.right_torque_effectiveness,
+            avg_left_pedal_smoothness: averages.left_pedal_smoothness,
+            avg_right_pedal_smoothness: averages.right_pedal_smoothness,
+            avg_left_watts: averages.left_watts,
+            avg_right_watts: averages.right_watts,
+            channels: self.channel_summaries(),
+            total_ascent: elevation.map(|(ascent, _)| ascent),
+            total_descent: elevation.map(|(_, descent)| descent),
+            max_grade: self.route.max_grade(),
+            bounding_box: self.route.bounding_box(),
+            work: (self.work / 1000.0) as f32,
+            calories: None,
+            start_time: 0,
+            efficiency_factor: None,
+            decoupling: None,
+            heartrate_drift: None,
+            w_prime_balance: None,
+            cleaned_samples: vec![],
+            nonzero_avg_watts: self.all.nonzero_watts.mean() as i32,
+            nonzero_avg_rpm: self.all.nonzero_rpm.mean() as i32,
+            nonzero_avg_heartrate: self.all.nonzero_heartrate.mean() as i32,
+        }
+    }
+
+    fn channel_summaries(&self) -> Vec<ChannelSummary> {
+        self.channels
+            .iter()
+            .map(|(name, stats)| ChannelSummary {
+                name: name.clone(),
+                unit: stats.unit.clone(),
+                min: stats.min,
+                avg: stats.stat.mean() as f32,
+                max: stats.max,
+            })
+            .collect()
+    }
+
+    fn lap_summaries(&self) -> Vec<LapSummary> {
+        let laps: Vec<LapSummary> = self
+            .laps
+            .iter()
+            .filter(|(_, stats)| stats.count() > 0)
+            .map(|(start_index, stats)| {
+                let averages = stats.averages();
+
+                LapSummary {
+                    start_index: *start_index,
+                    measurement_count: stats.count() as u32,
+                    avg_speed: averages.speed,
+                    avg_watts: averages.watts,
+                    avg_rpm: averages.rpm,
+                    avg_heartrate: averages.heartrate,
+                }
+            })
+            .collect();
+
+        // A single lap is the whole workout
+        if laps.len() < 2 {
+            return vec![];
+        }
+
+        laps
+    }
+}
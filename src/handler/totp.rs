@@ -0,0 +1,169 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::cycling_tracker::TotpEnrollment;
+use crate::handler::user::{hash_secret, verify_secret};
+use crate::handler::SQLiteHandler;
+
+const ISSUER: &str = "CyclingTracker";
+const TIME_STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODES: usize = 8;
+
+#[derive(Clone)]
+pub struct TotpHandler {
+    pub sqlite_handler: SQLiteHandler,
+}
+
+impl TotpHandler {
+    /// Generate a new secret and recovery codes for the user. Returns None if
+    /// two-factor authentication is already enabled.
+    pub async fn enroll(&self, username: String) -> Option<TotpEnrollment> {
+        if self.is_enabled(&username).await {
+            return None;
+        }
+
+        let secret = BASE32_NOPAD.encode(&random_bytes(20));
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODES)
+            .map(|_| BASE32_NOPAD.encode(&random_bytes(5)).to_lowercase())
+            .collect();
+
+        let saved = self
+            .sqlite_handler
+            .save_totp_enrollment(
+                username.clone(),
+                secret.clone(),
+                recovery_codes.iter().map(|c| hash_secret(c)).collect(),
+            )
+            .await;
+
+        if !saved {
+            return None;
+        }
+
+        Some(TotpEnrollment {
+            provisioning_uri: format!(
+                "otpauth://totp/{ISSUER}:{}?secret={secret}&issuer={ISSUER}",
+                percent_encode(&username)
+            ),
+            secret,
+            recovery_codes,
+        })
+    }
+
+    /// Enable two-factor authentication, given a valid code for the enrolled secret.
+    pub async fn confirm(&self, username: &str, code: &str) -> bool {
+        match self.sqlite_handler.get_totp_secret(username).await {
+            Some((secret, false)) => {
+                self.use_code(username, &secret, code).await
+                    && self.sqlite_handler.enable_totp(username).await
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn is_enabled(&self, username: &str) -> bool {
+        matches!(
+            self.sqlite_handler.get_totp_secret(username).await,
+            Some((_, true))
+        )
+    }
+
+    /// Check a TOTP code or, failing that, a recovery code. Neither can be used
+    /// twice: recovery codes are deleted once used, and TOTP codes are only
+    /// accepted for a later time step than the last accepted one.
+    pub async fn verify(&self, username: &str, code: &str) -> bool {
+        let Some((secret, true)) = self.sqlite_handler.get_totp_secret(username).await
+        else {
+            return false;
+        };
+
+        if self.use_code(username, &secret, code).await {
+            return true;
+        }
+
+        let code = code.trim().to_lowercase();
+        for code_hash in self.sqlite_handler.get_recovery_codes(username).await {
+            if verify_secret(&code, &code_hash) {
+                return self
+                    .sqlite_handler
+                    .delete_recovery_code(username, &code_hash)
+                    .await;
+            }
+        }
+
+        false
+    }
+
+    async fn use_code(&self, username: &str, secret: &str, code: &str) -> bool {
+        match matching_step(secret, code, now()) {
+            Some(step) => {
+                self.sqlite_handler
+                    .use_totp_step(username, step as i64)
+                    .await
+            }
+            None => false,
+        }
+    }
+}
+
+/// Compute the TOTP code of a base32 encoded secret at the given unix time.
+pub fn generate_code(secret: &str, time: u64) -> Option<String> {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let counter = time / TIME_STEP;
+
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key).ok()?;
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation, see RFC 4226 section 5.3
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary =
+        u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+
+    Some(format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    ))
+}
+
+/// Return the time step a code is valid for, checking the current time step and
+/// one step of clock drift in either direction.
+fn matching_step(secret: &str, code: &str, time: u64) -> Option<u64> {
+    let code = code.trim();
+    [time.saturating_sub(TIME_STEP), time, time + TIME_STEP]
+        .into_iter()
+        .find(|&t| generate_code(secret, t).is_some_and(|c| c == code))
+        .map(|t| t / TIME_STEP)
+}
+
+/// Percent-encode all but the unreserved characters of RFC 3986, section 2.3.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
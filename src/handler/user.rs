@@ -5,7 +5,7 @@ use argon2::{
     Argon2,
 };
 
-use crate::cycling_tracker::Credentials;
+use crate::cycling_tracker::{Credentials, Role, User};
 use crate::handler::SQLiteHandler;
 
 #[derive(Clone)]
@@ -15,12 +15,7 @@ pub struct UserHandler {
 
 impl UserHandler {
     pub async fn create(&self, credentials: Credentials) -> bool {
-        let argon2 = Argon2::default();
-        let salt = SaltString::generate(&mut OsRng);
-        let hash = argon2
-            .hash_password(credentials.password.as_bytes(), &salt)
-            .unwrap()
-            .to_string();
+        let hash = hash_secret(&credentials.password);
 
         self.sqlite_handler
             .create_user(credentials.username, hash)
@@ -28,18 +23,95 @@ impl UserHandler {
     }
 
     pub async fn login(&self, credentials: Credentials) -> bool {
+        if self.is_disabled(&credentials.username).await {
+            return false;
+        }
+
         let password_hash = self
             .sqlite_handler
             .get_hashed_password(credentials.username)
             .await;
         match password_hash {
-            Some(hash) => {
-                let parsed_hash = PasswordHash::new(&hash).unwrap();
-                return Argon2::default()
-                    .verify_password(credentials.password.as_bytes(), &parsed_hash)
-                    .is_ok();
-            }
+            Some(hash) => verify_secret(&credentials.password, &hash),
             None => false,
         }
     }
+
+    pub async fn exists(&self, username: String) -> bool {
+        self.sqlite_handler
+            .get_hashed_password(username)
+            .await
+            .is_some()
+    }
+
+    pub async fn is_disabled(&self, username: &str) -> bool {
+        self.sqlite_handler
+            .get_user(username)
+            .await
+            .is_some_and(|user| user.disabled)
+    }
+
+    pub async fn get_role(&self, username: &str) -> Option<Role> {
+        self.sqlite_handler
+            .get_user(username)
+            .await
+            .map(|user| user.role())
+    }
+
+    pub async fn list(&self) -> Vec<User> {
+        self.sqlite_handler.list_users().await
+    }
+
+    pub async fn set_disabled(&self, username: &str, disabled: bool) -> bool {
+        self.sqlite_handler
+            .set_user_disabled(username, disabled)
+            .await
+    }
+
+    pub async fn set_role(&self, username: &str, role: Role) -> bool {
+        self.sqlite_handler.set_user_role(username, role).await
+    }
+
+    pub async fn reset_password(&self, username: String, new_password: &str) -> bool {
+        let hash = hash_secret(new_password);
+
+        self.sqlite_handler.update_password(username, hash).await
+    }
+}
+
+impl Role {
+    pub fn as_db_str(&self) -> &'static str {
+        match self {
+            Role::Athlete => "athlete",
+            Role::Coach => "coach",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn from_db_str(role: &str) -> Self {
+        match role {
+            "coach" => Role::Coach,
+            "admin" => Role::Admin,
+            _ => Role::Athlete,
+        }
+    }
+}
+
+/// Hash a secret with argon2 and a random salt, returning it in PHC string format.
+pub fn hash_secret(secret: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+}
+
+/// Check a secret against a hash created by `hash_secret`.
+pub fn verify_secret(secret: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed_hash) => Argon2::default()
+            .verify_password(secret.as_bytes(), &parsed_hash)
+            .is_ok(),
+        Err(_) => false,
+    }
 }
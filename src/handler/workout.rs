@@ -1,49 +1,236 @@
-use crate::cycling_tracker::{Measurement, Workout, WorkoutSummary};
-use crate::handler::SQLiteHandler;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cycling_tracker::{
+    AerobicPoint, CriticalPowerModel, MeanMaxPower, Measurement, WeekTotal, Workout,
+    WorkoutSummary,
+};
+use crate::handler::analysis::aerobic_metrics;
+use crate::handler::critical_power::{fit, mean_max_power, MODEL_DURATIONS};
+use crate::handler::energy::calories;
+use crate::handler::resample::{resample_to_1hz, ResampleError};
+use crate::handler::{DataCleaning, SQLiteHandler, SegmentHandler, SummaryAccumulator};
+
+// Seconds of workouts the critical power model of live workouts is fitted to
+const RECENT_CRITICAL_POWER_WINDOW: i64 = 90 * 86400;
 
 #[derive(Clone)]
 pub struct WorkoutHandler {
     pub sqlite_handler: SQLiteHandler,
+    pub segment_handler: SegmentHandler,
+    pub auto_pause: AutoPause,
+    pub data_cleaning: DataCleaning,
+}
+
+/// When a rider is considered stopped, e.g. at a traffic light. Measurements are
+/// assumed to be one second apart.
+#[derive(Clone, Copy, Debug)]
+pub struct AutoPause {
+    /// Speed below which the rider might be stopped
+    pub min_speed: f32,
+    /// Cadence below which the rider might be stopped
+    pub min_rpm: i32,
+    /// Seconds below both thresholds before the rider is considered stopped
+    pub delay: usize,
+}
+
+impl Default for AutoPause {
+    fn default() -> Self {
+        Self {
+            min_speed: 2.0,
+            min_rpm: 10,
+            delay: 3,
+        }
+    }
+}
+
+impl AutoPause {
+    pub fn is_stopped(&self, measurement: &Measurement) -> bool {
+        measurement.speed < self.min_speed && measurement.rpm < self.min_rpm
+    }
 }
 
 impl WorkoutHandler {
-    pub async fn save_workout(&self, workout: &Workout) -> WorkoutSummary {
+    /// Clean and save a workout, resampling it first if its measurements have
+    /// timestamps. Fails if the timestamps span too long to resample.
+    pub async fn save_workout(
+        &self,
+        workout: &Workout,
+        username: &str,
+    ) -> Result<WorkoutSummary, ResampleError> {
+        let mut workout = resample_to_1hz(workout)?.unwrap_or_else(|| workout.clone());
+        let cleaned_samples = self.data_cleaning.clean(&mut workout.measurements);
+        let workout = &workout;
+
         let mut summary = self.create_summary(workout);
-        let summary_id = self.sqlite_handler.save_workout(&summary).await;
+        summary.cleaned_samples = cleaned_samples;
+
+        let profile = self.sqlite_handler.get_profile(username).await;
+        summary.calories = calories(summary.work, &workout.measurements, &profile);
+
+        let metrics = aerobic_metrics(&workout.measurements);
+        summary.efficiency_factor = metrics.efficiency_factor;
+        summary.decoupling = metrics.decoupling;
+        summary.heartrate_drift = metrics.heartrate_drift;
+        // Measurements are one second apart
+        summary.start_time = workout
+            .start_time
+            .unwrap_or_else(|| now() - workout.measurements.len() as i64);
+
+        let summary_id = self.sqlite_handler.save_workout(&summary, username).await;
         summary.id = Some(summary_id);
+        self.save_analysis(summary_id, &workout.measurements).await;
+
+        // Matching segments can take a while, and isn't part of the summary
+        if let Some(bounding_box) = summary.bounding_box.clone() {
+            let segment_handler = self.segment_handler.clone();
+            let measurements = workout.measurements.clone();
+            let username = username.to_string();
+
+            tokio::spawn(async move {
+                segment_handler
+                    .match_workout(summary_id, &username, &measurements, &bounding_box)
+                    .await;
+            });
+        }
 
-        summary
+        Ok(summary)
     }
 
     pub fn create_summary(&self, workout: &Workout) -> WorkoutSummary {
-        let readings = workout.measurements.len();
+        let accumulator = SummaryAccumulator::from_workout(self.auto_pause, workout);
 
-        if readings == 0 {
-            return WorkoutSummary {
-                km_ridden: 0.0,
-                ..Default::default()
-            };
+        WorkoutSummary {
+            measurements: workout.measurements.clone(),
+            ..accumulator.summary(workout.km_ridden)
         }
+    }
 
-        let acc_measurements = workout
-            .measurements
-            .clone()
-            .into_iter()
-            .reduce(|acc, e| acc + e)
-            .unwrap();
+    /// Replace the laps of a workout, if it belongs to the given user.
+    pub async fn set_laps(
+        &self,
+        workout_id: i32,
+        username: &str,
+        start_indices: &[u32],
+    ) -> bool {
+        match self.sqlite_handler.get_workout_owner(workout_id).await {
+            Some(owner) if owner == username => {
+                self.sqlite_handler
+                    .replace_laps(workout_id, start_indices)
+                    .await
+            }
+            _ => false,
+        }
+    }
 
-        WorkoutSummary {
-            id: None,
-            km_ridden: workout.km_ridden,
-            avg_speed: acc_measurements.speed / readings as f32,
-            avg_watts: acc_measurements.watts / readings as i32,
-            avg_rpm: acc_measurements.rpm / readings as i32,
-            avg_heartrate: acc_measurements.heartrate / readings as i32,
-            measurements: workout.measurements.clone(),
+    pub async fn weekly_totals(
+        &self,
+        username: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Vec<WeekTotal> {
+        self.sqlite_handler
+            .get_weekly_totals(username, from, to)
+            .await
+    }
+
+    /// Return the aerobic metrics of the user's workouts, oldest first, leaving
+    /// out workouts without heart rate.
+    pub async fn aerobic_trend(
+        &self,
+        username: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Vec<AerobicPoint> {
+        self.analyse_pending(username).await;
+
+        self.sqlite_handler
+            .get_aerobic_trend(username, from, to)
+            .await
+    }
+
+    /// Fit a critical power model to the user's best efforts, if there are
+    /// enough of them.
+    pub async fn critical_power(
+        &self,
+        username: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Option<CriticalPowerModel> {
+        self.analyse_pending(username).await;
+
+        fit(self
+            .sqlite_handler
+            .get_best_mean_max_power(username, from, to)
+            .await)
+    }
+
+    /// Fit a critical power model to the user's workouts of the last 90 days.
+    pub async fn recent_critical_power(
+        &self,
+        username: &str,
+    ) -> Option<CriticalPowerModel> {
+        let from = now() - RECENT_CRITICAL_POWER_WINDOW;
+
+        self.critical_power(username, Some(from), None).await
+    }
+
+    /// Analyse the user's workouts saved before analyses were stored with them.
+    async fn analyse_pending(&self, username: &str) {
+        for workout_id in self.sqlite_handler.get_unanalysed_workouts(username).await {
+            let measurements = self
+                .sqlite_handler
+                .get_measurements(workout_id)
+                .await
+                .unwrap_or_default();
+
+            self.save_analysis(workout_id, &measurements).await;
         }
     }
 
-    pub async fn get_measurements(&self, workout_id: i32) -> Option<Vec<Measurement>> {
+    async fn save_analysis(&self, workout_id: i32, measurements: &[Measurement]) {
+        let mean_max_power: Vec<MeanMaxPower> = MODEL_DURATIONS
+            .into_iter()
+            .filter_map(|duration| {
+                Some(MeanMaxPower {
+                    duration,
+                    watts: mean_max_power(measurements, duration)?,
+                    workout_id,
+                })
+            })
+            .collect();
+
+        self.sqlite_handler
+            .save_workout_analysis(
+                workout_id,
+                &aerobic_metrics(measurements),
+                &mean_max_power,
+            )
+            .await;
+    }
+
+    /// Return the Unix timestamp in seconds a workout started at, if known.
+    pub async fn start_time(&self, workout_id: i32) -> Option<i64> {
+        self.sqlite_handler.get_start_time(workout_id).await
+    }
+
+    /// Return the measurements of a workout, if it belongs to the given user.
+    pub async fn get_measurements(
+        &self,
+        workout_id: i32,
+        username: &str,
+    ) -> Option<Vec<Measurement>> {
+        let owner = self.sqlite_handler.get_workout_owner(workout_id).await?;
+        if owner != username {
+            return None;
+        }
+
         self.sqlite_handler.get_measurements(workout_id).await
     }
 }
+
+/// Seconds since the UNIX epoch
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64)
+}
@@ -5,8 +5,13 @@ pub mod handler;
 pub mod cycling_tracker {
     tonic::include_proto!("cyclingtracker");
 
+    pub use admin_server::AdminServer;
     pub use cycling_tracker_server::CyclingTrackerServer;
+    pub use profile_server::ProfileServer;
+    pub use segments_server::SegmentsServer;
     pub use session_auth_server::SessionAuthServer;
+    pub use sharing_server::SharingServer;
+    pub use workout_plans_server::WorkoutPlansServer;
 }
 
 pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!("fds/cyclingtracker.bin");
@@ -1,4 +1,10 @@
-use std::{pin::Pin, vec::IntoIter};
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+    vec::IntoIter,
+};
 use testcontainers_modules::{
     redis::{Redis, REDIS_PORT},
     testcontainers::{runners::AsyncRunner, ContainerAsync},
@@ -10,17 +16,52 @@ use tokio::{net::TcpListener, task::spawn};
 use tokio_stream::{wrappers::TcpListenerStream, Iter, StreamExt};
 use tonic::{metadata::MetadataValue, transport::channel::Channel, Request};
 
+use cycling_tracker::cycling_tracker::admin_client::AdminClient;
 use cycling_tracker::cycling_tracker::cycling_tracker_client::CyclingTrackerClient;
+use cycling_tracker::cycling_tracker::profile_client::ProfileClient;
+use cycling_tracker::cycling_tracker::segments_client::SegmentsClient;
 use cycling_tracker::cycling_tracker::session_auth_client::SessionAuthClient;
+use cycling_tracker::cycling_tracker::sharing_client::SharingClient;
+use cycling_tracker::cycling_tracker::workout_plans_client::WorkoutPlansClient;
+use cycling_tracker::cycling_tracker::Credentials;
+use cycling_tracker::handler::Notifier;
 use cycling_tracker::App;
 
 pub struct TestEnvironment {
     pub ct_service: CyclingTrackerClient<Channel>,
     pub auth_service: SessionAuthClient<Channel>,
+    pub admin_service: AdminClient<Channel>,
+    pub sharing_service: SharingClient<Channel>,
+    pub plan_service: WorkoutPlansClient<Channel>,
+    pub profile_service: ProfileClient<Channel>,
+    pub segment_service: SegmentsClient<Channel>,
+    pub notifier: Arc<TestNotifier>,
     pub redis_container: ContainerAsync<Redis>,
 }
 
+/// Keeps the last password reset token sent to each user
+#[derive(Default)]
+pub struct TestNotifier {
+    pub reset_tokens: Mutex<HashMap<String, String>>,
+}
+
+impl Notifier for TestNotifier {
+    fn send_password_reset(&self, username: &str, reset_token: &str) {
+        self.reset_tokens
+            .lock()
+            .unwrap()
+            .insert(username.to_string(), reset_token.to_string());
+    }
+}
+
 pub async fn run_test_env(db: SqlitePool) -> TestEnvironment {
+    run_test_env_with_recording_timeout(db, Duration::from_secs(600)).await
+}
+
+pub async fn run_test_env_with_recording_timeout(
+    db: SqlitePool,
+    recording_timeout: Duration,
+) -> TestEnvironment {
     let redis_container = Redis::default().start().await.unwrap();
     let host_ip = redis_container.get_host().await.unwrap();
     let host_port = redis_container
@@ -36,17 +77,25 @@ pub async fn run_test_env(db: SqlitePool) -> TestEnvironment {
         .get_connection()
         .expect("Failed to connect to redis while setting up test env");
 
-    // Add always-valid session-token
-    conn.set::<_, _, ()>("session-token", "user1").unwrap();
+    // Add always-valid session-token, for a user that can't login
+    conn.set::<_, _, ()>("session:session-token", "user1")
+        .unwrap();
+    sqlx::query("INSERT INTO USER (username, password) VALUES ('user1', '')")
+        .execute(&db)
+        .await
+        .unwrap();
 
     let grpc_addr = "127.0.0.1:0";
+    let notifier = Arc::new(TestNotifier::default());
 
     // Build app
     let app = App::builder()
         // Disable TLS and session tokens for test purposes
         .with_db(db)
         .with_redis(redis_client)
-        .setup_grpc(&grpc_addr, false)
+        .with_notifier(notifier.clone())
+        .with_recording_timeout(recording_timeout)
+        .setup_grpc(grpc_addr, false)
         .await
         .expect("Failed to setup gRPC")
         .build()
@@ -69,9 +118,40 @@ pub async fn run_test_env(db: SqlitePool) -> TestEnvironment {
         .await
         .expect("Failed to connect to gRPC CT Server");
 
+    // Get admin service client
+    let admin_service = AdminClient::connect(format!("http://{}", grpc_addr))
+        .await
+        .expect("Failed to connect to gRPC CT Server");
+
+    // Get sharing service client
+    let sharing_service = SharingClient::connect(format!("http://{}", grpc_addr))
+        .await
+        .expect("Failed to connect to gRPC CT Server");
+
+    // Get plan service client
+    let plan_service = WorkoutPlansClient::connect(format!("http://{}", grpc_addr))
+        .await
+        .expect("Failed to connect to gRPC CT Server");
+
+    // Get profile service client
+    let profile_service = ProfileClient::connect(format!("http://{}", grpc_addr))
+        .await
+        .expect("Failed to connect to gRPC CT Server");
+
+    // Get segment service client
+    let segment_service = SegmentsClient::connect(format!("http://{}", grpc_addr))
+        .await
+        .expect("Failed to connect to gRPC CT Server");
+
     TestEnvironment {
         ct_service,
         auth_service,
+        admin_service,
+        sharing_service,
+        plan_service,
+        profile_service,
+        segment_service,
+        notifier,
         redis_container,
     }
 }
@@ -91,8 +171,32 @@ pub fn vec_to_stream<T>(vec: Vec<T>) -> Request<Pin<Box<Iter<IntoIter<T>>>>> {
     with_metadata(Request::new(Box::pin(stream)))
 }
 
-pub fn with_metadata<T>(mut req: Request<T>) -> Request<T> {
-    let token: MetadataValue<_> = "session-token".parse().unwrap();
+pub fn with_metadata<T>(req: Request<T>) -> Request<T> {
+    with_token(req, "session-token")
+}
+
+pub fn with_token<T>(mut req: Request<T>, token: &str) -> Request<T> {
+    let token: MetadataValue<_> = token.parse().unwrap();
     req.metadata_mut().insert("authorization", token);
     req
 }
+
+/// Sign up a new user and return a session token for it
+pub async fn sign_up_and_login(
+    test_env: &mut TestEnvironment,
+    credentials: &Credentials,
+) -> String {
+    test_env
+        .auth_service
+        .sign_up(Request::new(credentials.clone()))
+        .await
+        .expect("Failed to sign up");
+
+    test_env
+        .auth_service
+        .login(Request::new(credentials.clone()))
+        .await
+        .expect("Failed to login")
+        .into_inner()
+        .token
+}
@@ -1,2 +1,6 @@
+pub mod test_admin;
 pub mod test_auth;
 pub mod test_cycling_tracker;
+pub mod test_plan;
+pub mod test_segment;
+pub mod test_sharing;
@@ -0,0 +1,261 @@
+use lazy_static::lazy_static;
+use pretty_assertions::assert_eq;
+use sqlx::SqlitePool;
+use tonic::{Code, Request};
+
+use crate::common::{run_test_env, sign_up_and_login, with_token, TestEnvironment};
+use cycling_tracker::cycling_tracker::{
+    ApiKeyRequest, Credentials, ForceLogoutRequest, ListApiKeysRequest,
+    ListUsersRequest, Role, ServerStatsRequest, User, UserDisabledRequest,
+    UserRoleRequest,
+};
+
+lazy_static! {
+    static ref ADMIN: Credentials = Credentials {
+        username: "Admin".to_string(),
+        password: "AdminPassword".to_string(),
+    };
+    static ref ATHLETE: Credentials = Credentials {
+        username: "Athlete".to_string(),
+        password: "AthletePassword".to_string(),
+    };
+}
+
+/// Sign up an admin and an athlete, returning their session tokens
+async fn setup_users(
+    db: &SqlitePool,
+    test_env: &mut TestEnvironment,
+) -> (String, String) {
+    let athlete_token = sign_up_and_login(test_env, &ATHLETE).await;
+
+    test_env
+        .auth_service
+        .sign_up(Request::new((*ADMIN).clone()))
+        .await
+        .expect("Failed to sign up");
+
+    // There's no RPC to create the first admin
+    sqlx::query("UPDATE USER SET role = 'admin' WHERE username = $1")
+        .bind(&ADMIN.username)
+        .execute(db)
+        .await
+        .unwrap();
+
+    let admin_token = test_env
+        .auth_service
+        .login(Request::new((*ADMIN).clone()))
+        .await
+        .expect("Failed to login")
+        .into_inner()
+        .token;
+
+    (admin_token, athlete_token)
+}
+
+#[sqlx::test]
+async fn test_list_users_and_set_role(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (admin_token, _) = setup_users(&db, &mut test_env).await;
+
+    test_env
+        .admin_service
+        .set_user_role(with_token(
+            Request::new(UserRoleRequest {
+                username: ATHLETE.username.clone(),
+                role: Role::Coach.into(),
+            }),
+            &admin_token,
+        ))
+        .await
+        .expect("Failed to set role");
+
+    let response = test_env
+        .admin_service
+        .list_users(with_token(Request::new(ListUsersRequest {}), &admin_token))
+        .await
+        .expect("Failed to list users")
+        .into_inner();
+
+    assert_eq!(
+        response.users,
+        vec![
+            User {
+                username: ADMIN.username.clone(),
+                role: Role::Admin.into(),
+                disabled: false,
+            },
+            User {
+                username: ATHLETE.username.clone(),
+                role: Role::Coach.into(),
+                disabled: false,
+            },
+            User {
+                username: "user1".to_string(),
+                role: Role::Athlete.into(),
+                disabled: false,
+            },
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn test_admin_only(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (_, athlete_token) = setup_users(&db, &mut test_env).await;
+
+    let response = test_env
+        .admin_service
+        .list_users(with_token(
+            Request::new(ListUsersRequest {}),
+            &athlete_token,
+        ))
+        .await
+        .expect_err("Athlete used the admin service");
+
+    assert_eq!(response.code(), Code::PermissionDenied);
+
+    let response = test_env
+        .admin_service
+        .get_server_stats(Request::new(ServerStatsRequest {}))
+        .await
+        .expect_err("Admin service used without a session");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+}
+
+#[sqlx::test]
+async fn test_disable_user(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (admin_token, athlete_token) = setup_users(&db, &mut test_env).await;
+
+    let api_key = test_env
+        .auth_service
+        .create_api_key(with_token(
+            Request::new(ApiKeyRequest {
+                name: "Trainer bridge".to_string(),
+                scopes: vec!["workouts:read".to_string()],
+            }),
+            &athlete_token,
+        ))
+        .await
+        .expect("Failed to create API key")
+        .into_inner()
+        .key;
+
+    test_env
+        .admin_service
+        .set_user_disabled(with_token(
+            Request::new(UserDisabledRequest {
+                username: ATHLETE.username.clone(),
+                disabled: true,
+            }),
+            &admin_token,
+        ))
+        .await
+        .expect("Failed to disable user");
+
+    // Existing sessions are ended
+    let response = test_env
+        .auth_service
+        .list_api_keys(with_token(
+            Request::new(ListApiKeysRequest {}),
+            &athlete_token,
+        ))
+        .await
+        .expect_err("Session of disabled user still valid");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+
+    let response = test_env
+        .auth_service
+        .login(Request::new((*ATHLETE).clone()))
+        .await
+        .expect_err("Disabled user logged in");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+
+    let response = test_env
+        .ct_service
+        .get_measurements(with_token(Request::new(Default::default()), &api_key))
+        .await
+        .expect_err("API key of disabled user still valid");
+
+    assert_eq!(response.code(), Code::PermissionDenied);
+
+    test_env
+        .admin_service
+        .set_user_disabled(with_token(
+            Request::new(UserDisabledRequest {
+                username: ATHLETE.username.clone(),
+                disabled: false,
+            }),
+            &admin_token,
+        ))
+        .await
+        .expect("Failed to enable user");
+
+    test_env
+        .auth_service
+        .login(Request::new((*ATHLETE).clone()))
+        .await
+        .expect("Failed to login after being enabled");
+}
+
+#[sqlx::test]
+async fn test_force_logout(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (admin_token, athlete_token) = setup_users(&db, &mut test_env).await;
+
+    test_env
+        .admin_service
+        .force_logout(with_token(
+            Request::new(ForceLogoutRequest {
+                username: ATHLETE.username.clone(),
+            }),
+            &admin_token,
+        ))
+        .await
+        .expect("Failed to force logout");
+
+    let response = test_env
+        .auth_service
+        .list_api_keys(with_token(
+            Request::new(ListApiKeysRequest {}),
+            &athlete_token,
+        ))
+        .await
+        .expect_err("Session still valid after force logout");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+
+    // Other users are not affected
+    test_env
+        .auth_service
+        .list_api_keys(with_token(
+            Request::new(ListApiKeysRequest {}),
+            &admin_token,
+        ))
+        .await
+        .expect("Admin session ended");
+}
+
+#[sqlx::test]
+async fn test_server_stats(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (admin_token, _) = setup_users(&db, &mut test_env).await;
+
+    let stats = test_env
+        .admin_service
+        .get_server_stats(with_token(
+            Request::new(ServerStatsRequest {}),
+            &admin_token,
+        ))
+        .await
+        .expect("Failed to get server stats")
+        .into_inner();
+
+    // Includes the user of the always-valid session token
+    assert_eq!(stats.user_count, 3);
+    assert_eq!(stats.workout_count, 0);
+    assert!(stats.storage_used > 0);
+}
@@ -1,10 +1,18 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use lazy_static::lazy_static;
 use pretty_assertions::assert_eq;
 use sqlx::SqlitePool;
 use tonic::{Code, Request};
 
-use crate::common::run_test_env;
-use cycling_tracker::cycling_tracker::{Credentials, SignUpResult};
+use crate::common::{run_test_env, sign_up_and_login, with_token, TestEnvironment};
+use cycling_tracker::cycling_tracker::{
+    ApiKeyRequest, ApiKeyResult, Credentials, ListApiKeysRequest, MeasurementsRequest,
+    PasswordReset, PasswordResetRequest, PasswordResetResult, RevokeApiKeyRequest,
+    SignUpResult, TotpChallengeResponse, TotpCode, TotpEnrollment,
+    TotpEnrollmentRequest, TotpResult, Workout,
+};
+use cycling_tracker::handler::totp::generate_code;
 
 lazy_static! {
     static ref CREDENTIALS: Credentials = Credentials {
@@ -67,3 +75,430 @@ async fn test_invalid_credentials(db: SqlitePool) {
 
     assert_eq!(response.code(), Code::Unauthenticated);
 }
+
+#[sqlx::test]
+async fn test_password_reset(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+    let session_token = sign_up_and_login(&mut test_env, &CREDENTIALS).await;
+
+    let response = test_env
+        .auth_service
+        .request_password_reset(Request::new(PasswordResetRequest {
+            username: CREDENTIALS.username.clone(),
+        }))
+        .await
+        .expect("Failed to request password reset")
+        .into_inner();
+
+    assert_eq!(response, PasswordResetResult { result: true });
+
+    let reset_token = test_env
+        .notifier
+        .reset_tokens
+        .lock()
+        .unwrap()
+        .get(&CREDENTIALS.username)
+        .cloned()
+        .expect("Reset token wasn't sent");
+
+    let reset = PasswordReset {
+        username: CREDENTIALS.username.clone(),
+        reset_token,
+        new_password: "NewPassword".to_string(),
+    };
+
+    test_env
+        .auth_service
+        .confirm_password_reset(Request::new(reset.clone()))
+        .await
+        .expect("Failed to reset password");
+
+    // Reset tokens can only be used once
+    let response = test_env
+        .auth_service
+        .confirm_password_reset(Request::new(reset))
+        .await
+        .expect_err("Reset token was used twice");
+
+    assert_eq!(response.code(), Code::PermissionDenied);
+
+    // Sessions started before the reset are ended
+    let response = test_env
+        .auth_service
+        .list_api_keys(with_token(
+            Request::new(ListApiKeysRequest {}),
+            &session_token,
+        ))
+        .await
+        .expect_err("Session survived the password reset");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+
+    let response = test_env
+        .auth_service
+        .login(Request::new((*CREDENTIALS).clone()))
+        .await
+        .expect_err("Login with old password succeeded");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+
+    test_env
+        .auth_service
+        .login(Request::new(Credentials {
+            username: CREDENTIALS.username.clone(),
+            password: "NewPassword".to_string(),
+        }))
+        .await
+        .expect("Failed to login with new password");
+}
+
+#[sqlx::test]
+async fn test_password_reset_invalid_token(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    test_env
+        .auth_service
+        .sign_up(Request::new((*CREDENTIALS).clone()))
+        .await
+        .expect("Failed to sign up");
+
+    let response = test_env
+        .auth_service
+        .confirm_password_reset(Request::new(PasswordReset {
+            username: CREDENTIALS.username.clone(),
+            reset_token: "not-a-token".to_string(),
+            new_password: "NewPassword".to_string(),
+        }))
+        .await
+        .expect_err("Password reset without a token succeeded");
+
+    assert_eq!(response.code(), Code::PermissionDenied);
+}
+
+fn current_code(secret: &str) -> String {
+    code_in(secret, 0)
+}
+
+/// The code of the next time step, which is accepted as clock drift after the
+/// current code was used
+fn next_code(secret: &str) -> String {
+    code_in(secret, 30)
+}
+
+fn code_in(secret: &str, seconds: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    generate_code(secret, now + seconds).unwrap()
+}
+
+async fn enable_totp(test_env: &mut TestEnvironment) -> TotpEnrollment {
+    let session_token = sign_up_and_login(test_env, &CREDENTIALS).await;
+
+    let enrollment = test_env
+        .auth_service
+        .enroll_totp(with_token(
+            Request::new(TotpEnrollmentRequest {}),
+            &session_token,
+        ))
+        .await
+        .expect("Failed to enroll TOTP")
+        .into_inner();
+
+    assert!(enrollment.provisioning_uri.starts_with("otpauth://totp/"));
+    assert_eq!(enrollment.recovery_codes.len(), 8);
+
+    let response = test_env
+        .auth_service
+        .confirm_totp(with_token(
+            Request::new(TotpCode {
+                code: current_code(&enrollment.secret),
+            }),
+            &session_token,
+        ))
+        .await
+        .expect("Failed to confirm TOTP")
+        .into_inner();
+
+    assert_eq!(response, TotpResult { result: true });
+
+    enrollment
+}
+
+#[sqlx::test]
+async fn test_totp_login(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+    let enrollment = enable_totp(&mut test_env).await;
+
+    let challenge = test_env
+        .auth_service
+        .login(Request::new((*CREDENTIALS).clone()))
+        .await
+        .expect("Failed to login")
+        .into_inner();
+
+    assert!(challenge.second_factor_required);
+
+    // Challenge tokens are not session tokens
+    let response = test_env
+        .auth_service
+        .enroll_totp(with_token(
+            Request::new(TotpEnrollmentRequest {}),
+            &challenge.token,
+        ))
+        .await
+        .expect_err("Challenge token accepted as session token");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+
+    // The current code confirmed the enrollment, so logging in takes the next one
+    let session = test_env
+        .auth_service
+        .verify_totp(Request::new(TotpChallengeResponse {
+            challenge_token: challenge.token.clone(),
+            code: next_code(&enrollment.secret),
+        }))
+        .await
+        .expect("Failed to verify TOTP code")
+        .into_inner();
+
+    assert!(!session.second_factor_required);
+    assert_ne!(session.token, challenge.token);
+
+    // Codes can't be replayed
+    let challenge = test_env
+        .auth_service
+        .login(Request::new((*CREDENTIALS).clone()))
+        .await
+        .expect("Failed to login")
+        .into_inner();
+
+    let response = test_env
+        .auth_service
+        .verify_totp(Request::new(TotpChallengeResponse {
+            challenge_token: challenge.token,
+            code: next_code(&enrollment.secret),
+        }))
+        .await
+        .expect_err("Replayed TOTP code accepted");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+
+    // Two-factor authentication can't be enrolled twice
+    let response = test_env
+        .auth_service
+        .enroll_totp(with_token(
+            Request::new(TotpEnrollmentRequest {}),
+            &session.token,
+        ))
+        .await
+        .expect_err("TOTP enrolled twice");
+
+    assert_eq!(response.code(), Code::FailedPrecondition);
+}
+
+#[sqlx::test]
+async fn test_totp_provisioning_uri(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+    let session_token = sign_up_and_login(
+        &mut test_env,
+        &Credentials {
+            username: "Jane Doe:1?".to_string(),
+            password: "Password".to_string(),
+        },
+    )
+    .await;
+
+    let enrollment = test_env
+        .auth_service
+        .enroll_totp(with_token(
+            Request::new(TotpEnrollmentRequest {}),
+            &session_token,
+        ))
+        .await
+        .expect("Failed to enroll TOTP")
+        .into_inner();
+
+    // Usernames are percent-encoded in the label
+    assert_eq!(
+        enrollment.provisioning_uri,
+        format!(
+            "otpauth://totp/CyclingTracker:Jane%20Doe%3A1%3F?secret={}&issuer=CyclingTracker",
+            enrollment.secret
+        )
+    );
+}
+
+#[sqlx::test]
+async fn test_totp_recovery_code(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+    let enrollment = enable_totp(&mut test_env).await;
+
+    for expected_code in [Code::Ok, Code::Unauthenticated] {
+        let challenge = test_env
+            .auth_service
+            .login(Request::new((*CREDENTIALS).clone()))
+            .await
+            .expect("Failed to login")
+            .into_inner();
+
+        let response = test_env
+            .auth_service
+            .verify_totp(Request::new(TotpChallengeResponse {
+                challenge_token: challenge.token,
+                code: enrollment.recovery_codes[0].clone(),
+            }))
+            .await;
+
+        // Recovery codes can only be used once
+        let code = response.map_or_else(|status| status.code(), |_| Code::Ok);
+        assert_eq!(code, expected_code);
+    }
+}
+
+#[sqlx::test]
+async fn test_totp_invalid_code(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+    let enrollment = enable_totp(&mut test_env).await;
+
+    let challenge = test_env
+        .auth_service
+        .login(Request::new((*CREDENTIALS).clone()))
+        .await
+        .expect("Failed to login")
+        .into_inner();
+
+    let response = test_env
+        .auth_service
+        .verify_totp(Request::new(TotpChallengeResponse {
+            challenge_token: challenge.token.clone(),
+            code: "not-a-code".to_string(),
+        }))
+        .await
+        .expect_err("Invalid TOTP code accepted");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+
+    // The challenge is invalidated after a failed attempt
+    let response = test_env
+        .auth_service
+        .verify_totp(Request::new(TotpChallengeResponse {
+            challenge_token: challenge.token,
+            code: current_code(&enrollment.secret),
+        }))
+        .await
+        .expect_err("Challenge token reused after a failed attempt");
+
+    assert_eq!(response.code(), Code::Unauthenticated);
+}
+
+#[sqlx::test]
+async fn test_api_key(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+    let session_token = sign_up_and_login(&mut test_env, &CREDENTIALS).await;
+
+    let created = test_env
+        .auth_service
+        .create_api_key(with_token(
+            Request::new(ApiKeyRequest {
+                name: "Trainer bridge".to_string(),
+                scopes: vec!["workouts:write".to_string()],
+            }),
+            &session_token,
+        ))
+        .await
+        .expect("Failed to create API key")
+        .into_inner();
+
+    let api_key = created.api_key.unwrap();
+    assert_eq!(api_key.name, "Trainer bridge");
+
+    let listed = test_env
+        .auth_service
+        .list_api_keys(with_token(
+            Request::new(ListApiKeysRequest {}),
+            &session_token,
+        ))
+        .await
+        .expect("Failed to list API keys")
+        .into_inner();
+
+    assert_eq!(listed.api_keys, vec![api_key.clone()]);
+
+    test_env
+        .ct_service
+        .save_workout(with_token(Request::new(Workout::default()), &created.key))
+        .await
+        .expect("Failed to save workout with API key");
+
+    // The key was not granted workouts:read
+    let response = test_env
+        .ct_service
+        .get_measurements(with_token(
+            Request::new(MeasurementsRequest {
+                id: 1,
+                athlete: None,
+                ..Default::default()
+            }),
+            &created.key,
+        ))
+        .await
+        .expect_err("API key used outside of its scopes");
+
+    assert_eq!(response.code(), Code::PermissionDenied);
+
+    // API keys can't manage the account
+    let response = test_env
+        .auth_service
+        .list_api_keys(with_token(
+            Request::new(ListApiKeysRequest {}),
+            &created.key,
+        ))
+        .await
+        .expect_err("API key used to manage the account");
+
+    assert_eq!(response.code(), Code::PermissionDenied);
+
+    let response = test_env
+        .auth_service
+        .revoke_api_key(with_token(
+            Request::new(RevokeApiKeyRequest { id: api_key.id }),
+            &session_token,
+        ))
+        .await
+        .expect("Failed to revoke API key")
+        .into_inner();
+
+    assert_eq!(response, ApiKeyResult { result: true });
+
+    let response = test_env
+        .ct_service
+        .save_workout(with_token(Request::new(Workout::default()), &created.key))
+        .await
+        .expect_err("Revoked API key accepted");
+
+    assert_eq!(response.code(), Code::PermissionDenied);
+}
+
+#[sqlx::test]
+async fn test_api_key_invalid_scope(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+    let session_token = sign_up_and_login(&mut test_env, &CREDENTIALS).await;
+
+    for scopes in [vec![], vec!["account".to_string()]] {
+        let response = test_env
+            .auth_service
+            .create_api_key(with_token(
+                Request::new(ApiKeyRequest {
+                    name: "Trainer bridge".to_string(),
+                    scopes,
+                }),
+                &session_token,
+            ))
+            .await
+            .expect_err("API key created with invalid scopes");
+
+        assert_eq!(response.code(), Code::InvalidArgument);
+    }
+}
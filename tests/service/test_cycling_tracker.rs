@@ -1,11 +1,28 @@
+use std::time::Duration;
+
 use lazy_static::lazy_static;
 use pretty_assertions::assert_eq;
 use sqlx::SqlitePool;
-use tonic::Request;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request};
 
-use crate::common::{run_test_env, stream_to_vec, vec_to_stream, with_metadata};
+use crate::common::{
+    run_test_env, run_test_env_with_recording_timeout, stream_to_vec, vec_to_stream,
+    with_metadata, TestEnvironment,
+};
 use cycling_tracker::cycling_tracker::{
-    Measurement, Workout, WorkoutRequest, WorkoutSummary,
+    ghost_race_request, live_recording_request, live_recording_response,
+    measurements_request::Range, AerobicPoint, AerobicTrendRequest, Aggregation,
+    BoundingBox, ChannelSummary, ChannelValue, CleanedSample, CleaningReason,
+    CriticalPowerRequest, DetectIntervalsRequest, DetectedInterval, FinishMarker,
+    GhostAlignment, GhostRaceRequest, GhostRaceStart, GhostUpdate, IndexRange,
+    IntervalKind, LapMarker, LapSummary, LiveRecordingRequest, LiveRecordingResponse,
+    MeanMaxPower, Measurement, MeasurementField, MeasurementsRequest, PauseMarker,
+    RecordingAck, RecordingStatusRequest, ResumeMarker, Route, Sex, TimeRange,
+    UserProfile, WeekTotal, WeeklyTotalsRequest, Workout, WorkoutRequest,
+    WorkoutSummary,
 };
 
 lazy_static! {
@@ -15,18 +32,21 @@ lazy_static! {
             watts: 290,
             rpm: 90,
             heartrate: 130,
+            ..Default::default()
         },
         Measurement {
             speed: 30.0,
             watts: 300,
             rpm: 95,
             heartrate: 140,
+            ..Default::default()
         },
         Measurement {
             speed: 31.0,
             watts: 310,
             rpm: 100,
             heartrate: 150,
+            ..Default::default()
         },
     ];
     static ref WORKOUT_SUMMARY: WorkoutSummary = WorkoutSummary {
@@ -37,9 +57,54 @@ lazy_static! {
         avg_rpm: 95,
         avg_heartrate: 140,
         measurements: (*MEASUREMENTS).clone(),
+        laps: vec![],
+        elapsed_time: 3,
+        moving_time: 3,
+        moving_avg_speed: 30.0,
+        moving_avg_watts: 300,
+        moving_avg_rpm: 95,
+        moving_avg_heartrate: 140,
+        paused: false,
+        stddev_speed: 0.8164966,
+        stddev_watts: 8,
+        stddev_rpm: 4,
+        stddev_heartrate: 8,
+        avg_left_right_balance: None,
+        avg_left_torque_effectiveness: None,
+        avg_right_torque_effectiveness: None,
+        avg_left_pedal_smoothness: None,
+        avg_right_pedal_smoothness: None,
+        avg_left_watts: None,
+        avg_right_watts: None,
+        channels: vec![],
+        total_ascent: None,
+        total_descent: None,
+        max_grade: None,
+        bounding_box: None,
+        work: 0.9,
+        calories: Some(0.8962715),
+        start_time: 0,
+        efficiency_factor: Some(2.142857),
+        decoupling: Some(5.707483),
+        heartrate_drift: Some(11.538462),
+        w_prime_balance: None,
+        cleaned_samples: vec![],
+        nonzero_avg_watts: 300,
+        nonzero_avg_rpm: 95,
+        nonzero_avg_heartrate: 140,
     };
 }
 
+/// Saved summaries start relative to when they're saved
+fn without_start_time(summary: WorkoutSummary) -> WorkoutSummary {
+    assert!(summary.start_time > 0);
+
+    WorkoutSummary {
+        start_time: 0,
+        ..summary
+    }
+}
+
 #[sqlx::test]
 async fn test_save_workout_and_get_measurements(db: SqlitePool) {
     let mut test_env = run_test_env(db).await;
@@ -47,6 +112,9 @@ async fn test_save_workout_and_get_measurements(db: SqlitePool) {
     let save_request = with_metadata(Request::new(Workout {
         km_ridden: 53.5,
         measurements: (*MEASUREMENTS).clone(),
+        athlete: None,
+        laps: vec![],
+        start_time: None,
     }));
 
     let actual_response = test_env
@@ -56,9 +124,13 @@ async fn test_save_workout_and_get_measurements(db: SqlitePool) {
         .expect("Failed to save workout")
         .into_inner();
 
-    assert_eq!(actual_response, *WORKOUT_SUMMARY);
+    assert_eq!(without_start_time(actual_response), *WORKOUT_SUMMARY);
 
-    let get_request = with_metadata(Request::new(WorkoutRequest { id: 1 }));
+    let get_request = with_metadata(Request::new(MeasurementsRequest {
+        id: 1,
+        athlete: None,
+        ..Default::default()
+    }));
 
     let response_stream = test_env
         .ct_service
@@ -84,7 +156,12 @@ async fn test_record_workout(db: SqlitePool) {
         .expect("Failed to record workout")
         .into_inner();
 
-    assert_eq!(actual_response, *WORKOUT_SUMMARY);
+    // Recordings are saved with the distance ridden in their measurements
+    let expected_response = WorkoutSummary {
+        km_ridden: 90.0,
+        ..WORKOUT_SUMMARY.clone()
+    };
+    assert_eq!(without_start_time(actual_response), expected_response);
 }
 
 #[sqlx::test]
@@ -113,7 +190,43 @@ async fn test_get_current_averages(db: SqlitePool) {
                 watts: 290,
                 rpm: 90,
                 heartrate: 130,
+                ..Default::default()
             }],
+            laps: vec![],
+            elapsed_time: 1,
+            moving_time: 1,
+            moving_avg_speed: 29.0,
+            moving_avg_watts: 290,
+            moving_avg_rpm: 90,
+            moving_avg_heartrate: 130,
+            paused: false,
+            stddev_speed: 0.0,
+            stddev_watts: 0,
+            stddev_rpm: 0,
+            stddev_heartrate: 0,
+            avg_left_right_balance: None,
+            avg_left_torque_effectiveness: None,
+            avg_right_torque_effectiveness: None,
+            avg_left_pedal_smoothness: None,
+            avg_right_pedal_smoothness: None,
+            avg_left_watts: None,
+            avg_right_watts: None,
+            channels: vec![],
+            total_ascent: None,
+            total_descent: None,
+            max_grade: None,
+            bounding_box: None,
+            work: 0.29,
+            calories: None,
+            start_time: 0,
+            efficiency_factor: None,
+            decoupling: None,
+            heartrate_drift: None,
+            w_prime_balance: None,
+            cleaned_samples: vec![],
+            nonzero_avg_watts: 290,
+            nonzero_avg_rpm: 90,
+            nonzero_avg_heartrate: 130,
         },
         WorkoutSummary {
             id: None,
@@ -128,14 +241,51 @@ async fn test_get_current_averages(db: SqlitePool) {
                     watts: 290,
                     rpm: 90,
                     heartrate: 130,
+                    ..Default::default()
                 },
                 Measurement {
                     speed: 30.0,
                     watts: 300,
                     rpm: 95,
                     heartrate: 140,
+                    ..Default::default()
                 },
             ],
+            laps: vec![],
+            elapsed_time: 2,
+            moving_time: 2,
+            moving_avg_speed: 29.5,
+            moving_avg_watts: 295,
+            moving_avg_rpm: 92,
+            moving_avg_heartrate: 135,
+            paused: false,
+            stddev_speed: 0.5,
+            stddev_watts: 5,
+            stddev_rpm: 3,
+            stddev_heartrate: 5,
+            avg_left_right_balance: None,
+            avg_left_torque_effectiveness: None,
+            avg_right_torque_effectiveness: None,
+            avg_left_pedal_smoothness: None,
+            avg_right_pedal_smoothness: None,
+            avg_left_watts: None,
+            avg_right_watts: None,
+            channels: vec![],
+            total_ascent: None,
+            total_descent: None,
+            max_grade: None,
+            bounding_box: None,
+            work: 0.59,
+            calories: None,
+            start_time: 0,
+            efficiency_factor: None,
+            decoupling: None,
+            heartrate_drift: None,
+            w_prime_balance: None,
+            cleaned_samples: vec![],
+            nonzero_avg_watts: 295,
+            nonzero_avg_rpm: 92,
+            nonzero_avg_heartrate: 135,
         },
         WorkoutSummary {
             id: None,
@@ -150,23 +300,1624 @@ async fn test_get_current_averages(db: SqlitePool) {
                     watts: 290,
                     rpm: 90,
                     heartrate: 130,
+                    ..Default::default()
                 },
                 Measurement {
                     speed: 30.0,
                     watts: 300,
                     rpm: 95,
                     heartrate: 140,
+                    ..Default::default()
                 },
                 Measurement {
                     speed: 31.0,
                     watts: 310,
                     rpm: 100,
                     heartrate: 150,
+                    ..Default::default()
                 },
             ],
+            laps: vec![],
+            elapsed_time: 3,
+            moving_time: 3,
+            moving_avg_speed: 30.0,
+            moving_avg_watts: 300,
+            moving_avg_rpm: 95,
+            moving_avg_heartrate: 140,
+            paused: false,
+            stddev_speed: 0.8164966,
+            stddev_watts: 8,
+            stddev_rpm: 4,
+            stddev_heartrate: 8,
+            avg_left_right_balance: None,
+            avg_left_torque_effectiveness: None,
+            avg_right_torque_effectiveness: None,
+            avg_left_pedal_smoothness: None,
+            avg_right_pedal_smoothness: None,
+            avg_left_watts: None,
+            avg_right_watts: None,
+            channels: vec![],
+            total_ascent: None,
+            total_descent: None,
+            max_grade: None,
+            bounding_box: None,
+            work: 0.9,
+            calories: None,
+            start_time: 0,
+            efficiency_factor: None,
+            decoupling: None,
+            heartrate_drift: None,
+            w_prime_balance: None,
+            cleaned_samples: vec![],
+            nonzero_avg_watts: 300,
+            nonzero_avg_rpm: 95,
+            nonzero_avg_heartrate: 140,
         },
     ];
 
     let actual_response = stream_to_vec(response_stream).await;
     assert_eq!(actual_response, expected_response);
 }
+
+fn with_recording_id<T>(req: Request<T>, recording_id: &str) -> Request<T> {
+    let mut req = with_metadata(req);
+    req.metadata_mut()
+        .insert("recording-id", recording_id.parse().unwrap());
+    req
+}
+
+/// Start recording with the given id, returning the sender of the measurement
+/// stream and the pending call
+fn start_recording(
+    test_env: &TestEnvironment,
+    recording_id: &str,
+) -> (
+    Sender<Measurement>,
+    JoinHandle<Result<tonic::Response<WorkoutSummary>, tonic::Status>>,
+) {
+    let (tx, rx) = channel(8);
+    let request =
+        with_recording_id(Request::new(ReceiverStream::new(rx)), recording_id);
+
+    let mut ct_service = test_env.ct_service.clone();
+    let call = tokio::spawn(async move { ct_service.record_workout(request).await });
+
+    (tx, call)
+}
+
+/// Wait until the given number of measurements have been checkpointed
+async fn wait_for_acknowledged(
+    test_env: &mut TestEnvironment,
+    recording_id: &str,
+    acknowledged: u64,
+) {
+    loop {
+        let status = test_env
+            .ct_service
+            .get_recording_status(with_metadata(Request::new(RecordingStatusRequest {
+                recording_id: recording_id.to_string(),
+            })))
+            .await
+            .map(|response| response.into_inner().acknowledged)
+            .ok();
+
+        if status == Some(acknowledged) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[sqlx::test]
+async fn test_resume_recording(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let (tx, call) = start_recording(&test_env, "resumed-ride");
+    for measurement in &MEASUREMENTS[..2] {
+        tx.send(measurement.clone()).await.unwrap();
+    }
+    wait_for_acknowledged(&mut test_env, "resumed-ride", 2).await;
+
+    // Drop the connection mid-ride
+    call.abort();
+
+    let request = with_recording_id(
+        Request::new(tokio_stream::iter(MEASUREMENTS[2..].to_vec())),
+        "resumed-ride",
+    );
+
+    let actual_response = test_env
+        .ct_service
+        .resume_recording(request)
+        .await
+        .expect("Failed to resume recording")
+        .into_inner();
+
+    let expected_response = WorkoutSummary {
+        km_ridden: 90.0,
+        ..WORKOUT_SUMMARY.clone()
+    };
+    assert_eq!(without_start_time(actual_response), expected_response);
+
+    let status = test_env
+        .ct_service
+        .get_recording_status(with_metadata(Request::new(RecordingStatusRequest {
+            recording_id: "resumed-ride".to_string(),
+        })))
+        .await
+        .expect_err("Recording still open after finishing");
+
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[sqlx::test]
+async fn test_finalize_orphaned_recording(db: SqlitePool) {
+    let mut test_env =
+        run_test_env_with_recording_timeout(db, Duration::from_secs(1)).await;
+
+    let (tx, call) = start_recording(&test_env, "orphaned-ride");
+    tx.send(MEASUREMENTS[0].clone()).await.unwrap();
+    wait_for_acknowledged(&mut test_env, "orphaned-ride", 1).await;
+
+    call.abort();
+
+    let measurements = loop {
+        let response = test_env
+            .ct_service
+            .get_measurements(with_metadata(Request::new(MeasurementsRequest {
+                id: 1,
+                athlete: None,
+                ..Default::default()
+            })))
+            .await;
+
+        if let Ok(response) = response {
+            break stream_to_vec(response.into_inner()).await;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+
+    assert_eq!(measurements, MEASUREMENTS[..1].to_vec());
+
+    let request = with_recording_id(
+        Request::new(tokio_stream::iter(MEASUREMENTS[1..].to_vec())),
+        "orphaned-ride",
+    );
+
+    let status = test_env
+        .ct_service
+        .resume_recording(request)
+        .await
+        .expect_err("Resumed a finalized recording");
+
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[sqlx::test]
+async fn test_live_recording(db: SqlitePool) {
+    use live_recording_request::Event;
+    use live_recording_response::Event as ResponseEvent;
+
+    let mut test_env = run_test_env(db).await;
+
+    let events = vec![
+        Event::Measurement(MEASUREMENTS[0].clone()),
+        Event::Pause(PauseMarker {}),
+        // Ignored while paused
+        Event::Measurement(MEASUREMENTS[1].clone()),
+        Event::Resume(ResumeMarker {}),
+        Event::Measurement(MEASUREMENTS[2].clone()),
+        Event::Lap(LapMarker {}),
+        Event::Finish(FinishMarker {}),
+    ];
+    let request = vec_to_stream(
+        events
+            .into_iter()
+            .map(|event| LiveRecordingRequest { event: Some(event) })
+            .collect(),
+    );
+
+    let response_stream = test_env
+        .ct_service
+        .live_recording(request)
+        .await
+        .expect("Failed to start live recording")
+        .into_inner();
+
+    let ack = |sequence| LiveRecordingResponse {
+        event: Some(ResponseEvent::Ack(RecordingAck { sequence })),
+    };
+    let summary = |summary| LiveRecordingResponse {
+        event: Some(ResponseEvent::Summary(summary)),
+    };
+
+    let expected_response = vec![
+        ack(0),
+        summary(WorkoutSummary {
+            id: None,
+            km_ridden: 29.0,
+            avg_speed: 29.0,
+            avg_watts: 290,
+            avg_rpm: 90,
+            avg_heartrate: 130,
+            measurements: vec![],
+            laps: vec![],
+            elapsed_time: 1,
+            moving_time: 1,
+            moving_avg_speed: 29.0,
+            moving_avg_watts: 290,
+            moving_avg_rpm: 90,
+            moving_avg_heartrate: 130,
+            paused: false,
+            stddev_speed: 0.0,
+            stddev_watts: 0,
+            stddev_rpm: 0,
+            stddev_heartrate: 0,
+            avg_left_right_balance: None,
+            avg_left_torque_effectiveness: None,
+            avg_right_torque_effectiveness: None,
+            avg_left_pedal_smoothness: None,
+            avg_right_pedal_smoothness: None,
+            avg_left_watts: None,
+            avg_right_watts: None,
+            channels: vec![],
+            total_ascent: None,
+            total_descent: None,
+            max_grade: None,
+            bounding_box: None,
+            work: 0.29,
+            calories: None,
+            start_time: 0,
+            efficiency_factor: None,
+            decoupling: None,
+            heartrate_drift: None,
+            w_prime_balance: None,
+            cleaned_samples: vec![],
+            nonzero_avg_watts: 290,
+            nonzero_avg_rpm: 90,
+            nonzero_avg_heartrate: 130,
+        }),
+        ack(1),
+        ack(2),
+        ack(3),
+        ack(4),
+        summary(WorkoutSummary {
+            id: None,
+            km_ridden: 60.0,
+            avg_speed: 30.0,
+            avg_watts: 300,
+            avg_rpm: 95,
+            avg_heartrate: 140,
+            measurements: vec![],
+            laps: vec![],
+            elapsed_time: 2,
+            moving_time: 2,
+            moving_avg_speed: 30.0,
+            moving_avg_watts: 300,
+            moving_avg_rpm: 95,
+            moving_avg_heartrate: 140,
+            paused: false,
+            stddev_speed: 1.0,
+            stddev_watts: 10,
+            stddev_rpm: 5,
+            stddev_heartrate: 10,
+            avg_left_right_balance: None,
+            avg_left_torque_effectiveness: None,
+            avg_right_torque_effectiveness: None,
+            avg_left_pedal_smoothness: None,
+            avg_right_pedal_smoothness: None,
+            avg_left_watts: None,
+            avg_right_watts: None,
+            channels: vec![],
+            total_ascent: None,
+            total_descent: None,
+            max_grade: None,
+            bounding_box: None,
+            work: 0.6,
+            calories: None,
+            start_time: 0,
+            efficiency_factor: None,
+            decoupling: None,
+            heartrate_drift: None,
+            w_prime_balance: None,
+            cleaned_samples: vec![],
+            nonzero_avg_watts: 300,
+            nonzero_avg_rpm: 95,
+            nonzero_avg_heartrate: 140,
+        }),
+        ack(5),
+        ack(6),
+        LiveRecordingResponse {
+            event: Some(ResponseEvent::Saved(WorkoutSummary {
+                id: Some(1),
+                km_ridden: 60.0,
+                avg_speed: 30.0,
+                avg_watts: 300,
+                avg_rpm: 95,
+                avg_heartrate: 140,
+                measurements: vec![MEASUREMENTS[0].clone(), MEASUREMENTS[2].clone()],
+                laps: vec![],
+                elapsed_time: 2,
+                moving_time: 2,
+                moving_avg_speed: 30.0,
+                moving_avg_watts: 300,
+                moving_avg_rpm: 95,
+                moving_avg_heartrate: 140,
+                paused: false,
+                stddev_speed: 1.0,
+                stddev_watts: 10,
+                stddev_rpm: 5,
+                stddev_heartrate: 10,
+                avg_left_right_balance: None,
+                avg_left_torque_effectiveness: None,
+                avg_right_torque_effectiveness: None,
+                avg_left_pedal_smoothness: None,
+                avg_right_pedal_smoothness: None,
+                avg_left_watts: None,
+                avg_right_watts: None,
+                channels: vec![],
+                total_ascent: None,
+                total_descent: None,
+                max_grade: None,
+                bounding_box: None,
+                work: 0.6,
+                calories: Some(0.5975144),
+                start_time: 0,
+                efficiency_factor: Some(2.142857),
+                decoupling: Some(7.356322),
+                heartrate_drift: Some(15.384616),
+                w_prime_balance: None,
+                cleaned_samples: vec![],
+                nonzero_avg_watts: 300,
+                nonzero_avg_rpm: 95,
+                nonzero_avg_heartrate: 140,
+            })),
+        },
+    ];
+
+    let mut actual_response = stream_to_vec(response_stream).await;
+    if let Some(LiveRecordingResponse {
+        event: Some(ResponseEvent::Saved(summary)),
+        ..
+    }) = actual_response.last_mut()
+    {
+        *summary = without_start_time(summary.clone());
+    }
+    assert_eq!(actual_response, expected_response);
+}
+
+#[sqlx::test]
+async fn test_save_workout_with_laps(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+
+    // Out of range lap boundaries are ignored
+    let save_request = with_metadata(Request::new(Workout {
+        km_ridden: 53.5,
+        measurements: (*MEASUREMENTS).clone(),
+        athlete: None,
+        laps: vec![1, 5],
+        start_time: None,
+    }));
+
+    let actual_response = test_env
+        .ct_service
+        .save_workout(save_request)
+        .await
+        .expect("Failed to save workout")
+        .into_inner();
+
+    let expected_laps = vec![
+        LapSummary {
+            start_index: 0,
+            measurement_count: 1,
+            avg_speed: 29.0,
+            avg_watts: 290,
+            avg_rpm: 90,
+            avg_heartrate: 130,
+        },
+        LapSummary {
+            start_index: 1,
+            measurement_count: 2,
+            avg_speed: 30.5,
+            avg_watts: 305,
+            avg_rpm: 97,
+            avg_heartrate: 145,
+        },
+    ];
+    assert_eq!(actual_response.laps, expected_laps);
+
+    let stored_laps: Vec<(i64,)> =
+        sqlx::query_as("SELECT start_index FROM LAP WHERE workout_id = 1")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(stored_laps, vec![(0,), (1,)]);
+}
+
+#[sqlx::test]
+async fn test_get_detected_intervals(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+
+    let watts = |watts| Measurement {
+        speed: 30.0,
+        watts,
+        rpm: 90,
+        heartrate: 140,
+        ..Default::default()
+    };
+
+    // 10s easy, 40s hard with a 2s dropout, 20s easy
+    let mut measurements = vec![watts(100); 10];
+    measurements.extend(vec![watts(300); 15]);
+    measurements.extend(vec![watts(150); 2]);
+    measurements.extend(vec![watts(300); 23]);
+    measurements.extend(vec![watts(100); 20]);
+
+    test_env
+        .ct_service
+        .save_workout(with_metadata(Request::new(Workout {
+            km_ridden: 35.0,
+            measurements,
+            athlete: None,
+            laps: vec![],
+            start_time: None,
+        })))
+        .await
+        .expect("Failed to save workout");
+
+    let actual_response = test_env
+        .ct_service
+        .get_detected_intervals(with_metadata(Request::new(DetectIntervalsRequest {
+            id: 1,
+            athlete: None,
+            ftp: 250,
+            threshold: None,
+            min_duration: None,
+            max_dropout: None,
+            save_as_laps: true,
+        })))
+        .await
+        .expect("Failed to detect intervals")
+        .into_inner();
+
+    let expected_intervals = vec![
+        DetectedInterval {
+            kind: IntervalKind::Recovery.into(),
+            start_index: 0,
+            duration: 10,
+            avg_watts: 100,
+            normalized_power: 100,
+        },
+        DetectedInterval {
+            kind: IntervalKind::Work.into(),
+            start_index: 10,
+            duration: 40,
+            avg_watts: 292,
+            normalized_power: 290,
+        },
+        DetectedInterval {
+            kind: IntervalKind::Recovery.into(),
+            start_index: 50,
+            duration: 20,
+            avg_watts: 100,
+            normalized_power: 100,
+        },
+    ];
+    assert_eq!(actual_response.intervals, expected_intervals);
+
+    let stored_laps: Vec<(i64,)> =
+        sqlx::query_as("SELECT start_index FROM LAP WHERE workout_id = 1")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(stored_laps, vec![(0,), (10,), (50,)]);
+}
+
+#[sqlx::test]
+async fn test_auto_pause(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let riding = Measurement {
+        speed: 30.0,
+        watts: 200,
+        rpm: 90,
+        heartrate: 140,
+        ..Default::default()
+    };
+    let stopped = Measurement {
+        speed: 0.0,
+        watts: 0,
+        rpm: 0,
+        // Heart rate sensor dropout
+        heartrate: 0,
+        ..Default::default()
+    };
+
+    let request = vec_to_stream(vec![
+        riding.clone(),
+        stopped.clone(),
+        stopped.clone(),
+        stopped.clone(),
+        riding.clone(),
+    ]);
+
+    let response_stream = test_env
+        .ct_service
+        .get_current_averages(request)
+        .await
+        .expect("Failed to get current averages")
+        .into_inner();
+
+    let summaries = stream_to_vec(response_stream).await;
+
+    // Pauses after 3 seconds stopped
+    let paused: Vec<bool> = summaries.iter().map(|s| s.paused).collect();
+    assert_eq!(paused, vec![false, false, false, true, false]);
+
+    let summary = summaries.last().unwrap();
+    assert_eq!(summary.elapsed_time, 5);
+    assert_eq!(summary.moving_time, 2);
+    assert_eq!(summary.avg_speed, 12.0);
+    assert_eq!(summary.avg_rpm, 36);
+    assert_eq!(summary.moving_avg_speed, 30.0);
+    assert_eq!(summary.moving_avg_rpm, 90);
+    assert_eq!(summary.moving_avg_heartrate, 140);
+    assert_eq!(summary.avg_watts, 80);
+    assert_eq!(summary.nonzero_avg_watts, 200);
+    assert_eq!(summary.nonzero_avg_rpm, 90);
+    assert_eq!(summary.avg_heartrate, 56);
+    assert_eq!(summary.nonzero_avg_heartrate, 140);
+}
+
+#[sqlx::test]
+async fn test_get_current_averages_without_history(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    // Summing these as i32 would overflow
+    let measurement = Measurement {
+        speed: 30.0,
+        watts: i32::MAX / 2 + 1,
+        rpm: 90,
+        heartrate: 140,
+        ..Default::default()
+    };
+
+    let mut request = vec_to_stream(vec![measurement.clone(); 3]);
+    request
+        .metadata_mut()
+        .insert("omit-measurements", "true".parse().unwrap());
+
+    let response_stream = test_env
+        .ct_service
+        .get_current_averages(request)
+        .await
+        .expect("Failed to get current averages")
+        .into_inner();
+
+    let summaries = stream_to_vec(response_stream).await;
+    assert_eq!(summaries.len(), 3);
+
+    for summary in summaries {
+        assert_eq!(summary.measurements, vec![]);
+        assert_eq!(summary.avg_watts, measurement.watts);
+        assert_eq!(summary.stddev_watts, 0);
+    }
+}
+
+#[sqlx::test]
+async fn test_ghost_race(db: SqlitePool) {
+    use ghost_race_request::Event;
+
+    let mut test_env = run_test_env(db).await;
+
+    // 10 meters every second
+    let at_speed = |speed| Measurement {
+        speed,
+        watts: 200,
+        rpm: 90,
+        heartrate: 140,
+        ..Default::default()
+    };
+    let ghost: Vec<Measurement> = (0..4)
+        .map(|i| Measurement {
+            watts: 200 + i,
+            ..at_speed(36.0)
+        })
+        .collect();
+
+    test_env
+        .ct_service
+        .save_workout(with_metadata(Request::new(Workout {
+            km_ridden: 0.04,
+            measurements: ghost.clone(),
+            athlete: None,
+            laps: vec![],
+            start_time: None,
+        })))
+        .await
+        .expect("Failed to save workout");
+
+    let mut events = vec![Event::Start(GhostRaceStart {
+        workout_id: 1,
+        athlete: None,
+        alignment: GhostAlignment::Distance.into(),
+    })];
+    events.extend(
+        [72.0, 18.0, 18.0, 18.0, 18.0, 72.0]
+            .map(|speed| Event::Measurement(at_speed(speed))),
+    );
+    let request = vec_to_stream(
+        events
+            .into_iter()
+            .map(|event| GhostRaceRequest { event: Some(event) })
+            .collect(),
+    );
+
+    let response_stream = test_env
+        .ct_service
+        .ghost_race(request)
+        .await
+        .expect("Failed to start ghost race")
+        .into_inner();
+
+    let update =
+        |ghost: Option<&Measurement>, distance, ghost_distance, ahead| GhostUpdate {
+            ghost: ghost.cloned(),
+            elapsed_time: 0,
+            distance,
+            ghost_distance,
+            seconds_ahead: ahead,
+            meters_ahead: distance - ghost_distance,
+        };
+    let expected_updates = vec![
+        update(ghost.get(1), 20.0, 10.0, Some(1.0)),
+        update(ghost.get(2), 25.0, 20.0, Some(0.5)),
+        update(ghost.get(2), 30.0, 30.0, Some(0.0)),
+        update(ghost.get(3), 35.0, 40.0, Some(-0.5)),
+        update(ghost.get(3), 40.0, 40.0, Some(-1.0)),
+        // Rode further than the ghost's whole workout
+        update(None, 60.0, 40.0, None),
+    ]
+    .into_iter()
+    .zip(1..)
+    .map(|(update, elapsed_time)| GhostUpdate {
+        elapsed_time,
+        ..update
+    })
+    .collect::<Vec<_>>();
+
+    assert_eq!(stream_to_vec(response_stream).await, expected_updates);
+}
+
+#[sqlx::test]
+async fn test_ghost_race_not_started(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let request = vec_to_stream(vec![GhostRaceRequest {
+        event: Some(ghost_race_request::Event::Measurement(
+            MEASUREMENTS[0].clone(),
+        )),
+    }]);
+
+    let mut response_stream = test_env
+        .ct_service
+        .ghost_race(request)
+        .await
+        .expect("Failed to start ghost race")
+        .into_inner();
+
+    let status = response_stream
+        .message()
+        .await
+        .expect_err("Ghost race ran without a workout");
+    assert_eq!(status.code(), Code::FailedPrecondition);
+}
+
+#[sqlx::test]
+async fn test_optional_measurement_channels(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let measurements = vec![
+        Measurement {
+            speed: 30.0,
+            watts: 200,
+            rpm: 90,
+            heartrate: 140,
+            left_right_balance: Some(50.0),
+            left_torque_effectiveness: Some(80.0),
+            right_torque_effectiveness: Some(70.0),
+            left_pedal_smoothness: Some(20.0),
+            right_pedal_smoothness: Some(22.0),
+            left_watts: Some(100),
+            right_watts: Some(100),
+            channels: vec![],
+            latitude: None,
+            longitude: None,
+            altitude: None,
+            timestamp: None,
+            index: None,
+        },
+        // Dropouts of the power meter's extra channels
+        Measurement {
+            speed: 30.0,
+            watts: 300,
+            rpm: 90,
+            heartrate: 140,
+            ..Default::default()
+        },
+        Measurement {
+            speed: 30.0,
+            watts: 250,
+            rpm: 90,
+            heartrate: 140,
+            left_right_balance: Some(48.0),
+            left_torque_effectiveness: Some(90.0),
+            right_torque_effectiveness: Some(80.0),
+            left_pedal_smoothness: Some(30.0),
+            right_pedal_smoothness: Some(24.0),
+            left_watts: Some(120),
+            right_watts: Some(130),
+            channels: vec![],
+            latitude: None,
+            longitude: None,
+            altitude: None,
+            timestamp: None,
+            index: None,
+        },
+    ];
+
+    let summary = test_env
+        .ct_service
+        .save_workout(with_metadata(Request::new(Workout {
+            km_ridden: 0.025,
+            measurements: measurements.clone(),
+            athlete: None,
+            laps: vec![],
+            start_time: None,
+        })))
+        .await
+        .expect("Failed to save workout")
+        .into_inner();
+
+    assert_eq!(summary.avg_watts, 250);
+    assert_eq!(summary.avg_left_right_balance, Some(49.0));
+    assert_eq!(summary.avg_left_torque_effectiveness, Some(85.0));
+    assert_eq!(summary.avg_right_torque_effectiveness, Some(75.0));
+    assert_eq!(summary.avg_left_pedal_smoothness, Some(25.0));
+    assert_eq!(summary.avg_right_pedal_smoothness, Some(23.0));
+    assert_eq!(summary.avg_left_watts, Some(110));
+    assert_eq!(summary.avg_right_watts, Some(115));
+
+    let response_stream = test_env
+        .ct_service
+        .get_measurements(with_metadata(Request::new(MeasurementsRequest {
+            id: summary.id.unwrap(),
+            athlete: None,
+            ..Default::default()
+        })))
+        .await
+        .expect("Failed to get measurements")
+        .into_inner();
+
+    assert_eq!(stream_to_vec(response_stream).await, measurements);
+}
+
+#[sqlx::test]
+async fn test_custom_channels(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let core_temp = |value| ChannelValue {
+        name: "core_temp".to_string(),
+        value,
+        unit: "°C".to_string(),
+    };
+    let smo2 = |value| ChannelValue {
+        name: "smo2".to_string(),
+        value,
+        unit: "%".to_string(),
+    };
+    let with_channels = |channels| Measurement {
+        channels,
+        ..MEASUREMENTS[0].clone()
+    };
+
+    let measurements = vec![
+        with_channels(vec![core_temp(37.5), smo2(60.0)]),
+        // The SmO2 monitor dropped out
+        with_channels(vec![core_temp(37.75)]),
+        with_channels(vec![core_temp(38.0), smo2(50.0)]),
+    ];
+
+    let summary = test_env
+        .ct_service
+        .save_workout(with_metadata(Request::new(Workout {
+            km_ridden: 0.025,
+            measurements: measurements.clone(),
+            athlete: None,
+            laps: vec![],
+            start_time: None,
+        })))
+        .await
+        .expect("Failed to save workout")
+        .into_inner();
+
+    assert_eq!(
+        summary.channels,
+        vec![
+            ChannelSummary {
+                name: "core_temp".to_string(),
+                unit: "°C".to_string(),
+                min: 37.5,
+                avg: 37.75,
+                max: 38.0,
+            },
+            ChannelSummary {
+                name: "smo2".to_string(),
+                unit: "%".to_string(),
+                min: 50.0,
+                avg: 55.0,
+                max: 60.0,
+            },
+        ]
+    );
+
+    let response_stream = test_env
+        .ct_service
+        .get_measurements(with_metadata(Request::new(MeasurementsRequest {
+            id: summary.id.unwrap(),
+            athlete: None,
+            ..Default::default()
+        })))
+        .await
+        .expect("Failed to get measurements")
+        .into_inner();
+
+    assert_eq!(stream_to_vec(response_stream).await, measurements);
+}
+
+#[sqlx::test]
+async fn test_elevation(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    // A 2 meter bump, a climb of 7 meters at 5% and a descent of 4 meters, at 10
+    // meters per second
+    let altitudes = [100.0, 102.0, 100.0]
+        .into_iter()
+        .chain((1..=14).map(|i| 100.0 + 0.5 * i as f32))
+        .chain([106.0, 105.0, 104.0, 103.0]);
+    let measurements = altitudes
+        .map(|altitude| Measurement {
+            speed: 36.0,
+            altitude: Some(altitude),
+            ..Default::default()
+        })
+        .collect();
+
+    let summary = test_env
+        .ct_service
+        .save_workout(with_metadata(Request::new(Workout {
+            km_ridden: 0.21,
+            measurements,
+            athlete: None,
+            laps: vec![],
+            start_time: None,
+        })))
+        .await
+        .expect("Failed to save workout")
+        .into_inner();
+
+    assert_eq!(summary.total_ascent, Some(7.0));
+    assert_eq!(summary.total_descent, Some(4.0));
+    assert!((summary.max_grade.unwrap() - 5.0).abs() < 1e-4);
+    assert_eq!(summary.bounding_box, None);
+}
+
+#[sqlx::test]
+async fn test_get_route(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let at = |latitude, longitude| Measurement {
+        latitude: Some(latitude),
+        longitude: Some(longitude),
+        altitude: Some(250.0),
+        ..MEASUREMENTS[0].clone()
+    };
+    let measurements = vec![
+        at(38.5, -120.2),
+        at(40.7, -120.95),
+        // Positions can drop out, e.g. in tunnels
+        MEASUREMENTS[0].clone(),
+        at(43.252, -126.453),
+    ];
+
+    let summary = test_env
+        .ct_service
+        .save_workout(with_metadata(Request::new(Workout {
+            km_ridden: 0.04,
+            measurements: measurements.clone(),
+            athlete: None,
+            laps: vec![],
+            start_time: None,
+        })))
+        .await
+        .expect("Failed to save workout")
+        .into_inner();
+
+    let bounding_box = BoundingBox {
+        min_latitude: 38.5,
+        min_longitude: -126.453,
+        max_latitude: 43.252,
+        max_longitude: -120.2,
+    };
+    assert_eq!(summary.bounding_box, Some(bounding_box.clone()));
+    assert_eq!(summary.total_ascent, Some(0.0));
+
+    let request = WorkoutRequest {
+        id: summary.id.unwrap(),
+        athlete: None,
+    };
+
+    let route = test_env
+        .ct_service
+        .get_route(with_metadata(Request::new(request.clone())))
+        .await
+        .expect("Failed to get route")
+        .into_inner();
+
+    assert_eq!(
+        route,
+        Route {
+            polyline: "_p~iF~ps|U_ulLnnqC_mqNvxq`@".to_string(),
+            point_count: 3,
+            bounding_box: Some(bounding_box),
+        }
+    );
+
+    let response_stream = test_env
+        .ct_service
+        .get_measurements(with_metadata(Request::new(MeasurementsRequest {
+            id: request.id,
+            athlete: None,
+            ..Default::default()
+        })))
+        .await
+        .expect("Failed to get measurements")
+        .into_inner();
+
+    assert_eq!(stream_to_vec(response_stream).await, measurements);
+}
+
+#[sqlx::test]
+async fn test_energy(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    // Monday, 1 January 2024, in UTC
+    let monday = 1_704_067_200;
+
+    let save = |measurements: Vec<Measurement>, start_time: i64| {
+        let mut ct_service = test_env.ct_service.clone();
+        async move {
+            ct_service
+                .save_workout(with_metadata(Request::new(Workout {
+                    km_ridden: 1.0,
+                    measurements,
+                    athlete: None,
+                    laps: vec![],
+                    start_time: Some(start_time),
+                })))
+                .await
+                .expect("Failed to save workout")
+                .into_inner()
+        }
+    };
+
+    let heart_rate_only = vec![
+        Measurement {
+            speed: 25.0,
+            heartrate: 150,
+            ..Default::default()
+        };
+        60
+    ];
+    let power = vec![
+        Measurement {
+            speed: 30.0,
+            watts: 200,
+            ..Default::default()
+        };
+        10
+    ];
+
+    // Heart rate needs the age, weight and sex of the athlete
+    let summary = save(heart_rate_only.clone(), monday + 3600).await;
+    assert_eq!(summary.work, 0.0);
+    assert_eq!(summary.calories, None);
+
+    let status = test_env
+        .profile_service
+        .update_profile(with_metadata(Request::new(UserProfile {
+            gross_efficiency: Some(1.5),
+            ..Default::default()
+        })))
+        .await
+        .expect_err("Saved a gross efficiency above 1");
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let mut profile = UserProfile {
+        age: Some(30),
+        weight: Some(70.0),
+        gross_efficiency: Some(0.2),
+        ..Default::default()
+    };
+    profile.set_sex(Sex::Male);
+    test_env
+        .profile_service
+        .update_profile(with_metadata(Request::new(profile)))
+        .await
+        .expect("Failed to update profile");
+
+    // About 59.5 kJ per minute at 150 bpm
+    let summary = save(heart_rate_only, monday + 6 * 86400).await;
+    assert!((summary.calories.unwrap() - 14.222).abs() < 0.01);
+
+    let summary = save(power, monday + 7 * 86400 + 10).await;
+    assert_eq!(summary.work, 2.0);
+    assert!((summary.calories.unwrap() - 2.390).abs() < 0.01);
+
+    let totals = test_env
+        .ct_service
+        .get_weekly_totals(with_metadata(Request::new(WeeklyTotalsRequest {
+            athlete: None,
+            from: Some(monday),
+            to: None,
+        })))
+        .await
+        .expect("Failed to get weekly totals")
+        .into_inner();
+
+    let calories: Vec<f32> = totals.weeks.iter().map(|w| w.calories).collect();
+    assert!((calories[0] - 2.390).abs() < 0.01);
+    assert!((calories[1] - 14.222).abs() < 0.01);
+    assert_eq!(
+        totals.weeks,
+        vec![
+            WeekTotal {
+                week_start: monday + 7 * 86400,
+                workout_count: 1,
+                km_ridden: 1.0,
+                elapsed_time: 10,
+                moving_time: 10,
+                work: 2.0,
+                calories: calories[0],
+            },
+            WeekTotal {
+                week_start: monday,
+                workout_count: 2,
+                km_ridden: 2.0,
+                elapsed_time: 120,
+                moving_time: 120,
+                work: 0.0,
+                calories: calories[1],
+            },
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn test_aerobic_trend(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+
+    let steady = |heartrates: [i32; 2]| -> Vec<Measurement> {
+        heartrates
+            .into_iter()
+            .flat_map(|heartrate| {
+                vec![
+                    Measurement {
+                        speed: 30.0,
+                        watts: 200,
+                        rpm: 90,
+                        heartrate,
+                        ..Default::default()
+                    };
+                    60
+                ]
+            })
+            .collect()
+    };
+
+    let mut workout_ids = vec![];
+    for (heartrates, start_time) in
+        [([140, 154], 2000), ([140, 140], 1000), ([0, 0], 1500)]
+    {
+        let summary = test_env
+            .ct_service
+            .save_workout(with_metadata(Request::new(Workout {
+                km_ridden: 1.0,
+                measurements: steady(heartrates),
+                athlete: None,
+                laps: vec![],
+                start_time: Some(start_time),
+            })))
+            .await
+            .expect("Failed to save workout")
+            .into_inner();
+        workout_ids.push(summary.id.unwrap());
+
+        if heartrates == [140, 154] {
+            assert_eq!(summary.efficiency_factor, Some(200.0 / 147.0));
+            assert_eq!(summary.decoupling, Some(9.090912));
+            assert_eq!(summary.heartrate_drift, Some(10.0));
+        }
+    }
+
+    let trend = test_env
+        .ct_service
+        .get_aerobic_trend(with_metadata(Request::new(AerobicTrendRequest {
+            athlete: None,
+            from: None,
+            to: None,
+        })))
+        .await
+        .expect("Failed to get aerobic trend")
+        .into_inner();
+
+    // Oldest first, without the workout lacking heart rate
+    assert_eq!(
+        trend.points,
+        vec![
+            AerobicPoint {
+                workout_id: workout_ids[1],
+                start_time: 1000,
+                efficiency_factor: Some(200.0 / 140.0),
+                decoupling: Some(0.0),
+                heartrate_drift: Some(0.0),
+            },
+            AerobicPoint {
+                workout_id: workout_ids[0],
+                start_time: 2000,
+                efficiency_factor: Some(200.0 / 147.0),
+                decoupling: Some(9.090912),
+                heartrate_drift: Some(10.0),
+            },
+        ]
+    );
+
+    let trend = test_env
+        .ct_service
+        .get_aerobic_trend(with_metadata(Request::new(AerobicTrendRequest {
+            athlete: None,
+            from: Some(1500),
+            to: None,
+        })))
+        .await
+        .expect("Failed to get aerobic trend")
+        .into_inner();
+    assert_eq!(trend.points.len(), 1);
+    assert_eq!(trend.points[0].workout_id, workout_ids[0]);
+
+    // Workouts saved before analyses were stored are analysed when needed
+    sqlx::query(
+        "UPDATE WORKOUT_SUMMARY SET efficiency_factor = NULL, decoupling = NULL,
+        heartrate_drift = NULL, analysed = FALSE",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+
+    let trend = test_env
+        .ct_service
+        .get_aerobic_trend(with_metadata(Request::new(AerobicTrendRequest {
+            athlete: None,
+            from: Some(1500),
+            to: None,
+        })))
+        .await
+        .expect("Failed to get aerobic trend")
+        .into_inner();
+    assert_eq!(trend.points.len(), 1);
+    assert_eq!(trend.points[0].decoupling, Some(9.090912));
+}
+
+#[sqlx::test]
+async fn test_critical_power(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let request = || {
+        with_metadata(Request::new(CriticalPowerRequest {
+            athlete: None,
+            from: Some(1_000_000),
+            to: None,
+        }))
+    };
+
+    let status = test_env
+        .ct_service
+        .get_critical_power(request())
+        .await
+        .expect_err("Fitted a model without workouts");
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    // Efforts on the curve of 250 W critical power and 18 kJ W′, and an older
+    // effort outside the window
+    let efforts = [
+        (120, 400, None),
+        (180, 350, None),
+        (300, 310, None),
+        (600, 280, None),
+        (1200, 265, None),
+        (120, 1000, Some(1000)),
+    ];
+    let mut workout_ids = vec![];
+    for (duration, watts, start_time) in efforts {
+        let summary = test_env
+            .ct_service
+            .save_workout(with_metadata(Request::new(Workout {
+                km_ridden: 1.0,
+                measurements: vec![
+                    Measurement {
+                        speed: 30.0,
+                        watts,
+                        ..Default::default()
+                    };
+                    duration
+                ],
+                athlete: None,
+                laps: vec![],
+                start_time,
+            })))
+            .await
+            .expect("Failed to save workout")
+            .into_inner();
+        workout_ids.push(summary.id.unwrap());
+    }
+
+    let model = test_env
+        .ct_service
+        .get_critical_power(request())
+        .await
+        .expect("Failed to fit critical power")
+        .into_inner();
+
+    assert!((model.critical_power - 250.0).abs() < 0.01);
+    assert!((model.w_prime - 18000.0).abs() < 1.0);
+    assert!((model.r_squared - 1.0).abs() < 1e-6);
+    assert_eq!(
+        model.mean_max_power,
+        efforts[..5]
+            .iter()
+            .zip(workout_ids.iter())
+            .map(|(&(duration, watts, _), &workout_id)| MeanMaxPower {
+                duration: duration as u32,
+                watts: watts as f32,
+                workout_id,
+            })
+            .collect::<Vec<_>>()
+    );
+
+    // Live workouts spend W′ above critical power, and recover below it
+    let measurements = [350, 350, 150].map(|watts| Measurement {
+        speed: 30.0,
+        watts,
+        ..Default::default()
+    });
+    let response_stream = test_env
+        .ct_service
+        .get_current_averages(vec_to_stream(measurements.to_vec()))
+        .await
+        .expect("Failed to get current averages")
+        .into_inner();
+
+    let balances: Vec<f32> = stream_to_vec(response_stream)
+        .await
+        .into_iter()
+        .map(|summary| summary.w_prime_balance.unwrap())
+        .collect();
+    for (balance, expected) in balances.iter().zip([17900.0, 17800.0, 17801.111]) {
+        assert!((balance - expected).abs() < 1.0, "{balance} != {expected}");
+    }
+    assert_eq!(balances.len(), 3);
+}
+
+#[sqlx::test]
+async fn test_data_cleaning(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let measurement = |speed, watts, heartrate| Measurement {
+        speed,
+        watts,
+        rpm: 90,
+        heartrate,
+        ..Default::default()
+    };
+    let mut measurements = vec![
+        measurement(30.0, 200, 140),
+        // Heart rate dropout
+        measurement(30.0, 200, 0),
+        // Power spike during the dropout
+        measurement(30.0, 2000, 0),
+        measurement(-5.0, 200, 146),
+        measurement(30.0, 200, 146),
+        measurement(30.0, 200, 146),
+    ];
+    // The heart rate strap was taken off, which is too long to interpolate
+    measurements.extend(vec![measurement(30.0, 200, 0); 4]);
+
+    let summary = test_env
+        .ct_service
+        .save_workout(with_metadata(Request::new(Workout {
+            km_ridden: 1.0,
+            measurements,
+            athlete: None,
+            laps: vec![],
+            start_time: None,
+        })))
+        .await
+        .expect("Failed to save workout")
+        .into_inner();
+
+    let cleaned =
+        |index, field: MeasurementField, reason: CleaningReason, original, value| {
+            CleanedSample {
+                index,
+                field: field.into(),
+                reason: reason.into(),
+                original_value: original,
+                value,
+            }
+        };
+    assert_eq!(
+        summary.cleaned_samples,
+        vec![
+            cleaned(
+                1,
+                MeasurementField::Heartrate,
+                CleaningReason::Dropout,
+                0.0,
+                142.0
+            ),
+            cleaned(
+                2,
+                MeasurementField::Watts,
+                CleaningReason::Spike,
+                2000.0,
+                200.0
+            ),
+            cleaned(
+                2,
+                MeasurementField::Heartrate,
+                CleaningReason::Dropout,
+                0.0,
+                144.0
+            ),
+            cleaned(
+                3,
+                MeasurementField::Speed,
+                CleaningReason::OutOfRange,
+                -5.0,
+                30.0
+            ),
+        ]
+    );
+    assert_eq!(summary.avg_watts, 200);
+    assert_eq!(summary.avg_speed, 30.0);
+
+    let response_stream = test_env
+        .ct_service
+        .get_measurements(with_metadata(Request::new(MeasurementsRequest {
+            id: summary.id.unwrap(),
+            athlete: None,
+            ..Default::default()
+        })))
+        .await
+        .expect("Failed to get measurements")
+        .into_inner();
+
+    let stored = stream_to_vec(response_stream).await;
+    let heartrates: Vec<i32> = stored.iter().map(|m| m.heartrate).collect();
+    assert_eq!(heartrates, vec![140, 142, 144, 146, 146, 146, 0, 0, 0, 0]);
+    assert!(stored.iter().all(|m| m.watts == 200 && m.speed == 30.0));
+}
+
+#[sqlx::test]
+async fn test_downsampled_measurements(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    // A single peak in power, and steadily rising cadence
+    let measurements: Vec<Measurement> = (0..10)
+        .map(|i| Measurement {
+            speed: 30.0,
+            watts: if i == 3 { 900 } else { 100 },
+            rpm: 80 + i,
+            heartrate: 140,
+            ..Default::default()
+        })
+        .collect();
+
+    let start_time = 1_000_000;
+    let summary = test_env
+        .ct_service
+        .save_workout(with_metadata(Request::new(Workout {
+            km_ridden: 1.0,
+            measurements: measurements.clone(),
+            athlete: None,
+            laps: vec![],
+            start_time: Some(start_time),
+        })))
+        .await
+        .expect("Failed to save workout")
+        .into_inner();
+    let id = summary.id.unwrap();
+
+    let get = |request: MeasurementsRequest| {
+        let mut ct_service = test_env.ct_service.clone();
+        async move {
+            let response_stream = ct_service
+                .get_measurements(with_metadata(Request::new(MeasurementsRequest {
+                    id,
+                    ..request
+                })))
+                .await?
+                .into_inner();
+
+            Ok::<_, tonic::Status>(stream_to_vec(response_stream).await)
+        }
+    };
+    let indices = |measurements: &[Measurement]| -> Vec<u32> {
+        measurements.iter().map(|m| m.index.unwrap()).collect()
+    };
+
+    let mean = get(MeasurementsRequest {
+        max_points: Some(5),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert_eq!(indices(&mean), vec![0, 2, 4, 6, 8]);
+    let cadences: Vec<i32> = mean.iter().map(|m| m.rpm).collect();
+    assert_eq!(cadences, vec![80, 82, 84, 86, 88]);
+    assert_eq!(mean[1].watts, 500);
+
+    let mut request = MeasurementsRequest {
+        max_points: Some(5),
+        ..Default::default()
+    };
+    request.set_aggregation(Aggregation::Max);
+    let max = get(request).await.unwrap();
+    let cadences: Vec<i32> = max.iter().map(|m| m.rpm).collect();
+    assert_eq!(cadences, vec![81, 83, 85, 87, 89]);
+    assert_eq!(max[1].watts, 900);
+
+    // The peak is kept as is
+    let mut request = MeasurementsRequest {
+        max_points: Some(4),
+        ..Default::default()
+    };
+    request.set_aggregation(Aggregation::Lttb);
+    let lttb = get(request).await.unwrap();
+    assert_eq!(indices(&lttb), vec![0, 3, 5, 9]);
+    assert_eq!(lttb[1].watts, 900);
+
+    let range = get(MeasurementsRequest {
+        range: Some(Range::IndexRange(IndexRange {
+            start: 2,
+            end: Some(5),
+        })),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert_eq!(indices(&range), vec![2, 3, 4]);
+    assert_eq!(range[1].watts, 900);
+
+    let range = get(MeasurementsRequest {
+        range: Some(Range::TimeRange(TimeRange {
+            start: start_time + 7,
+            end: None,
+        })),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert_eq!(indices(&range), vec![7, 8, 9]);
+
+    for invalid in [
+        MeasurementsRequest {
+            max_points: Some(0),
+            ..Default::default()
+        },
+        MeasurementsRequest {
+            range: Some(Range::IndexRange(IndexRange {
+                start: 5,
+                end: Some(2),
+            })),
+            ..Default::default()
+        },
+        MeasurementsRequest {
+            range: Some(Range::TimeRange(TimeRange {
+                start: i64::MIN,
+                end: None,
+            })),
+            ..Default::default()
+        },
+    ] {
+        let status = get(invalid).await.expect_err("Accepted an invalid request");
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    // Without options, every measurement is returned as stored
+    assert_eq!(
+        get(MeasurementsRequest::default()).await.unwrap(),
+        measurements
+    );
+}
+
+#[sqlx::test]
+async fn test_resample_to_1hz(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let start = 1_700_000_000_000;
+    let at = |offset: i64, watts| Measurement {
+        speed: 30.0,
+        watts,
+        rpm: 90,
+        heartrate: 140,
+        timestamp: Some(start + offset),
+        ..Default::default()
+    };
+
+    let summary = test_env
+        .ct_service
+        .save_workout(with_metadata(Request::new(Workout {
+            km_ridden: 1.0,
+            measurements: vec![
+                at(0, 100),
+                at(2000, 200),
+                at(2500, 300),
+                at(5000, 400),
+                // The device stopped recording
+                at(60_000, 500),
+            ],
+            athlete: None,
+            // Laps start at the first whole second after them
+            laps: vec![2],
+            start_time: None,
+        })))
+        .await
+        .expect("Failed to save workout")
+        .into_inner();
+
+    assert_eq!(summary.start_time, 1_700_000_000);
+    assert_eq!(summary.elapsed_time, 61);
+    assert_eq!(summary.laps[1].start_index, 3);
+
+    let watts: Vec<i32> = summary.measurements.iter().map(|m| m.watts).collect();
+    assert_eq!(watts[..7], [100, 150, 200, 320, 360, 400, 0]);
+    assert_eq!(watts[60], 500);
+    assert!(watts[6..60].iter().all(|&w| w == 0));
+    assert!(summary.measurements.iter().all(|m| m.timestamp.is_none()));
+
+    // Bogus timestamps would resample to far too many measurements
+    for (first, last) in [(0, 1_000_000_000_000_000), (i64::MIN, i64::MAX)] {
+        let status = test_env
+            .ct_service
+            .save_workout(with_metadata(Request::new(Workout {
+                km_ridden: 1.0,
+                measurements: vec![
+                    Measurement {
+                        timestamp: Some(first),
+                        ..Default::default()
+                    },
+                    Measurement {
+                        timestamp: Some(last),
+                        ..Default::default()
+                    },
+                ],
+                athlete: None,
+                laps: vec![],
+                start_time: None,
+            })))
+            .await
+            .expect_err("Resampled a span of years");
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+}
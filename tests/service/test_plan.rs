@@ -0,0 +1,475 @@
+use pretty_assertions::assert_eq;
+use sqlx::SqlitePool;
+use tonic::{Code, Request};
+
+use crate::common::{
+    run_test_env, stream_to_vec, vec_to_stream, with_metadata, TestEnvironment,
+};
+use cycling_tracker::cycling_tracker::{
+    live_recording_request, live_recording_response,
+    plan_step::{Power, PowerEnd},
+    ExportPlanRequest, FinishMarker, ListPlansRequest, LiveRecordingRequest,
+    Measurement, PlanFile, PlanFileFormat, PlanRequest, PlanResult, PlanStep,
+    PlanTarget, StartPlan, StepCompliance, TargetRange, WorkoutPlan, WorkoutPlanList,
+};
+
+fn sweet_spot_plan() -> WorkoutPlan {
+    WorkoutPlan {
+        id: None,
+        name: "Sweet spot".to_string(),
+        steps: vec![
+            PlanStep {
+                duration: 2,
+                power: Some(Power::Watts(200)),
+                heartrate: None,
+                rpm: None,
+                power_end: None,
+            },
+            PlanStep {
+                duration: 2,
+                power: Some(Power::FtpShare(0.9)),
+                heartrate: Some(TargetRange { min: 140, max: 160 }),
+                rpm: Some(TargetRange { min: 85, max: 95 }),
+                power_end: None,
+            },
+        ],
+    }
+}
+
+async fn create_plan(test_env: &mut TestEnvironment, plan: WorkoutPlan) -> WorkoutPlan {
+    test_env
+        .plan_service
+        .create_plan(with_metadata(Request::new(plan)))
+        .await
+        .expect("Failed to create plan")
+        .into_inner()
+}
+
+#[sqlx::test]
+async fn test_create_list_delete_plan(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let plan = create_plan(&mut test_env, sweet_spot_plan()).await;
+    assert_eq!(
+        plan,
+        WorkoutPlan {
+            id: Some(1),
+            ..sweet_spot_plan()
+        }
+    );
+
+    let stored = test_env
+        .plan_service
+        .get_plan(with_metadata(Request::new(PlanRequest { id: 1 })))
+        .await
+        .expect("Failed to get plan")
+        .into_inner();
+    assert_eq!(stored, plan);
+
+    let plans = test_env
+        .plan_service
+        .list_plans(with_metadata(Request::new(ListPlansRequest {})))
+        .await
+        .expect("Failed to list plans")
+        .into_inner();
+    assert_eq!(plans, WorkoutPlanList { plans: vec![plan] });
+
+    let result = test_env
+        .plan_service
+        .delete_plan(with_metadata(Request::new(PlanRequest { id: 1 })))
+        .await
+        .expect("Failed to delete plan")
+        .into_inner();
+    assert_eq!(result, PlanResult { result: true });
+
+    let status = test_env
+        .plan_service
+        .get_plan(with_metadata(Request::new(PlanRequest { id: 1 })))
+        .await
+        .expect_err("Deleted plan was returned");
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[sqlx::test]
+async fn test_create_invalid_plan(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let mut empty_range = sweet_spot_plan();
+    empty_range.steps[1].rpm = Some(TargetRange { min: 95, max: 85 });
+
+    let mut no_duration = sweet_spot_plan();
+    no_duration.steps[0].duration = 0;
+
+    let no_steps = WorkoutPlan {
+        steps: vec![],
+        ..sweet_spot_plan()
+    };
+
+    for plan in [empty_range, no_duration, no_steps] {
+        let status = test_env
+            .plan_service
+            .create_plan(with_metadata(Request::new(plan)))
+            .await
+            .expect_err("Invalid plan was created");
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+}
+
+#[sqlx::test]
+async fn test_live_recording_with_plan(db: SqlitePool) {
+    use live_recording_request::Event;
+    use live_recording_response::Event as ResponseEvent;
+
+    let mut test_env = run_test_env(db).await;
+    let plan = create_plan(&mut test_env, sweet_spot_plan()).await;
+
+    let measurement = |watts, rpm, heartrate| {
+        Event::Measurement(Measurement {
+            speed: 30.0,
+            watts,
+            rpm,
+            heartrate,
+            ..Default::default()
+        })
+    };
+    let events = vec![
+        Event::StartPlan(StartPlan {
+            plan_id: plan.id.unwrap(),
+            ftp: 250,
+        }),
+        measurement(210, 90, 130),
+        measurement(230, 90, 135),
+        measurement(225, 90, 150),
+        measurement(225, 100, 155),
+        Event::Finish(FinishMarker {}),
+    ];
+    let mut request = vec_to_stream(
+        events
+            .into_iter()
+            .map(|event| LiveRecordingRequest { event: Some(event) })
+            .collect(),
+    );
+    request
+        .metadata_mut()
+        .insert("recording-id", "plan-recording".parse().unwrap());
+
+    let response_stream = test_env
+        .ct_service
+        .live_recording(request)
+        .await
+        .expect("Failed to start live recording")
+        .into_inner();
+
+    // Leave out acks and summaries, which are covered by the recording tests
+    let plan_events: Vec<ResponseEvent> = stream_to_vec(response_stream)
+        .await
+        .into_iter()
+        .filter_map(|response| response.event)
+        .filter(|event| {
+            matches!(
+                event,
+                ResponseEvent::Target(_) | ResponseEvent::Compliance(_)
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        plan_events,
+        vec![
+            ResponseEvent::Target(PlanTarget {
+                step_index: 0,
+                duration: 2,
+                watts: Some(200),
+                heartrate: None,
+                rpm: None,
+                end_watts: None,
+            }),
+            ResponseEvent::Compliance(StepCompliance {
+                step_index: 0,
+                compliance: 0.5,
+                avg_watts: 220,
+                avg_rpm: 90,
+                avg_heartrate: 132,
+            }),
+            ResponseEvent::Target(PlanTarget {
+                step_index: 1,
+                duration: 2,
+                watts: Some(225),
+                heartrate: Some(TargetRange { min: 140, max: 160 }),
+                rpm: Some(TargetRange { min: 85, max: 95 }),
+                end_watts: None,
+            }),
+            ResponseEvent::Compliance(StepCompliance {
+                step_index: 1,
+                compliance: 0.5,
+                avg_watts: 225,
+                avg_rpm: 95,
+                avg_heartrate: 152,
+            }),
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn test_import_zwo(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let content = r#"<workout_file>
+    <author>Coach</author>
+    <name>Over-unders</name>
+    <sportType>bike</sportType>
+    <workout>
+        <Warmup Duration="600" PowerLow="0.5" PowerHigh="0.75"/>
+        <IntervalsT Repeat="2" OnDuration="120" OffDuration="60" OnPower="1.05"
+            OffPower="0.95" Cadence="95" CadenceResting="85"/>
+        <FreeRide Duration="300"/>
+    </workout>
+</workout_file>"#;
+
+    let plan = test_env
+        .plan_service
+        .import_plan(with_metadata(Request::new(PlanFile {
+            format: PlanFileFormat::Zwo.into(),
+            content: content.to_string(),
+            name: None,
+        })))
+        .await
+        .expect("Failed to import plan")
+        .into_inner();
+
+    let step = |duration, power, power_end, rpm| PlanStep {
+        duration,
+        power,
+        heartrate: None,
+        rpm,
+        power_end,
+    };
+    let over = step(
+        120,
+        Some(Power::FtpShare(1.05)),
+        None,
+        Some(TargetRange { min: 90, max: 100 }),
+    );
+    let under = step(
+        60,
+        Some(Power::FtpShare(0.95)),
+        None,
+        Some(TargetRange { min: 80, max: 90 }),
+    );
+
+    assert_eq!(
+        plan,
+        WorkoutPlan {
+            id: Some(1),
+            name: "Over-unders".to_string(),
+            steps: vec![
+                step(
+                    600,
+                    Some(Power::FtpShare(0.5)),
+                    Some(PowerEnd::EndFtpShare(0.75)),
+                    None
+                ),
+                over.clone(),
+                under.clone(),
+                over,
+                under,
+                step(300, None, None, None),
+            ],
+        }
+    );
+}
+
+#[sqlx::test]
+async fn test_import_erg_and_mrc(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let erg = "[COURSE HEADER]
+VERSION = 2
+UNITS = ENGLISH
+DESCRIPTION = Ramp test
+MINUTES WATTS
+[END COURSE HEADER]
+[COURSE DATA]
+0.00\t100
+5.00\t200
+5.00\t250
+6.50\t250
+[END COURSE DATA]
+";
+    let mrc = erg.replace("MINUTES WATTS", "MINUTES PERCENT");
+
+    let mut plans = vec![];
+    for (format, content) in [
+        (PlanFileFormat::Erg, erg.to_string()),
+        (PlanFileFormat::Mrc, mrc),
+    ] {
+        let plan = test_env
+            .plan_service
+            .import_plan(with_metadata(Request::new(PlanFile {
+                format: format.into(),
+                content,
+                name: None,
+            })))
+            .await
+            .expect("Failed to import plan")
+            .into_inner();
+        plans.push(plan);
+    }
+
+    assert_eq!(
+        plans,
+        vec![
+            WorkoutPlan {
+                id: Some(1),
+                name: "Ramp test".to_string(),
+                steps: vec![
+                    PlanStep {
+                        duration: 300,
+                        power: Some(Power::Watts(100)),
+                        heartrate: None,
+                        rpm: None,
+                        power_end: Some(PowerEnd::EndWatts(200)),
+                    },
+                    PlanStep {
+                        duration: 90,
+                        power: Some(Power::Watts(250)),
+                        heartrate: None,
+                        rpm: None,
+                        power_end: None,
+                    },
+                ],
+            },
+            WorkoutPlan {
+                id: Some(2),
+                name: "Ramp test".to_string(),
+                steps: vec![
+                    PlanStep {
+                        duration: 300,
+                        power: Some(Power::FtpShare(1.0)),
+                        heartrate: None,
+                        rpm: None,
+                        power_end: Some(PowerEnd::EndFtpShare(2.0)),
+                    },
+                    PlanStep {
+                        duration: 90,
+                        power: Some(Power::FtpShare(2.5)),
+                        heartrate: None,
+                        rpm: None,
+                        power_end: None,
+                    },
+                ],
+            },
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn test_import_invalid_file(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let files = [
+        (PlanFileFormat::Zwo, "<workout_file><workout>"),
+        (
+            PlanFileFormat::Zwo,
+            "<workout_file><workout><SteadyState Power=\"0.8\"/></workout></workout_file>",
+        ),
+        (PlanFileFormat::Erg, "[COURSE DATA]\n0.00 100\nten 200\n"),
+        (
+            PlanFileFormat::Zwo,
+            "<workout_file><workout><IntervalsT Repeat=\"18446744073709551615\" \
+             OnDuration=\"30\" OffDuration=\"30\" OnPower=\"1.2\" OffPower=\"0.5\"/>\
+             </workout></workout_file>",
+        ),
+    ];
+
+    for (format, content) in files {
+        let status = test_env
+            .plan_service
+            .import_plan(with_metadata(Request::new(PlanFile {
+                format: format.into(),
+                content: content.to_string(),
+                name: None,
+            })))
+            .await
+            .expect_err("Invalid file was imported");
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+}
+
+#[sqlx::test]
+async fn test_export_plan(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    let mut plan = sweet_spot_plan();
+    plan.steps.push(PlanStep {
+        duration: 60,
+        power: Some(Power::FtpShare(0.9)),
+        heartrate: None,
+        rpm: None,
+        power_end: Some(PowerEnd::EndFtpShare(0.5)),
+    });
+    let plan = create_plan(&mut test_env, plan).await;
+
+    let export = |format, ftp| {
+        with_metadata(Request::new(ExportPlanRequest {
+            id: plan.id.unwrap(),
+            format,
+            ftp,
+        }))
+    };
+
+    let zwo = test_env
+        .plan_service
+        .export_plan(export(PlanFileFormat::Zwo.into(), Some(250)))
+        .await
+        .expect("Failed to export plan")
+        .into_inner();
+    assert_eq!(
+        zwo.content,
+        r#"<workout_file>
+    <name>Sweet spot</name>
+    <sportType>bike</sportType>
+    <workout>
+        <SteadyState Duration="2" Power="0.8"/>
+        <SteadyState Duration="2" Power="0.9" Cadence="90"/>
+        <Ramp Duration="60" PowerLow="0.9" PowerHigh="0.5"/>
+    </workout>
+</workout_file>
+"#
+    );
+
+    let erg = test_env
+        .plan_service
+        .export_plan(export(PlanFileFormat::Erg.into(), Some(250)))
+        .await
+        .expect("Failed to export plan")
+        .into_inner();
+    assert_eq!(
+        erg.content,
+        "[COURSE HEADER]
+VERSION = 2
+UNITS = ENGLISH
+DESCRIPTION = Sweet spot
+FILE NAME = Sweet spot
+FTP = 250
+MINUTES WATTS
+[END COURSE HEADER]
+[COURSE DATA]
+0.00\t200
+0.03\t200
+0.03\t225
+0.07\t225
+0.07\t225
+1.07\t125
+[END COURSE DATA]
+"
+    );
+
+    // Converting watts to shares of FTP requires the FTP
+    let status = test_env
+        .plan_service
+        .export_plan(export(PlanFileFormat::Zwo.into(), None))
+        .await
+        .expect_err("Plan was exported without FTP");
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
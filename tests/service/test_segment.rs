@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use pretty_assertions::assert_eq;
+use sqlx::SqlitePool;
+use tonic::{Code, Request};
+
+use crate::common::{
+    run_test_env, sign_up_and_login, with_metadata, with_token, TestEnvironment,
+};
+use cycling_tracker::cycling_tracker::{
+    CreateSegmentRequest, Credentials, Measurement, SegmentEffort, SegmentLeaderboard,
+    SegmentRequest, Workout,
+};
+
+/// A ride heading north, covering the given degrees of latitude every second
+fn ride(step: f64, seconds: usize, watts: i32) -> Vec<Measurement> {
+    (0..seconds)
+        .map(|i| Measurement {
+            speed: 30.0,
+            watts,
+            latitude: Some(i as f64 * step),
+            longitude: Some(0.0),
+            ..Default::default()
+        })
+        .collect()
+}
+
+async fn save_workout(
+    test_env: &mut TestEnvironment,
+    token: &str,
+    measurements: Vec<Measurement>,
+) -> i32 {
+    test_env
+        .ct_service
+        .save_workout(with_token(
+            Request::new(Workout {
+                km_ridden: 0.3,
+                measurements,
+                athlete: None,
+                laps: vec![],
+                start_time: None,
+            }),
+            token,
+        ))
+        .await
+        .expect("Failed to save workout")
+        .into_inner()
+        .id
+        .unwrap()
+}
+
+async fn leaderboard(test_env: &mut TestEnvironment, id: i32) -> SegmentLeaderboard {
+    test_env
+        .segment_service
+        .get_segment_leaderboard(with_metadata(Request::new(SegmentRequest { id })))
+        .await
+        .expect("Failed to get leaderboard")
+        .into_inner()
+}
+
+#[sqlx::test]
+async fn test_segment_leaderboard(db: SqlitePool) {
+    let mut test_env = run_test_env(db).await;
+
+    // About 11 meters per second
+    let workout_id =
+        save_workout(&mut test_env, "session-token", ride(0.0001, 30, 200)).await;
+
+    let status = test_env
+        .segment_service
+        .create_segment(with_metadata(Request::new(CreateSegmentRequest {
+            workout_id,
+            name: "Sprint".to_string(),
+            start_index: 20,
+            end_index: 30,
+        })))
+        .await
+        .expect_err("Created a segment past the end of the workout");
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let segment = test_env
+        .segment_service
+        .create_segment(with_metadata(Request::new(CreateSegmentRequest {
+            workout_id,
+            name: "Sprint".to_string(),
+            start_index: 5,
+            end_index: 20,
+        })))
+        .await
+        .expect("Failed to create segment")
+        .into_inner();
+    assert!((segment.distance - 166.8).abs() < 0.1);
+
+    // Turns east halfway through the segment, so it doesn't count
+    let tourist = Credentials {
+        username: "Tourist".to_string(),
+        password: "TouristPassword".to_string(),
+    };
+    let tourist_token = sign_up_and_login(&mut test_env, &tourist).await;
+    let mut detour = ride(0.0001, 30, 400);
+    for (i, measurement) in detour.iter_mut().enumerate().skip(12) {
+        measurement.latitude = Some(0.0012);
+        measurement.longitude = Some((i - 12) as f64 * 0.0001);
+    }
+    save_workout(&mut test_env, &tourist_token, detour).await;
+
+    // Half as fast, so twice the time
+    let rider = Credentials {
+        username: "Rider".to_string(),
+        password: "RiderPassword".to_string(),
+    };
+    let rider_token = sign_up_and_login(&mut test_env, &rider).await;
+    let rider_workout_id =
+        save_workout(&mut test_env, &rider_token, ride(0.00005, 60, 300)).await;
+
+    // Matching runs in the background
+    let mut leaderboard = leaderboard(&mut test_env, segment.id).await;
+    for _ in 0..50 {
+        if leaderboard.efforts.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        leaderboard = self::leaderboard(&mut test_env, segment.id).await;
+    }
+
+    assert_eq!(leaderboard.segment, Some(segment));
+    assert_eq!(
+        leaderboard.efforts,
+        vec![
+            SegmentEffort {
+                username: "user1".to_string(),
+                workout_id,
+                start_index: 5,
+                elapsed_time: 15,
+                avg_watts: 200,
+            },
+            SegmentEffort {
+                username: rider.username,
+                workout_id: rider_workout_id,
+                start_index: 10,
+                elapsed_time: 30,
+                avg_watts: 300,
+            },
+        ]
+    );
+
+    let status = test_env
+        .segment_service
+        .get_segment_leaderboard(with_metadata(Request::new(SegmentRequest { id: 0 })))
+        .await
+        .expect_err("Got a leaderboard of a missing segment");
+    assert_eq!(status.code(), Code::NotFound);
+}
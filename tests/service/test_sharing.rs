@@ -0,0 +1,570 @@
+use lazy_static::lazy_static;
+use pretty_assertions::assert_eq;
+use sqlx::SqlitePool;
+use tokio_stream::StreamExt;
+use tonic::{Code, Request};
+
+use crate::common::{
+    run_test_env, sign_up_and_login, stream_to_vec, with_token, TestEnvironment,
+};
+use cycling_tracker::cycling_tracker::{
+    AcceptInvitationRequest, Credentials, ListInvitationsRequest, ListSharesRequest,
+    LiveWorkoutUpdate, Measurement, MeasurementsRequest, PrivacyZone,
+    RevokeShareRequest, Share, ShareInvitation, SharePermission, UserProfile,
+    WatchLiveWorkoutRequest, Workout, WorkoutRequest,
+};
+
+lazy_static! {
+    static ref ATHLETE: Credentials = Credentials {
+        username: "Athlete".to_string(),
+        password: "AthletePassword".to_string(),
+    };
+    static ref COACH: Credentials = Credentials {
+        username: "Coach".to_string(),
+        password: "CoachPassword".to_string(),
+    };
+    static ref MEASUREMENTS: Vec<Measurement> = vec![Measurement {
+        speed: 29.0,
+        watts: 290,
+        rpm: 90,
+        heartrate: 130,
+        ..Default::default()
+    }];
+}
+
+/// Sign up an athlete and a coach, returning their session tokens
+async fn setup_users(
+    db: &SqlitePool,
+    test_env: &mut TestEnvironment,
+) -> (String, String) {
+    let athlete_token = sign_up_and_login(test_env, &ATHLETE).await;
+    let coach_token = sign_up_and_login(test_env, &COACH).await;
+
+    sqlx::query("UPDATE USER SET role = 'coach' WHERE username = $1")
+        .bind(&COACH.username)
+        .execute(db)
+        .await
+        .unwrap();
+
+    (athlete_token, coach_token)
+}
+
+async fn save_workout(
+    test_env: &mut TestEnvironment,
+    token: &str,
+    athlete: Option<String>,
+) -> Result<i32, Code> {
+    test_env
+        .ct_service
+        .save_workout(with_token(
+            Request::new(Workout {
+                km_ridden: 29.0,
+                measurements: (*MEASUREMENTS).clone(),
+                athlete,
+                laps: vec![],
+                start_time: None,
+            }),
+            token,
+        ))
+        .await
+        .map(|response| response.into_inner().id.unwrap())
+        .map_err(|status| status.code())
+}
+
+async fn get_measurements(
+    test_env: &mut TestEnvironment,
+    token: &str,
+    id: i32,
+    athlete: Option<String>,
+) -> Result<Vec<Measurement>, Code> {
+    match test_env
+        .ct_service
+        .get_measurements(with_token(
+            Request::new(MeasurementsRequest {
+                id,
+                athlete,
+                ..Default::default()
+            }),
+            token,
+        ))
+        .await
+    {
+        Ok(response) => Ok(stream_to_vec(response.into_inner()).await),
+        Err(status) => Err(status.code()),
+    }
+}
+
+async fn invite_coach(
+    test_env: &mut TestEnvironment,
+    athlete_token: &str,
+    coach_token: &str,
+    permission: SharePermission,
+) {
+    test_env
+        .sharing_service
+        .invite_coach(with_token(
+            Request::new(ShareInvitation {
+                coach: COACH.username.clone(),
+                permission: permission.into(),
+            }),
+            athlete_token,
+        ))
+        .await
+        .expect("Failed to invite coach");
+
+    test_env
+        .sharing_service
+        .accept_invitation(with_token(
+            Request::new(AcceptInvitationRequest {
+                athlete: ATHLETE.username.clone(),
+            }),
+            coach_token,
+        ))
+        .await
+        .expect("Failed to accept invitation");
+}
+
+#[sqlx::test]
+async fn test_read_access(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (athlete_token, coach_token) = setup_users(&db, &mut test_env).await;
+    let athlete = Some(ATHLETE.username.clone());
+
+    let id = save_workout(&mut test_env, &athlete_token, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        get_measurements(&mut test_env, &coach_token, id, athlete.clone()).await,
+        Err(Code::PermissionDenied)
+    );
+
+    test_env
+        .sharing_service
+        .invite_coach(with_token(
+            Request::new(ShareInvitation {
+                coach: COACH.username.clone(),
+                permission: SharePermission::Read.into(),
+            }),
+            &athlete_token,
+        ))
+        .await
+        .expect("Failed to invite coach");
+
+    let share = Share {
+        athlete: ATHLETE.username.clone(),
+        coach: COACH.username.clone(),
+        permission: SharePermission::Read.into(),
+        accepted: false,
+    };
+
+    let invitations = test_env
+        .sharing_service
+        .list_invitations(with_token(
+            Request::new(ListInvitationsRequest {}),
+            &coach_token,
+        ))
+        .await
+        .expect("Failed to list invitations")
+        .into_inner();
+
+    assert_eq!(invitations.shares, vec![share.clone()]);
+
+    // Invitations don't grant access until accepted
+    assert_eq!(
+        get_measurements(&mut test_env, &coach_token, id, athlete.clone()).await,
+        Err(Code::PermissionDenied)
+    );
+
+    test_env
+        .sharing_service
+        .accept_invitation(with_token(
+            Request::new(AcceptInvitationRequest {
+                athlete: ATHLETE.username.clone(),
+            }),
+            &coach_token,
+        ))
+        .await
+        .expect("Failed to accept invitation");
+
+    let shares = test_env
+        .sharing_service
+        .list_shares(with_token(
+            Request::new(ListSharesRequest {}),
+            &athlete_token,
+        ))
+        .await
+        .expect("Failed to list shares")
+        .into_inner();
+
+    assert_eq!(
+        shares.shares,
+        vec![Share {
+            accepted: true,
+            ..share
+        }]
+    );
+
+    assert_eq!(
+        get_measurements(&mut test_env, &coach_token, id, athlete.clone()).await,
+        Ok((*MEASUREMENTS).clone())
+    );
+
+    // Read access doesn't allow saving workouts
+    assert_eq!(
+        save_workout(&mut test_env, &coach_token, athlete.clone()).await,
+        Err(Code::PermissionDenied)
+    );
+
+    test_env
+        .sharing_service
+        .revoke_share(with_token(
+            Request::new(RevokeShareRequest {
+                username: COACH.username.clone(),
+            }),
+            &athlete_token,
+        ))
+        .await
+        .expect("Failed to revoke share");
+
+    assert_eq!(
+        get_measurements(&mut test_env, &coach_token, id, athlete).await,
+        Err(Code::PermissionDenied)
+    );
+}
+
+#[sqlx::test]
+async fn test_write_access(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (athlete_token, coach_token) = setup_users(&db, &mut test_env).await;
+
+    invite_coach(
+        &mut test_env,
+        &athlete_token,
+        &coach_token,
+        SharePermission::Write,
+    )
+    .await;
+
+    let id = save_workout(&mut test_env, &coach_token, Some(ATHLETE.username.clone()))
+        .await
+        .unwrap();
+
+    // The workout belongs to the athlete
+    assert_eq!(
+        get_measurements(&mut test_env, &athlete_token, id, None).await,
+        Ok((*MEASUREMENTS).clone())
+    );
+    assert_eq!(
+        get_measurements(&mut test_env, &coach_token, id, None).await,
+        Err(Code::NotFound)
+    );
+}
+
+#[sqlx::test]
+async fn test_own_workouts_only(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (athlete_token, coach_token) = setup_users(&db, &mut test_env).await;
+
+    let id = save_workout(&mut test_env, &athlete_token, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        get_measurements(&mut test_env, &coach_token, id, None).await,
+        Err(Code::NotFound)
+    );
+}
+
+#[sqlx::test]
+async fn test_invite_requires_coach_role(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (_, coach_token) = setup_users(&db, &mut test_env).await;
+
+    // The athlete doesn't have the coach role
+    let response = test_env
+        .sharing_service
+        .invite_coach(with_token(
+            Request::new(ShareInvitation {
+                coach: ATHLETE.username.clone(),
+                permission: SharePermission::Read.into(),
+            }),
+            &coach_token,
+        ))
+        .await
+        .expect_err("Invited user without coach role");
+
+    assert_eq!(response.code(), Code::NotFound);
+}
+
+#[sqlx::test]
+async fn test_watch_live_workout(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (athlete_token, coach_token) = setup_users(&db, &mut test_env).await;
+    let athlete = Some(ATHLETE.username.clone());
+
+    let response = test_env
+        .ct_service
+        .watch_live_workout(with_token(
+            Request::new(WatchLiveWorkoutRequest {
+                athlete: athlete.clone(),
+            }),
+            &coach_token,
+        ))
+        .await
+        .expect_err("Watched without access");
+    assert_eq!(response.code(), Code::PermissionDenied);
+
+    invite_coach(
+        &mut test_env,
+        &athlete_token,
+        &coach_token,
+        SharePermission::Read,
+    )
+    .await;
+
+    let mut updates = test_env
+        .ct_service
+        .watch_live_workout(with_token(
+            Request::new(WatchLiveWorkoutRequest { athlete }),
+            &coach_token,
+        ))
+        .await
+        .expect("Failed to watch live workout")
+        .into_inner();
+
+    test_env
+        .ct_service
+        .record_workout(with_token(
+            Request::new(tokio_stream::iter((*MEASUREMENTS).clone())),
+            &athlete_token,
+        ))
+        .await
+        .expect("Failed to record workout");
+
+    let update = updates.next().await.unwrap().unwrap();
+    assert_eq!(update.measurement, Some(MEASUREMENTS[0].clone()));
+    assert_eq!(update.summary.unwrap().avg_watts, 290);
+
+    let update = updates.next().await.unwrap().unwrap();
+    assert_eq!(
+        update,
+        LiveWorkoutUpdate {
+            finished: true,
+            ..Default::default()
+        }
+    );
+}
+
+#[sqlx::test]
+async fn test_privacy_zones(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (athlete_token, coach_token) = setup_users(&db, &mut test_env).await;
+    let athlete = Some(ATHLETE.username.clone());
+    invite_coach(
+        &mut test_env,
+        &athlete_token,
+        &coach_token,
+        SharePermission::Read,
+    )
+    .await;
+
+    let home = PrivacyZone {
+        latitude: 0.005,
+        longitude: 0.0,
+        radius: 150.0,
+    };
+
+    let status = test_env
+        .profile_service
+        .update_profile(with_token(
+            Request::new(UserProfile {
+                privacy_zones: vec![PrivacyZone {
+                    radius: -1.0,
+                    ..home.clone()
+                }],
+                ..Default::default()
+            }),
+            &athlete_token,
+        ))
+        .await
+        .expect_err("Saved a zone with a negative radius");
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let profile = UserProfile {
+        privacy_zones: vec![home],
+        ..Default::default()
+    };
+    test_env
+        .profile_service
+        .update_profile(with_token(Request::new(profile.clone()), &athlete_token))
+        .await
+        .expect("Failed to update profile");
+
+    // About 111 meters apart, heading north through the zone
+    let measurements: Vec<Measurement> = (0..12)
+        .map(|i| Measurement {
+            latitude: Some(i as f64 * 0.001),
+            longitude: Some(0.0),
+            ..MEASUREMENTS[0].clone()
+        })
+        .collect();
+
+    let id = test_env
+        .ct_service
+        .save_workout(with_token(
+            Request::new(Workout {
+                km_ridden: 1.2,
+                measurements: measurements.clone(),
+                athlete: None,
+                laps: vec![],
+                start_time: None,
+            }),
+            &athlete_token,
+        ))
+        .await
+        .expect("Failed to save workout")
+        .into_inner()
+        .id
+        .unwrap();
+
+    // The owner sees everything
+    assert_eq!(
+        get_measurements(&mut test_env, &athlete_token, id, None).await,
+        Ok(measurements.clone())
+    );
+
+    // Others don't see the first and last 200 meters, nor the zone
+    let visible: Vec<Option<f64>> =
+        get_measurements(&mut test_env, &coach_token, id, athlete.clone())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.latitude)
+            .collect();
+    let expected = (0..12)
+        .map(|i| [2, 3, 7, 8, 9].contains(&i).then_some(i as f64 * 0.001))
+        .collect::<Vec<_>>();
+    assert_eq!(visible, expected);
+
+    let route = test_env
+        .ct_service
+        .get_route(with_token(
+            Request::new(WorkoutRequest {
+                id,
+                athlete: athlete.clone(),
+            }),
+            &coach_token,
+        ))
+        .await
+        .expect("Failed to get route")
+        .into_inner();
+    assert_eq!(route.point_count, 5);
+
+    let mut updates = test_env
+        .ct_service
+        .watch_live_workout(with_token(
+            Request::new(WatchLiveWorkoutRequest { athlete }),
+            &coach_token,
+        ))
+        .await
+        .expect("Failed to watch live workout")
+        .into_inner();
+
+    test_env
+        .ct_service
+        .record_workout(with_token(
+            Request::new(tokio_stream::iter(measurements[..1].to_vec())),
+            &athlete_token,
+        ))
+        .await
+        .expect("Failed to record workout");
+
+    let update = updates.next().await.unwrap().unwrap();
+    assert_eq!(update.measurement, Some(MEASUREMENTS[0].clone()));
+    assert_eq!(update.summary.unwrap().bounding_box, None);
+
+    let stored = test_env
+        .profile_service
+        .get_profile(with_token(Request::new(Default::default()), &athlete_token))
+        .await
+        .expect("Failed to get profile")
+        .into_inner();
+    assert_eq!(stored, profile);
+}
+
+#[sqlx::test]
+async fn test_watch_live_workout_privacy(db: SqlitePool) {
+    let mut test_env = run_test_env(db.clone()).await;
+    let (athlete_token, coach_token) = setup_users(&db, &mut test_env).await;
+    invite_coach(
+        &mut test_env,
+        &athlete_token,
+        &coach_token,
+        SharePermission::Read,
+    )
+    .await;
+
+    // Far from the ride, so only the start and end are hidden
+    test_env
+        .profile_service
+        .update_profile(with_token(
+            Request::new(UserProfile {
+                privacy_zones: vec![PrivacyZone {
+                    latitude: 1.0,
+                    longitude: 1.0,
+                    radius: 100.0,
+                }],
+                ..Default::default()
+            }),
+            &athlete_token,
+        ))
+        .await
+        .expect("Failed to update profile");
+
+    let mut updates = test_env
+        .ct_service
+        .watch_live_workout(with_token(
+            Request::new(WatchLiveWorkoutRequest {
+                athlete: Some(ATHLETE.username.clone()),
+            }),
+            &coach_token,
+        ))
+        .await
+        .expect("Failed to watch live workout")
+        .into_inner();
+
+    // About 111 meters apart
+    let measurements: Vec<Measurement> = (0..12)
+        .map(|i| Measurement {
+            latitude: Some(i as f64 * 0.001),
+            longitude: Some(0.0),
+            ..MEASUREMENTS[0].clone()
+        })
+        .collect();
+    let expected = (0..12)
+        .map(|i| (2..10).contains(&i).then_some(i as f64 * 0.001))
+        .collect::<Vec<_>>();
+
+    // Every ride on the same stream hides its own start and end
+    for _ in 0..2 {
+        test_env
+            .ct_service
+            .record_workout(with_token(
+                Request::new(tokio_stream::iter(measurements.clone())),
+                &athlete_token,
+            ))
+            .await
+            .expect("Failed to record workout");
+
+        let mut visible = Vec::new();
+        loop {
+            let update = updates.next().await.unwrap().unwrap();
+            if update.finished {
+                break;
+            }
+            visible.push(update.measurement.unwrap().latitude);
+        }
+        assert_eq!(visible, expected);
+    }
+}